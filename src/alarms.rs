@@ -0,0 +1,515 @@
+use chrono::{DateTime, Local};
+use egui::{Color32, Grid, RichText, ScrollArea};
+use rodio::{source::Source, Decoder, OutputStream, Sink};
+use std::fmt::Display;
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::journal::{Event, EventKind};
+use crate::signals::Severity;
+
+//#################################################### Alarm manager.
+
+/// The annunciation state of an alarm, after ISA-18.2.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AlarmState {
+    Normal,
+    ActiveUnacked,
+    ActiveAcked,
+    ClearedUnacked,
+}
+
+impl Display for AlarmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlarmState::Normal => write!(f, "Normal"),
+            AlarmState::ActiveUnacked => write!(f, "Active"),
+            AlarmState::ActiveAcked => write!(f, "Active, acked"),
+            AlarmState::ClearedUnacked => write!(f, "Cleared, unacked"),
+        }
+    }
+}
+
+pub struct Alarm {
+    pub name: String,
+    pub severity: Severity,
+    pub state: AlarmState,
+    /// The raw condition, tracked even while the alarm is shelved.
+    pub condition: bool,
+    pub raised_at: DateTime<Local>,
+    pub shelved_until: Option<DateTime<Local>>,
+}
+
+impl Alarm {
+    pub fn is_shelved(&self) -> bool {
+        self.shelved_until.is_some()
+    }
+
+    pub fn is_unacked(&self) -> bool {
+        matches!(
+            self.state,
+            AlarmState::ActiveUnacked | AlarmState::ClearedUnacked
+        )
+    }
+}
+
+/// Keeps the state of every alarm source and drives the horn.
+///
+/// Sources report their raw condition every frame through [`AlarmManager::update`],
+/// the operator actions are applied directly on the manager.
+#[derive(Default)]
+pub struct AlarmManager {
+    alarms: Vec<Alarm>,
+    /// The first alarm raised since the panel was last healthy or reset.
+    first_out: Option<String>,
+    /// Opened the first time an alarm has to sound.
+    horn: Option<Horn>,
+    /// Set by the operator to silence the horn until the next new alarm.
+    silenced: bool,
+    /// The transitions and operator actions not yet written to the journal.
+    events: Vec<Event>,
+}
+
+impl AlarmManager {
+    /// Feeds the current condition of an alarm source.
+    pub fn update(&mut self, name: &str, condition: bool, severity: Severity) {
+        let index = match self.alarms.iter().position(|alarm| alarm.name == name) {
+            Some(index) => index,
+            None if !condition => return,
+            None => {
+                self.alarms.push(Alarm {
+                    name: name.to_string(),
+                    severity,
+                    state: AlarmState::Normal,
+                    condition: false,
+                    raised_at: Local::now(),
+                    shelved_until: None,
+                });
+                self.alarms.len() - 1
+            }
+        };
+
+        let alarm = &mut self.alarms[index];
+        alarm.severity = severity;
+        let raised = condition && !alarm.condition;
+        alarm.condition = condition;
+        if alarm.is_shelved() {
+            return;
+        }
+
+        match (alarm.state, condition) {
+            (AlarmState::Normal | AlarmState::ClearedUnacked, true) if raised => {
+                alarm.state = AlarmState::ActiveUnacked;
+                alarm.raised_at = Local::now();
+                self.silenced = false;
+                self.events
+                    .push(alarm_event(alarm, EventKind::Alarm, "Alarm raised"));
+                if self.first_out.is_none() {
+                    self.first_out = Some(alarm.name.clone());
+                }
+            }
+            (AlarmState::ActiveUnacked, false) => {
+                alarm.state = AlarmState::ClearedUnacked;
+                self.events
+                    .push(alarm_event(alarm, EventKind::Alarm, "Alarm cleared"));
+            }
+            (AlarmState::ActiveAcked, false) => {
+                alarm.state = AlarmState::Normal;
+                self.events
+                    .push(alarm_event(alarm, EventKind::Alarm, "Alarm cleared"));
+            }
+            _ => {}
+        }
+    }
+
+    /// Expires the shelving timeouts, forgets the alarms back to normal and
+    /// sounds the horn while an alarm is unacknowledged. Called once per frame.
+    pub fn tick(&mut self) {
+        let now = Local::now();
+        for alarm in self.alarms.iter_mut() {
+            if alarm.shelved_until.is_some_and(|until| until <= now) {
+                self.events
+                    .push(alarm_event(alarm, EventKind::Shelve, "Shelf expired"));
+                if release(alarm, &mut self.events) {
+                    self.silenced = false;
+                }
+            }
+        }
+        self.alarms.retain(|alarm| {
+            alarm.state != AlarmState::Normal || alarm.is_shelved() || alarm.condition
+        });
+        if self.alarms.iter().all(|alarm| !alarm.condition) && !self.has_unacked() {
+            self.first_out = None;
+        }
+
+        let sounding = self.has_unacked() && !self.silenced;
+        if sounding || self.horn.is_some() {
+            self.horn
+                .get_or_insert_with(Horn::new)
+                .set_sounding(sounding);
+        }
+    }
+
+    pub fn acknowledge(&mut self, name: &str) {
+        if let Some(alarm) = self.alarms.iter_mut().find(|alarm| alarm.name == name) {
+            acknowledge(alarm, &mut self.events);
+        }
+    }
+
+    pub fn acknowledge_all(&mut self) {
+        for alarm in self.alarms.iter_mut() {
+            acknowledge(alarm, &mut self.events);
+        }
+    }
+
+    /// Suppresses an alarm for `duration`. It comes back unacknowledged if the
+    /// condition is still present when the shelf expires.
+    pub fn shelve(&mut self, name: &str, duration: Duration) {
+        if let Some(alarm) = self.alarms.iter_mut().find(|alarm| alarm.name == name) {
+            alarm.shelved_until = chrono::Duration::from_std(duration)
+                .ok()
+                .map(|duration| Local::now() + duration);
+            alarm.state = AlarmState::Normal;
+            let message = format!("Shelved for {} min", duration.as_secs() / 60);
+            self.events
+                .push(alarm_event(alarm, EventKind::Shelve, &message).by_operator());
+        }
+    }
+
+    pub fn unshelve(&mut self, name: &str) {
+        if let Some(alarm) = self.alarms.iter_mut().find(|alarm| alarm.name == name) {
+            if !alarm.is_shelved() {
+                return;
+            }
+            self.events
+                .push(alarm_event(alarm, EventKind::Shelve, "Unshelved").by_operator());
+            if release(alarm, &mut self.events) {
+                self.silenced = false;
+            }
+        }
+    }
+
+    pub fn reset_first_out(&mut self) {
+        self.first_out = None;
+    }
+
+    pub fn silence(&mut self) {
+        self.silenced = true;
+        self.events
+            .push(Event::new(EventKind::Acknowledge, "Horn", None, "Horn silenced").by_operator());
+    }
+
+    /// Hands over the events raised since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn first_out(&self) -> Option<&str> {
+        self.first_out.as_deref()
+    }
+
+    pub fn state(&self, name: &str) -> AlarmState {
+        self.alarms
+            .iter()
+            .find(|alarm| alarm.name == name)
+            .map_or(AlarmState::Normal, |alarm| alarm.state)
+    }
+
+    pub fn alarms(&self) -> &[Alarm] {
+        &self.alarms
+    }
+
+    pub fn has_unacked(&self) -> bool {
+        self.alarms.iter().any(|alarm| alarm.is_unacked())
+    }
+
+    pub fn unacked_count(&self) -> usize {
+        self.alarms
+            .iter()
+            .filter(|alarm| alarm.is_unacked())
+            .count()
+    }
+}
+
+fn acknowledge(alarm: &mut Alarm, events: &mut Vec<Event>) {
+    match alarm.state {
+        AlarmState::ActiveUnacked => alarm.state = AlarmState::ActiveAcked,
+        AlarmState::ClearedUnacked => alarm.state = AlarmState::Normal,
+        _ => return,
+    }
+    events.push(alarm_event(alarm, EventKind::Acknowledge, "Acknowledged").by_operator());
+}
+
+/// Takes an alarm off the shelf, raising it again if its condition is still present.
+/// Returns true if it was raised.
+fn release(alarm: &mut Alarm, events: &mut Vec<Event>) -> bool {
+    alarm.shelved_until = None;
+    if alarm.condition {
+        alarm.state = AlarmState::ActiveUnacked;
+        alarm.raised_at = Local::now();
+        events.push(alarm_event(alarm, EventKind::Alarm, "Alarm raised"));
+    }
+    alarm.condition
+}
+
+fn alarm_event(alarm: &Alarm, kind: EventKind, message: &str) -> Event {
+    Event::new(kind, &alarm.name, Some(alarm.severity), message)
+}
+//####################################################
+
+//#################################################### The horn.
+
+/// Loops `alarm.wav` on the default audio output. Without an audio device the
+/// horn stays silent.
+struct Horn {
+    // The stream has to outlive the sink for the sound to play.
+    _stream: Option<OutputStream>,
+    sink: Option<Sink>,
+}
+
+impl Horn {
+    fn new() -> Self {
+        let Ok((stream, handle)) = OutputStream::try_default() else {
+            log::warn!("No audio output found, the alarm horn is disabled.");
+            return Self {
+                _stream: None,
+                sink: None,
+            };
+        };
+
+        let sink = Sink::try_new(&handle).ok().and_then(|sink| {
+            let sound =
+                Decoder::new(Cursor::new(include_bytes!("../alarm.wav").as_slice())).ok()?;
+            sink.pause();
+            sink.append(sound.repeat_infinite());
+            Some(sink)
+        });
+        Self {
+            _stream: Some(stream),
+            sink,
+        }
+    }
+
+    fn set_sounding(&mut self, sounding: bool) {
+        if let Some(sink) = &self.sink {
+            if sounding && sink.is_paused() {
+                sink.play();
+            } else if !sounding && !sink.is_paused() {
+                sink.pause();
+            }
+        }
+    }
+}
+//####################################################
+
+//#################################################### Alarm summary window.
+
+const SHELVE_DURATIONS: [(&str, u64); 3] = [("15 min", 15), ("1 h", 60), ("8 h", 480)];
+
+pub fn alarm_summary_ui(ui: &mut egui::Ui, alarms: &mut AlarmManager) {
+    ui.horizontal(|ui| {
+        if ui
+            .button(format!(
+                "{} Acknowledge all",
+                egui_phosphor::regular::CHECKS
+            ))
+            .clicked()
+        {
+            alarms.acknowledge_all();
+        }
+        if ui
+            .add_enabled(
+                alarms.has_unacked() && !alarms.silenced,
+                egui::Button::new(format!(
+                    "{} Silence horn",
+                    egui_phosphor::regular::BELL_SLASH
+                )),
+            )
+            .clicked()
+        {
+            alarms.silence();
+        }
+        if ui.button("Reset first-out").clicked() {
+            alarms.reset_first_out();
+        }
+    });
+    ui.separator();
+
+    let first_out = alarms.first_out().map(|name| name.to_string());
+    let mut acknowledge = None;
+    let mut shelve = None;
+    let mut unshelve = None;
+
+    ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+        Grid::new("alarm_summary")
+            .num_columns(6)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Time");
+                ui.strong("Alarm");
+                ui.strong("Severity");
+                ui.strong("State");
+                ui.strong("");
+                ui.strong("");
+                ui.end_row();
+
+                for alarm in alarms.alarms() {
+                    ui.label(alarm.raised_at.format("%d/%m/%Y %H:%M:%S").to_string());
+                    let mut name = RichText::new(&alarm.name).strong();
+                    if first_out.as_ref() == Some(&alarm.name) {
+                        name = name.color(Color32::WHITE).background_color(Color32::BLACK);
+                    }
+                    ui.label(name)
+                        .on_hover_text(if first_out.as_ref() == Some(&alarm.name) {
+                            "First-out"
+                        } else {
+                            ""
+                        });
+                    ui.label(
+                        RichText::new(format!("{}", alarm.severity))
+                            .color(Color32::WHITE)
+                            .background_color(alarm.severity.color()),
+                    );
+                    match alarm.shelved_until {
+                        Some(until) => {
+                            ui.label(format!("Shelved until {}", until.format("%H:%M")));
+                        }
+                        None => {
+                            ui.label(format!("{}", alarm.state));
+                        }
+                    }
+
+                    if ui
+                        .add_enabled(
+                            alarm.is_unacked(),
+                            egui::Button::new(egui_phosphor::regular::CHECK),
+                        )
+                        .on_hover_text("Acknowledge")
+                        .clicked()
+                    {
+                        acknowledge = Some(alarm.name.clone());
+                    }
+                    if alarm.is_shelved() {
+                        if ui.button("Unshelve").clicked() {
+                            unshelve = Some(alarm.name.clone());
+                        }
+                    } else {
+                        ui.menu_button("Shelve", |ui| {
+                            for (label, minutes) in SHELVE_DURATIONS {
+                                if ui.button(label).clicked() {
+                                    shelve = Some((alarm.name.clone(), minutes));
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+
+    if let Some(name) = acknowledge {
+        alarms.acknowledge(&name);
+    }
+    if let Some((name, minutes)) = shelve {
+        alarms.shelve(&name, Duration::from_secs(minutes * 60));
+    }
+    if let Some(name) = unshelve {
+        alarms.unshelve(&name);
+    }
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_acknowledged_alarm_returns_to_normal_when_cleared() {
+        let mut alarms = AlarmManager::default();
+        alarms.update("TT-101 High", false, Severity::High);
+        assert!(alarms.alarms().is_empty());
+
+        alarms.update("TT-101 High", true, Severity::High);
+        assert_eq!(alarms.state("TT-101 High"), AlarmState::ActiveUnacked);
+        assert!(alarms.has_unacked());
+
+        alarms.acknowledge("TT-101 High");
+        assert_eq!(alarms.state("TT-101 High"), AlarmState::ActiveAcked);
+        assert!(!alarms.has_unacked());
+
+        alarms.update("TT-101 High", false, Severity::High);
+        assert_eq!(alarms.state("TT-101 High"), AlarmState::Normal);
+        alarms.tick();
+        assert!(alarms.alarms().is_empty());
+
+        let messages: Vec<String> = alarms
+            .take_events()
+            .iter()
+            .map(|event| event.message.clone())
+            .collect();
+        assert_eq!(messages, ["Alarm raised", "Acknowledged", "Alarm cleared"]);
+    }
+
+    #[test]
+    fn a_cleared_alarm_stays_until_acknowledged() {
+        let mut alarms = AlarmManager::default();
+        alarms.update("PT-201 Low", true, Severity::Medium);
+        alarms.update("PT-201 Low", false, Severity::Medium);
+        assert_eq!(alarms.state("PT-201 Low"), AlarmState::ClearedUnacked);
+        assert_eq!(alarms.unacked_count(), 1);
+
+        // Coming back before the acknowledgement raises it again.
+        alarms.update("PT-201 Low", true, Severity::Medium);
+        assert_eq!(alarms.state("PT-201 Low"), AlarmState::ActiveUnacked);
+        alarms.update("PT-201 Low", false, Severity::Medium);
+
+        alarms.acknowledge_all();
+        assert_eq!(alarms.state("PT-201 Low"), AlarmState::Normal);
+        assert_eq!(alarms.unacked_count(), 0);
+    }
+
+    #[test]
+    fn a_shelved_alarm_comes_back_only_if_still_present() {
+        let mut alarms = AlarmManager::default();
+        alarms.update("LT-301 High", true, Severity::Low);
+        alarms.shelve("LT-301 High", Duration::from_secs(900));
+        assert!(alarms.alarms()[0].is_shelved());
+        assert_eq!(alarms.state("LT-301 High"), AlarmState::Normal);
+
+        // Transitions are ignored while shelved, but the condition is tracked.
+        alarms.update("LT-301 High", false, Severity::Low);
+        alarms.update("LT-301 High", true, Severity::Low);
+        assert_eq!(alarms.state("LT-301 High"), AlarmState::Normal);
+
+        alarms.unshelve("LT-301 High");
+        assert!(!alarms.alarms()[0].is_shelved());
+        assert_eq!(alarms.state("LT-301 High"), AlarmState::ActiveUnacked);
+
+        alarms.acknowledge("LT-301 High");
+        alarms.shelve("LT-301 High", Duration::from_secs(900));
+        alarms.update("LT-301 High", false, Severity::Low);
+        alarms.unshelve("LT-301 High");
+        assert_eq!(alarms.state("LT-301 High"), AlarmState::Normal);
+    }
+
+    #[test]
+    fn the_first_out_is_the_first_alarm_raised() {
+        let mut alarms = AlarmManager::default();
+        alarms.update("A", true, Severity::High);
+        alarms.update("B", true, Severity::Critical);
+        assert_eq!(alarms.first_out(), Some("A"));
+
+        alarms.reset_first_out();
+        assert_eq!(alarms.first_out(), None);
+        alarms.update("C", true, Severity::Low);
+        assert_eq!(alarms.first_out(), Some("C"));
+    }
+
+    #[test]
+    fn the_horn_is_not_opened_while_nothing_sounds() {
+        let mut alarms = AlarmManager::default();
+        alarms.tick();
+        assert!(alarms.horn.is_none());
+    }
+}
@@ -1,101 +1,536 @@
 use egui::{
-    style::Selection, Button, Color32, ComboBox, Label, PointerButton, RichText, Rounding, Slider,
-    Stroke, Vec2, Visuals,
+    style::Selection, Button, Color32, ComboBox, Label, RichText, Rounding, Slider, Stroke, Vec2,
+    Visuals,
 };
 
-use egui_phosphor;
 use epaint::Pos2;
-use parking_lot::Mutex;
-use rfd;
-use rmodbus::{client::ModbusRequest, ModbusProto};
 use serialport::available_ports;
-use std::path::PathBuf;
+use std::collections::HashMap;
 use std::{
     fmt::Display,
-    fs::File,
+    future::Future,
     net::SocketAddr,
-    sync::Arc,
+    path::Path,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
     thread,
     time::{Duration, Instant},
 };
-use sync::tcp::connect_with_timeout;
-use tokio_modbus::prelude::{sync::rtu::connect_slave, sync::tcp::connect, *};
+use tokio_modbus::prelude::*;
+use tokio_serial::SerialStream;
 
-use actix_web::{middleware, rt, web, App, HttpRequest, HttpServer};
+use tokio::runtime::{self, Runtime};
+use tokio::sync::mpsc::{
+    error::TryRecvError, unbounded_channel, UnboundedReceiver, UnboundedSender,
+};
+use tokio::sync::watch;
+use tokio::task::block_in_place;
+
+use actix_web::{dev::ServerHandle, middleware, rt, web, App, HttpRequest, HttpServer};
 
-use s7::{client::Client, field::Bool, field::Fields, field::Float, tcp, transport::Connection};
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use s7::{tcp, transport::Connection, transport::Transport};
 use std::net::{IpAddr, Ipv4Addr};
 
-//#################################################### Main App Struct
+use crate::alarms::*;
+use crate::blocks::*;
+use crate::bus::*;
+use crate::codec::*;
+use crate::datascan::*;
+use crate::health::*;
+use crate::historian::*;
+use crate::journal::*;
+use crate::limits::*;
+use crate::logger::*;
+use crate::logix::*;
+use crate::replay::*;
+use crate::scan::*;
+use crate::siemens::*;
+use crate::signals::*;
+use crate::tags::*;
+use crate::trend::*;
+use crate::writes::*;
 
-const DB: i32 = 1;
+//#################################################### Main App Struct
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct CarbonApp {
+    devices: Vec<Device>,
+    /// The protocol and configuration of the single device saved before
+    /// devices became a list, moved into the first device when loaded.
+    #[serde(
+        rename = "protocol",
+        skip_serializing,
+        deserialize_with = "deserialize_some"
+    )]
+    legacy_protocol: Option<Protocol>,
+    #[serde(
+        rename = "device_config_buffer",
+        skip_serializing,
+        deserialize_with = "deserialize_some"
+    )]
+    legacy_config: Option<DeviceConfigUiBuffer>,
+    /// The device shown in the options.
     #[serde(skip)]
-    app_run_state: AppRunState,
-    device_config_buffer: DeviceConfigUiBuffer,
+    selected_device: usize,
     #[serde(skip)]
-    mutex: Arc<Mutex<MutexData>>,
-    protocol: Protocol,
+    updates: UpdateChannel,
+    /// The last status reported by each connected device.
+    #[serde(skip)]
+    device_status: HashMap<String, DeviceStatus>,
     #[serde(skip)]
-    device_config: DeviceConfig,
+    services: Option<Services>,
+    /// Runs the polling tasks of all the devices, built with the first one.
     #[serde(skip)]
-    tag1: f32,
-    tag2: f32,
-    tag3: f32,
+    runtime: Option<Runtime>,
     #[serde(skip)]
     about: bool,
     #[serde(skip)]
     options: bool,
     #[serde(skip)]
     edit_pos: bool,
-    //#[serde(skip)]
+    #[serde(deserialize_with = "deserialize_tags")]
     tags: Vec<Tag>,
     #[serde(skip)]
-    digital_inputs: u16,
+    tag_database: bool,
+    #[serde(skip)]
+    selected_tag: Option<usize>,
+    signals: Vec<DigitalSignal>,
+    #[serde(skip)]
+    signal_editor: bool,
+    #[serde(skip)]
+    signal_states: HashMap<String, bool>,
+    #[serde(skip)]
+    alarms: AlarmManager,
+    #[serde(skip)]
+    alarm_summary: bool,
+    #[serde(skip)]
+    limit_monitor: LimitMonitor,
+    #[serde(skip)]
+    journal: Journal,
+    #[serde(skip)]
+    journal_window: bool,
+    #[serde(skip)]
+    journal_filter: JournalFilter,
+    /// The number of full journal files kept, 0 keeps them all.
+    journal_retained_files: usize,
+    trend: Trend,
+    #[serde(skip)]
+    trend_buffer: TrendBuffer,
     #[serde(skip)]
-    digital_inputs2: u16,
-    widgets_pos: WidgetsPos,
+    trend_window: bool,
     #[serde(skip)]
-    blink_time: usize,
-    blink_flag: bool,
-    logger_path: PathBuf,
+    replay: Option<Result<Replay, String>>,
+    #[serde(skip)]
+    replay_window: bool,
+    logger: LoggerConfig,
+    /// The logger settings being edited, until applied.
+    #[serde(skip)]
+    logger_draft: Option<LoggerConfig>,
+    #[serde(skip)]
+    logger_window: bool,
+    #[serde(skip)]
+    logger_status: LoggerStatus,
+    historian_config: HistorianConfig,
+    /// Opened once at start up, as the database file can't be opened twice.
+    #[serde(skip)]
+    historian: Option<Result<Arc<Historian>, String>>,
+    #[serde(skip)]
+    historian_window: bool,
+    #[serde(skip)]
+    historian_error: Option<String>,
+    #[serde(skip)]
+    server_error: Option<String>,
+    /// Joins the HTTP server last stopped, which holds its port until then.
+    #[serde(skip)]
+    server_stopping: Option<thread::JoinHandle<()>>,
+    #[serde(skip)]
+    setpoint: Option<SetpointDialog>,
 }
 //####################################################
 
-//#################################################### The Mutex used between
+//#################################################### The channels between
 //the main and background threads.
-struct MutexData {
-    data: Vec<u16>,
-    s7_read_data: S7Data,
-    s7_message: Option<S7MessageTag>,
-    achieved_scan_time: u128,
-    error_msg: String,
-    new_config: Option<DeviceConfigUiBuffer>,
-    log: bool,
-    kill_thread: bool,
+
+/// What the main thread asks of the polling task of a device.
+enum DeviceCommand {
+    Config(Box<DeviceConfigUiBuffer>),
+    Tags(Vec<Tag>),
+    Signals(Vec<DigitalSignal>),
+    Write(WriteRequest),
+    /// Reads the blocks of the on-demand scan class.
+    Scan,
+    /// Closes the link and ends the task.
+    Stop,
 }
-//####################################################
 
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
-struct Tag {
-    name: String,
-    #[serde(skip)]
-    value: f32,
-    pos: Pos2,
+/// The commands a polling task received since its last scan.
+#[derive(Default)]
+struct Pending {
+    config: Option<DeviceConfigUiBuffer>,
+    tags: Option<Vec<Tag>>,
+    signals: Option<Vec<DigitalSignal>>,
+    /// The confirmed writes, in the order the operator sent them.
+    writes: Vec<WriteRequest>,
+    /// The operator asked for the on-demand scan class.
+    scan: bool,
+    stop: bool,
 }
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
-struct WidgetsPos {
-    hello_button_pos: Pos2,
-    close_button_pos: Pos2,
-    tag1_pos: Pos2,
+
+/// The ends of the channels the polling task of a device works with.
+struct DriverLink {
+    device: String,
+    commands: UnboundedReceiver<DeviceCommand>,
+    updates: Sender<Update>,
+    services: ServiceFeed,
+    pending: Pending,
+    status: DeviceStatus,
+    /// The delay before the next connection attempt.
+    backoff: Backoff,
+    /// The values of the last good scan.
+    last: Option<Snapshot>,
+    /// Failed scans since the last good one.
+    missed_scans: u32,
+    /// The failed scans in a row after which the last values are stale.
+    stale_after_scans: u32,
+    /// The errors of the tags that couldn't be read in the last scan, by tag name.
+    tag_errors: HashMap<String, String>,
+}
+
+impl DriverLink {
+    fn new(
+        device: &str,
+        stale_after_scans: u32,
+        commands: UnboundedReceiver<DeviceCommand>,
+        updates: Sender<Update>,
+        services: ServiceFeed,
+    ) -> Self {
+        Self {
+            device: device.to_string(),
+            commands,
+            updates,
+            services,
+            pending: Pending::default(),
+            status: DeviceStatus::default(),
+            backoff: Backoff::default(),
+            last: None,
+            missed_scans: 0,
+            stale_after_scans,
+            tag_errors: HashMap::new(),
+        }
+    }
+
+    fn send(&self, update: Update) {
+        // Nobody is left to tell once the app is closed.
+        let _ = self.updates.send(update);
+    }
+
+    /// Hands a snapshot to the logger and historian, and to the UI for display.
+    fn send_snapshot(&self, snapshot: Snapshot) {
+        self.services.snapshot(&snapshot);
+        self.send(Update::Snapshot(snapshot));
+    }
+
+    fn journal(&self, kind: EventKind, message: &str) {
+        self.send(Update::Event(Event::new(kind, &self.device, None, message)));
+    }
+
+    fn report_status(&self) {
+        self.send(Update::Status {
+            device: self.device.clone(),
+            status: self.status.clone(),
+        });
+    }
+
+    /// Keeps a command for the next look at the pending ones. A closed
+    /// channel means the app is gone.
+    fn receive(&mut self, command: Option<DeviceCommand>) {
+        match command {
+            Some(DeviceCommand::Config(config)) => self.pending.config = Some(*config),
+            Some(DeviceCommand::Tags(tags)) => self.pending.tags = Some(tags),
+            Some(DeviceCommand::Signals(signals)) => self.pending.signals = Some(signals),
+            Some(DeviceCommand::Write(request)) => self.pending.writes.push(request),
+            Some(DeviceCommand::Scan) => self.pending.scan = true,
+            Some(DeviceCommand::Stop) | None => self.pending.stop = true,
+        }
+    }
+
+    /// Waits for the next command. Cancel safe, so it can race the scan ticks.
+    async fn receive_command(&mut self) {
+        let command = self.commands.recv().await;
+        self.receive(command);
+    }
+
+    /// Takes the commands received so far. The task stops when asked to or
+    /// once the app drops its end of the channel.
+    fn take_pending(&mut self) -> Pending {
+        loop {
+            match self.commands.try_recv() {
+                Ok(command) => self.receive(Some(command)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receive(None);
+                    break;
+                }
+            }
+        }
+        if self.pending.stop {
+            self.stopped();
+        }
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Journals the end of the task. The logger and historian keep the last
+    /// values of the device, which no longer hold once it's stopped.
+    fn stopped(&self) {
+        self.journal(EventKind::Connection, "Disconnected");
+        self.degrade(Quality::CommFail);
+    }
+
+    /// Waits for the next connection attempt, longer after each failure.
+    /// Returns false when the device was stopped in the meantime.
+    async fn wait_to_reconnect(&mut self) -> bool {
+        let until = tokio::time::Instant::now() + self.backoff.next_delay();
+        loop {
+            for request in std::mem::take(&mut self.pending.writes) {
+                self.report_write(request, Err("The device is offline.".to_string()));
+            }
+            self.pending.scan = false;
+            if self.pending.stop {
+                self.stopped();
+                return false;
+            }
+            if tokio::time::timeout_at(until, self.receive_command())
+                .await
+                .is_err()
+            {
+                return true;
+            }
+        }
+    }
+
+    /// Journals a write and reports its outcome to the setpoint dialog.
+    fn report_write(&self, request: WriteRequest, result: Result<(), String>) {
+        let result = match result {
+            Ok(()) => Ok(format!("Wrote {}", request.value)),
+            Err(e) => Err(format!("Could not write {}. {}", request.value, e)),
+        };
+        let message = match &result {
+            Ok(message) | Err(message) => message.clone(),
+        };
+        self.send(Update::Event(
+            Event::new(EventKind::Write, &request.source, None, &message).by_operator(),
+        ));
+        self.send(Update::WriteResult(WriteResult {
+            source: request.source,
+            result,
+        }));
+    }
+
+    fn connecting(&mut self) {
+        self.status.health.connecting();
+        self.report_status();
+    }
+
+    /// Journals the opening of the link.
+    fn connected(&mut self) {
+        self.status.health.connected();
+        self.journal(EventKind::Connection, "Connected");
+        self.report_status();
+    }
+
+    /// Reports a failed connection attempt.
+    fn connect_failed(&mut self, error_msg: String) {
+        self.status.health.connect_failed();
+        self.set_error(error_msg);
+        self.degrade(Quality::CommFail);
+    }
+
+    /// Reports a failed scan. Returns true when the link is deemed lost and
+    /// should be reopened.
+    fn scan_failed(&mut self, failure: Failure, error_msg: String) -> bool {
+        let lost = self.status.health.scan_failed(failure);
+        self.missed_scans += 1;
+        self.set_error(error_msg);
+        if lost {
+            self.journal(EventKind::Connection, "Link lost");
+            self.degrade(Quality::CommFail);
+        } else if self.missed_scans >= self.stale_after_scans {
+            self.degrade(Quality::Stale);
+        }
+        lost
+    }
+
+    /// Hands the last values over again with `quality`.
+    fn degrade(&self, quality: Quality) {
+        if let Some(last) = &self.last {
+            self.send_snapshot(last.degraded(quality));
+        }
+    }
+
+    /// Reports a comms error. It is only journaled when it differs from the
+    /// current one, so a dead link doesn't flood the journal.
+    fn set_error(&mut self, error_msg: String) {
+        if self.status.error_msg != error_msg {
+            self.send(Update::Event(Event::new(
+                EventKind::CommsError,
+                &self.device,
+                None,
+                &error_msg,
+            )));
+        }
+        self.status.error_msg = error_msg;
+        self.status.achieved_scan_time = 0;
+        self.report_status();
+    }
+
+    /// Journals the errors of the tags a good scan couldn't read, each only
+    /// when it differs from the one of the last scan.
+    fn report_tag_errors(&mut self, errors: HashMap<String, String>) {
+        for (tag, error) in &errors {
+            if self.tag_errors.get(tag) != Some(error) {
+                let message = format!("Could not read the tag. {}", error);
+                self.send(Update::Event(Event::new(
+                    EventKind::CommsError,
+                    tag,
+                    None,
+                    &message,
+                )));
+            }
+        }
+        self.tag_errors = errors;
+    }
+
+    /// Hands the values of a good scan to the main thread, clearing the
+    /// comms error.
+    fn publish(&mut self, snapshot: Snapshot, achieved_scan_time: u128, stats: &[ScanStats]) {
+        if !self.status.error_msg.is_empty() {
+            self.status.error_msg.clear();
+            self.journal(EventKind::Connection, "Communication restored");
+        }
+        self.status.health.scan_ok();
+        self.status.achieved_scan_time = achieved_scan_time;
+        self.status.scan_stats = stats.to_vec();
+        self.missed_scans = 0;
+        self.backoff.reset();
+        // The scan classes each read a part of the values.
+        match &mut self.last {
+            Some(last) => {
+                last.timestamp = snapshot.timestamp;
+                last.samples.extend(snapshot.samples.clone());
+                last.signal_states.extend(snapshot.signal_states.clone());
+            }
+            None => self.last = Some(snapshot.clone()),
+        }
+        self.send_snapshot(snapshot);
+        self.report_status();
+    }
+}
+
+/// Decodes the tags and signals of `device` out of the blocks read in a scan.
+/// Only the tags the blocks cover are part of the snapshot.
+fn decode_blocks(
+    blocks: Vec<(ReadBlock, BlockData)>,
+    tags: &[Tag],
+    signals: &[DigitalSignal],
+    device: &str,
+) -> Snapshot {
+    let mut tag_values = HashMap::new();
+    let mut signal_states = HashMap::new();
+    // A scan class only reads the blocks of its period, so the other tags
+    // aren't missing from it.
+    let scanned: Vec<Tag> = tags
+        .iter()
+        .filter(|tag| {
+            blocks.iter().any(|(block, _)| {
+                let count = tag.data_type.register_count() as u16;
+                block.covers(tag.register_type, tag.address, count)
+            })
+        })
+        .cloned()
+        .collect();
+    for (block, answer) in blocks {
+        let (table, start) = (block.register_type, block.start_address);
+        match answer {
+            BlockData::Registers(registers) => {
+                tag_values.extend(read_tags(tags, device, table, &registers, start));
+                signal_states.extend(read_signals(signals, device, table, &registers, start));
+            }
+            BlockData::Bits(bits) => {
+                signal_states.extend(read_bit_signals(signals, device, table, &bits, start));
+            }
+        }
+    }
+    Snapshot::new(device, &scanned, tag_values, signal_states)
+}
+
+/// The threads shared by all the devices, started with the first one and
+/// stopped with the last one.
+struct Services {
+    feed: ServiceFeed,
+    /// Sent by the HTTP server once it listens.
+    server: Receiver<ServerHandle>,
+    /// The thread of the HTTP server, which ends once it stops.
+    server_thread: thread::JoinHandle<()>,
+    /// The tag values served by `/tags`.
+    live: watch::Sender<Vec<TagReading>>,
+}
+
+impl Services {
+    /// Stops the HTTP server. The logger and historian threads end as their
+    /// channels are dropped. The returned thread ends once the server is gone.
+    fn stop(self) -> thread::JoinHandle<()> {
+        let Services {
+            server,
+            server_thread,
+            ..
+        } = self;
+        // The server may still be starting, so its handle is awaited off the UI thread.
+        thread::spawn(move || {
+            if let Ok(handle) = server.recv() {
+                rt::System::new().block_on(handle.stop(true));
+            }
+            let _ = server_thread.join();
+        })
+    }
+}
+
+/// The channels to the logger and historian threads. Each polling task holds
+/// a clone, so the values are recorded even while the UI isn't drawn.
+#[derive(Clone)]
+struct ServiceFeed {
+    logger: Sender<ServiceCommand<LoggerConfig>>,
+    historian: Option<Sender<ServiceCommand<HistorianConfig>>>,
+}
+
+impl ServiceFeed {
+    fn snapshot(&self, snapshot: &Snapshot) {
+        let _ = self.logger.send(ServiceCommand::Snapshot(snapshot.clone()));
+        if let Some(historian) = &self.historian {
+            let _ = historian.send(ServiceCommand::Snapshot(snapshot.clone()));
+        }
+    }
+}
+
+/// The receiving end of the updates of all the background threads.
+struct UpdateChannel {
+    sender: Sender<Update>,
+    receiver: Receiver<Update>,
+}
+
+impl Default for UpdateChannel {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self { sender, receiver }
+    }
 }
+//####################################################
+
 //#################################################### The available protocols.
 #[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Protocol {
     ModbusTcpProtocol,
     ModbusRtuProtocol,
@@ -111,7 +546,7 @@ impl Display for Protocol {
             Protocol::ModbusRtuProtocol => write!(f, "Modbus Serial"),
             Protocol::EthernetIpProtocol => write!(f, "Ethernet/IP"),
             Protocol::S7Protocol => write!(f, "Siemens S7"),
-            Protocol::Datascan => write!(f, "Datascan"),
+            Protocol::Datascan => write!(f, "Datascan (stand-in)"),
         }
     }
 }
@@ -143,49 +578,93 @@ impl Default for AppRunState {
         }
     }
 }
+/// A field device of the session, polled by its own thread.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+struct Device {
+    protocol: Protocol,
+    config: DeviceConfigUiBuffer,
+    #[serde(skip)]
+    run_state: AppRunState,
+    /// The command channel of the polling task, once connected.
+    #[serde(skip)]
+    commands: Option<UnboundedSender<DeviceCommand>>,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEVICE)
+    }
+}
+
+impl Device {
+    fn new(name: &str) -> Self {
+        Self {
+            protocol: Protocol::default(),
+            config: DeviceConfigUiBuffer {
+                device_name: name.to_string(),
+                ..Default::default()
+            },
+            run_state: AppRunState::default(),
+            commands: None,
+        }
+    }
+
+    /// Hands `command` over to the polling task, if there is one.
+    fn send(&self, command: DeviceCommand) {
+        if let Some(commands) = &self.commands {
+            // A stopped thread has nothing left to do with it.
+            let _ = commands.send(command);
+        }
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
 struct DeviceConfigUiBuffer {
+    device_name: String,
+    /// The failed scans in a row after which the last values are stale.
+    stale_after_scans: u32,
     modbus_serial_buffer: ModbusSerialConfig,
     modbus_tcp_buffer: ModbusTcpConfig,
     ethernet_ip_buffer: EthernetIpConfig,
     s7_buffer: S7Config,
+    datascan_buffer: DatascanConfig,
 }
 
 impl Default for DeviceConfigUiBuffer {
     fn default() -> Self {
         Self {
+            device_name: DEFAULT_DEVICE.to_string(),
+            stale_after_scans: STALE_AFTER_SCANS,
             modbus_serial_buffer: ModbusSerialConfig::default(),
             modbus_tcp_buffer: ModbusTcpConfig::default(),
-            ethernet_ip_buffer: EthernetIpConfig,
+            ethernet_ip_buffer: EthernetIpConfig::default(),
             s7_buffer: S7Config::default(),
+            datascan_buffer: DatascanConfig::default(),
         }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
-enum RegisterType {
-    Coils,
-    Inputs,
-    Holding,
-}
-
-impl Display for RegisterType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            RegisterType::Coils => write!(f, "Coils"),
-            RegisterType::Inputs => write!(f, "Input registers"),
-            RegisterType::Holding => write!(f, "Holding registers"),
+impl DeviceConfigUiBuffer {
+    /// The configuration the polling task of `protocol` is started with.
+    fn device_config(&self, protocol: &Protocol) -> DeviceConfig {
+        match protocol {
+            Protocol::ModbusTcpProtocol => DeviceConfig::ModbusTcp(self.modbus_tcp_buffer.clone()),
+            Protocol::ModbusRtuProtocol => {
+                DeviceConfig::ModbusSerial(self.modbus_serial_buffer.clone())
+            }
+            Protocol::EthernetIpProtocol => {
+                DeviceConfig::EthernetIp(self.ethernet_ip_buffer.clone())
+            }
+            Protocol::S7Protocol => DeviceConfig::S7(self.s7_buffer.clone()),
+            Protocol::Datascan => DeviceConfig::Datascan(self.datascan_buffer.clone()),
         }
     }
 }
 
-impl Default for RegisterType {
-    fn default() -> Self {
-        Self::Holding
-    }
-}
-
 #[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Parity {
     Even,
     Odd,
@@ -202,6 +681,16 @@ impl Display for Parity {
     }
 }
 
+impl Parity {
+    fn serial(&self) -> serialport::Parity {
+        match self {
+            Parity::Even => serialport::Parity::Even,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::NoneParity => serialport::Parity::None,
+        }
+    }
+}
+
 impl Default for Parity {
     fn default() -> Self {
         Self::NoneParity
@@ -223,6 +712,15 @@ impl Display for Baudrate {
     }
 }
 
+impl Baudrate {
+    fn bauds(&self) -> u32 {
+        match self {
+            Baudrate::Baud38400 => 38400,
+            Baudrate::Baud9600 => 9600,
+        }
+    }
+}
+
 impl Default for Baudrate {
     fn default() -> Self {
         Self::Baud38400
@@ -230,6 +728,7 @@ impl Default for Baudrate {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
 struct ModbusSerialConfig {
     port: String,
     baudrate: Baudrate,
@@ -253,6 +752,33 @@ impl Default for ModbusSerialConfig {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+struct DatascanConfig {
+    port: String,
+    baudrate: Baudrate,
+    parity: Parity,
+    /// Milliseconds to wait for each answer of a module.
+    timeout: u64,
+    /// Milliseconds between two reads of the point list.
+    scan_period: u64,
+    points: Vec<DatascanPoint>,
+}
+
+impl Default for DatascanConfig {
+    fn default() -> Self {
+        Self {
+            port: SIMULATOR_PORT.to_string(),
+            baudrate: Baudrate::Baud9600,
+            parity: Parity::default(),
+            timeout: 1000,
+            scan_period: 1000,
+            points: default_datascan_points(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
 struct ModbusTcpConfig {
     ip_address: String,
     port: usize,
@@ -270,62 +796,244 @@ impl Default for ModbusTcpConfig {
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
-struct EthernetIpConfig;
+#[serde(default)]
+struct EthernetIpConfig {
+    ip: String,
+    /// The backplane slot of the Logix CPU.
+    slot: u8,
+    /// Milliseconds to wait for each answer of the controller.
+    timeout: u64,
+    /// Milliseconds between two reads of the tag list.
+    scan_period: u64,
+    tags: Vec<LogixTag>,
+}
+
+impl Default for EthernetIpConfig {
+    fn default() -> Self {
+        Self {
+            ip: "127.0.0.1".to_string(),
+            slot: 0,
+            timeout: 5000,
+            scan_period: 1000,
+            tags: default_logix_tags(),
+        }
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
 struct S7Config {
     ip: String,
+    rack: u16,
+    slot: u16,
+    connection_type: S7ConnectionType,
+    /// Read and write timeout of the socket, in milliseconds.
+    timeout: u64,
+    /// Milliseconds between two reads of the tag list.
+    scan_period: u64,
+    tags: Vec<S7Tag>,
 }
 
 impl Default for S7Config {
     fn default() -> Self {
         Self {
             ip: "127.0.0.1".to_string(),
+            rack: 0,
+            slot: 1,
+            connection_type: S7ConnectionType::default(),
+            timeout: 5000,
+            scan_period: 1000,
+            tags: default_s7_tags(),
         }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Clone)]
-struct S7Data {
-    tag1: f32,
-    tag2: f32,
-    tag3: f32,
-    tag4: bool,
-    tag5: bool,
-    tag6: bool,
+/// The rack and slot of the CPU in each PLC family.
+const S7_FAMILIES: [(&str, u16, u16); 3] = [("S7-300", 0, 2), ("S7-1200", 0, 1), ("S7-1500", 0, 1)];
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy, Default)]
+enum S7ConnectionType {
+    #[default]
+    PG,
+    OP,
+    S7Basic,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Clone)]
-struct S7MessageTag {
-    message: S7Message,
-    db: i32,
-    offset: f32,
+impl S7ConnectionType {
+    fn connection(&self) -> Connection {
+        match self {
+            S7ConnectionType::PG => Connection::PG,
+            S7ConnectionType::OP => Connection::OP,
+            S7ConnectionType::S7Basic => Connection::Basic,
+        }
+    }
 }
-#[derive(serde::Deserialize, serde::Serialize, Clone)]
-enum S7Message {
-    S7Bool(bool),
-    S7Real(f32),
-    S7Int(i16),
+
+impl Display for S7ConnectionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            S7ConnectionType::PG => write!(f, "PG"),
+            S7ConnectionType::OP => write!(f, "OP"),
+            S7ConnectionType::S7Basic => write!(f, "S7 Basic"),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(from = "StoredModbusDefinitions")]
 struct ModbusDefinitions {
+    blocks: Vec<ReadBlock>,
+    /// Generates the blocks from the tag database instead of `blocks`.
+    auto_blocks: bool,
+    /// The most unused addresses read to join two generated blocks.
+    max_gap: u16,
+    /// The period of the normal scan class, in ms.
+    #[serde(alias = "scan_delay")]
+    scan_period: u64,
+    /// The period of the fast scan class, in ms.
+    fast_period: u64,
+    /// The period of the slow scan class, in ms.
+    slow_period: u64,
+}
+
+impl Default for ModbusDefinitions {
+    fn default() -> Self {
+        Self {
+            blocks: vec![ReadBlock::default()],
+            auto_blocks: false,
+            max_gap: 10,
+            scan_period: 1000,
+            fast_period: 200,
+            slow_period: 10000,
+        }
+    }
+}
+
+/// The Modbus definitions as saved. Before the block list, a device read a
+/// single block, which becomes the first one of the list.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct StoredModbusDefinitions {
+    blocks: Vec<ReadBlock>,
+    auto_blocks: bool,
+    max_gap: u16,
+    #[serde(alias = "scan_delay")]
+    scan_period: u64,
+    fast_period: u64,
+    slow_period: u64,
     register_type: RegisterType,
     start_address: u16,
+    /// 0 when the definitions were saved with a block list.
     register_count: u16,
-    scan_delay: u64,
-    request_function_vec: Vec<u8>,
 }
 
-impl Default for ModbusDefinitions {
+impl Default for StoredModbusDefinitions {
     fn default() -> Self {
+        let definitions = ModbusDefinitions::default();
         Self {
+            blocks: definitions.blocks,
+            auto_blocks: definitions.auto_blocks,
+            max_gap: definitions.max_gap,
+            scan_period: definitions.scan_period,
+            fast_period: definitions.fast_period,
+            slow_period: definitions.slow_period,
             register_type: RegisterType::default(),
             start_address: 0,
-            register_count: 38,
-            scan_delay: 1000,
-            request_function_vec: Vec::with_capacity(32),
+            register_count: 0,
+        }
+    }
+}
+
+impl From<StoredModbusDefinitions> for ModbusDefinitions {
+    fn from(stored: StoredModbusDefinitions) -> Self {
+        let mut blocks = stored.blocks;
+        if stored.register_count > 0 {
+            blocks = vec![ReadBlock {
+                register_type: stored.register_type,
+                start_address: stored.start_address,
+                count: stored.register_count.min(stored.register_type.max_count()),
+                ..Default::default()
+            }];
+        }
+        Self {
+            blocks,
+            auto_blocks: stored.auto_blocks,
+            max_gap: stored.max_gap,
+            scan_period: stored.scan_period,
+            fast_period: stored.fast_period,
+            slow_period: stored.slow_period,
+        }
+    }
+}
+
+impl ModbusDefinitions {
+    /// The blocks polled each scan.
+    fn read_blocks(&self, tags: &[Tag], signals: &[DigitalSignal], device: &str) -> Vec<ReadBlock> {
+        if self.auto_blocks {
+            generate_blocks(tags, signals, device, self.max_gap)
+        } else {
+            self.blocks.clone()
+        }
+    }
+
+    /// The period of the scan class in this device, `None` when it is only
+    /// read on demand.
+    fn class_period(&self, class: ScanClass) -> Option<Duration> {
+        match class {
+            ScanClass::Fast => Some(Duration::from_millis(self.fast_period)),
+            ScanClass::Normal => Some(Duration::from_millis(self.scan_period)),
+            ScanClass::Slow => Some(Duration::from_millis(self.slow_period)),
+            ScanClass::OnDemand => None,
+        }
+    }
+
+    /// The blocks sorted into their scan classes. Must be called from within
+    /// the runtime.
+    fn scan_plan(&self, tags: &[Tag], signals: &[DigitalSignal], device: &str) -> ScanPlan {
+        let mut classes = Vec::new();
+        let mut blocks = Vec::new();
+        let mut on_demand = Vec::new();
+        for (class, class_blocks) in group_by_class(self.read_blocks(tags, signals, device)) {
+            match self.class_period(class) {
+                Some(period) => {
+                    classes.push((class, period));
+                    blocks.push(class_blocks);
+                }
+                None => on_demand = class_blocks,
+            }
+        }
+        ScanPlan {
+            scheduler: Scheduler::new(&classes),
+            classes,
+            blocks,
+            on_demand,
+        }
+    }
+}
+
+/// The read blocks of a Modbus device, by scan class.
+struct ScanPlan {
+    scheduler: Scheduler,
+    /// The scheduled classes and their periods.
+    classes: Vec<(ScanClass, Duration)>,
+    /// The blocks of the scheduled classes, in the order of the scheduler.
+    blocks: Vec<Vec<ReadBlock>>,
+    /// The blocks only read when the operator asks for it.
+    on_demand: Vec<ReadBlock>,
+}
+
+impl ScanPlan {
+    /// Takes the blocks of `edited`. The scheduler, with its due times and
+    /// stats, is only replaced when the classes or their periods changed,
+    /// which is what this returns.
+    fn update(&mut self, edited: ScanPlan) -> bool {
+        if edited.classes != self.classes {
+            *self = edited;
+            return true;
         }
+        self.blocks = edited.blocks;
+        self.on_demand = edited.on_demand;
+        false
     }
 }
 
@@ -335,6 +1043,7 @@ enum DeviceConfig {
     ModbusSerial(ModbusSerialConfig),
     EthernetIp(EthernetIpConfig),
     S7(S7Config),
+    Datascan(DatascanConfig),
 }
 
 impl Default for DeviceConfig {
@@ -347,89 +1056,48 @@ impl Default for DeviceConfig {
 
 impl Default for CarbonApp {
     fn default() -> Self {
-        let mut tags: Vec<Tag> = Vec::new();
-
-        tags.push(Tag {
-            name: "LT1-1".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 350., y: 350. },
-        });
-        tags.push(Tag {
-            name: "PT1-1".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 350., y: 400. },
-        });
-        tags.push(Tag {
-            name: "PT2-1".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 450., y: 350. },
-        });
-        tags.push(Tag {
-            name: "PT1-2".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 450., y: 400. },
-        });
-        tags.push(Tag {
-            name: "PT2-2".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 550., y: 350. },
-        });
-        tags.push(Tag {
-            name: "PT2-3".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 550., y: 450. },
-        });
-        tags.push(Tag {
-            name: "PT3-1".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 550., y: 500. },
-        });
-        tags.push(Tag {
-            name: "TBA".to_string(),
-            value: 0.0,
-            pos: Pos2 { x: 550., y: 550. },
-        });
-
         Self {
             // Example stuff:
-            app_run_state: AppRunState::default(),
-            device_config_buffer: DeviceConfigUiBuffer::default(),
-            mutex: Arc::new(Mutex::new(MutexData {
-                data: Vec::new(),
-                s7_read_data: S7Data {
-                    tag1: 0.0,
-                    tag2: 0.0,
-                    tag3: 0.0,
-                    tag4: false,
-                    tag5: false,
-                    tag6: false,
-                },
-                s7_message: None,
-                achieved_scan_time: 0,
-                error_msg: "".to_string(),
-                new_config: None,
-                log: true,
-                kill_thread: false,
-            })),
-            protocol: Protocol::default(),
-            device_config: DeviceConfig::default(),
-            tag1: 0.0,
-            tag2: 0.0,
-            tag3: 0.0,
+            devices: vec![Device::default()],
+            legacy_protocol: None,
+            legacy_config: None,
+            selected_device: 0,
+            updates: UpdateChannel::default(),
+            device_status: HashMap::new(),
+            services: None,
+            runtime: None,
             about: false,
             options: false,
             edit_pos: false,
-            widgets_pos: WidgetsPos {
-                hello_button_pos: Pos2::new(850., 350.),
-                close_button_pos: Pos2::new(1050., 350.),
-                tag1_pos: Pos2::new(450., 350.),
-            },
-            tags,
-            digital_inputs: 0,
-            digital_inputs2: 0,
-            blink_time: 1000,
-            blink_flag: false,
-            logger_path: PathBuf::from("./LOGGER.txt"),
+            tags: default_tags(),
+            tag_database: false,
+            selected_tag: None,
+            signals: default_signals(),
+            signal_editor: false,
+            signal_states: HashMap::new(),
+            alarms: AlarmManager::default(),
+            alarm_summary: false,
+            limit_monitor: LimitMonitor::default(),
+            journal: Journal::default(),
+            journal_window: false,
+            journal_filter: JournalFilter::default(),
+            journal_retained_files: 0,
+            trend: Trend::default(),
+            trend_buffer: TrendBuffer::default(),
+            trend_window: false,
+            replay: None,
+            replay_window: false,
+            logger: LoggerConfig::default(),
+            logger_draft: None,
+            logger_window: false,
+            logger_status: LoggerStatus::default(),
+            historian_config: HistorianConfig::default(),
+            historian: None,
+            historian_window: false,
+            historian_error: None,
+            server_error: None,
+            server_stopping: None,
+            setpoint: None,
         }
     }
 }
@@ -481,15 +1149,43 @@ impl CarbonApp {
 
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
-        }
+        let mut app: Self = match cc.storage {
+            Some(storage) => eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default(),
+            None => Default::default(),
+        };
+        app.adopt_legacy_device();
+        app.journal.set_retained_files(app.journal_retained_files);
+        app.historian = Some(Historian::open(Path::new("./HISTORIAN.db")).map(Arc::new));
+        app
+    }
 
-        Default::default()
+    /// Makes the device of a state saved before devices became a list the
+    /// first and only device.
+    fn adopt_legacy_device(&mut self) {
+        let protocol = self.legacy_protocol.take();
+        let config = self.legacy_config.take();
+        if protocol.is_none() && config.is_none() {
+            return;
+        }
+        self.devices = vec![Device {
+            protocol: protocol.unwrap_or_default(),
+            config: config.unwrap_or_default(),
+            ..Default::default()
+        }];
     }
 }
 
-impl eframe::App for CarbonApp {
+/// Deserializes a field that is only present in some states, which RON stores
+/// without the `Some(...)` of an `Option`.
+pub(crate) fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl eframe::App for CarbonApp {
     /// Called by the frame work to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, self);
@@ -499,27 +1195,135 @@ impl eframe::App for CarbonApp {
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let Self {
-            app_run_state,
-            device_config_buffer,
-            mutex,
-            protocol,
-            device_config,
-            tag1,
-            tag2,
-            tag3,
+            devices,
+            selected_device,
+            updates,
+            device_status,
+            services,
+            runtime,
             about,
             options,
             edit_pos,
-            widgets_pos,
             tags,
-            digital_inputs,
-            digital_inputs2,
-            blink_time,
-            blink_flag,
-            logger_path,
+            tag_database,
+            selected_tag,
+            signals,
+            signal_editor,
+            signal_states,
+            alarms,
+            alarm_summary,
+            limit_monitor,
+            journal,
+            journal_window,
+            journal_filter,
+            journal_retained_files,
+            trend,
+            trend_buffer,
+            trend_window,
+            replay,
+            replay_window,
+            logger,
+            logger_draft,
+            logger_window,
+            logger_status,
+            historian_config,
+            historian,
+            historian_window,
+            historian_error,
+            server_error,
+            server_stopping,
+            setpoint,
+            ..
         } = self;
 
         ctx.request_repaint();
+
+        // Everything the background threads sent since the last frame, in order.
+        let mut scanned = false;
+        for update in updates.receiver.try_iter() {
+            match update {
+                Update::Snapshot(snapshot) => {
+                    scanned = true;
+                    for tag in tags.iter_mut() {
+                        if let Some(sample) = snapshot.samples.get(&tag.name) {
+                            tag.set_sample(sample);
+                        }
+                    }
+                    // The signals read in this scan take its states. A scan
+                    // class only reads some of them, so the others are kept.
+                    signal_states
+                        .retain(|label, _| signals.iter().any(|signal| signal.label == *label));
+                    signal_states.extend(snapshot.signal_states.clone());
+
+                    // Only the good values are trended and checked against their limits.
+                    let now = std::time::Instant::now();
+                    for (name, sample) in snapshot.samples.iter() {
+                        trend_buffer.set_quality(name, snapshot.timestamp, sample.quality);
+                        let (Quality::Good, Some(Value::Number(value))) =
+                            (sample.quality, &sample.value)
+                        else {
+                            continue;
+                        };
+                        let value = *value;
+                        trend_buffer.push(name, sample.timestamp, value);
+                        if let Some(tag) = tags.iter().find(|tag| tag.name == *name) {
+                            for (name, condition, severity) in
+                                limit_monitor.evaluate(&tag.name, &tag.limits, value, now)
+                            {
+                                alarms.update(&name, condition, severity);
+                            }
+                        }
+                    }
+                }
+                Update::Status { device, status } => {
+                    // A disconnected device may still report on its way out.
+                    if devices
+                        .iter()
+                        .any(|d| d.config.device_name == device && d.commands.is_some())
+                    {
+                        device_status.insert(device, status);
+                    }
+                }
+                Update::Event(event) => journal.record(event),
+                Update::WriteResult(result) => {
+                    if let Some(dialog) = setpoint.as_mut() {
+                        dialog.report(&result);
+                    }
+                }
+                Update::LoggerStatus(status) => *logger_status = status,
+                Update::HistorianError(error) => *historian_error = error,
+                Update::ServerError(error) => {
+                    journal.record(Event::new(
+                        EventKind::CommsError,
+                        "HTTP server",
+                        None,
+                        &error,
+                    ));
+                    *server_error = Some(error);
+                }
+            }
+        }
+        if let (true, Some(services)) = (scanned, services.as_ref()) {
+            services
+                .live
+                .send_replace(tags.iter().map(TagReading::new).collect());
+        }
+        for (name, status) in device_status.iter() {
+            let offline = status.health.state == LinkState::Offline;
+            alarms.update(&comm_fail_alarm(name), offline, Severity::High);
+        }
+
+        // We feed the alarm manager with the latest signal states
+        for signal in signals.iter() {
+            if let Some(state) = signal_states.get(&signal.label) {
+                alarms.update(&signal.label, signal.is_alarm(*state), signal.severity);
+            }
+        }
+        alarms.tick();
+        for event in alarms.take_events() {
+            journal.record(event);
+        }
+
         #[cfg(not(target_arch = "wasm32"))] // no File->Quit on web pages!
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
@@ -528,13 +1332,59 @@ impl eframe::App for CarbonApp {
                     if ui.button("Options").clicked() {
                         *options = !*options;
                     }
+                    if ui.button("Logger").clicked() {
+                        *logger_window = !*logger_window;
+                    }
+                    if ui.button("Historian").clicked() {
+                        *historian_window = !*historian_window;
+                    }
+                    if ui.button("Open log").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Logger files", &["txt", "csv"])
+                            .set_directory(&logger.directory)
+                            .pick_file()
+                        {
+                            *replay = Some(Replay::open(&path));
+                            *replay_window = true;
+                        }
+                        ui.close_menu();
+                    }
                     if ui.button("Quit").clicked() {
                         _frame.close();
                     }
                 });
                 ui.menu_button("Edit", |ui| {
                     ui.checkbox(edit_pos, "Edit positions");
+                    if ui.button("Tag database").clicked() {
+                        *tag_database = !*tag_database;
+                    }
+                    if ui.button("Digital signals").clicked() {
+                        *signal_editor = !*signal_editor;
+                    }
+                });
+                ui.menu_button("View", |ui| {
+                    if ui.button("Event journal").clicked() {
+                        *journal_window = !*journal_window;
+                    }
+                    if ui.button("Trend").clicked() {
+                        *trend_window = !*trend_window;
+                    }
                 });
+                let unacked = alarms.unacked_count();
+                let alarm_button = if unacked > 0 {
+                    RichText::new(format!(
+                        "{} Alarms ({})",
+                        egui_phosphor::regular::BELL,
+                        unacked
+                    ))
+                    .color(Color32::WHITE)
+                    .background_color(Color32::DARK_RED)
+                } else {
+                    RichText::new(format!("{} Alarms", egui_phosphor::regular::BELL))
+                };
+                if ui.button(alarm_button).clicked() {
+                    *alarm_summary = !*alarm_summary;
+                }
                 ui.menu_button("Help", |ui| {
                     if ui.button("About").clicked() {
                         *about = !*about;
@@ -548,26 +1398,229 @@ impl eframe::App for CarbonApp {
                 "Developed by Abdelkader Madoui. All rights reserved 2024.\nabdelkadermadoui@protonmail.com",
             )));
         });
+        egui::Window::new(format!("{} Tag Database", egui_phosphor::regular::DATABASE))
+            .open(tag_database)
+            .show(ctx, |ui| {
+                if tag_database_ui(ui, tags, selected_tag) {
+                    // The limit alarms of a renamed or deleted tag would never clear.
+                    for (name, severity) in
+                        limit_monitor.retain(|name| tags.iter().any(|tag| tag.name == name))
+                    {
+                        alarms.update(&name, false, severity);
+                    }
+                    // We hand the edited tags over to the polling tasks
+                    for device in devices.iter() {
+                        device.send(DeviceCommand::Tags(tags.clone()));
+                    }
+                }
+            });
+        egui::Window::new(format!(
+            "{} Digital Signals",
+            egui_phosphor::regular::LIST_BULLETS
+        ))
+        .open(signal_editor)
+        .show(ctx, |ui| {
+            if signals_ui(ui, signals) {
+                // We hand the edited signals over to the polling tasks
+                for device in devices.iter() {
+                    device.send(DeviceCommand::Signals(signals.clone()));
+                }
+            }
+        });
+        egui::Window::new(format!("{} Alarm Summary", egui_phosphor::regular::BELL))
+            .open(alarm_summary)
+            .show(ctx, |ui| {
+                alarm_summary_ui(ui, alarms);
+            });
+        egui::Window::new(format!(
+            "{} Event Journal",
+            egui_phosphor::regular::NOTEBOOK
+        ))
+        .open(journal_window)
+        .show(ctx, |ui| {
+            journal_ui(ui, journal, journal_filter, journal_retained_files);
+        });
+        egui::Window::new(format!("{} Trend", egui_phosphor::regular::CHART_LINE))
+            .open(trend_window)
+            .default_width(700.)
+            .show(ctx, |ui| {
+                let historian = historian
+                    .as_ref()
+                    .and_then(|historian| historian.as_ref().ok());
+                if let Some(error) = trend_ui(ui, trend, trend_buffer, historian, tags) {
+                    *historian_error = Some(error);
+                }
+            });
+        egui::Window::new(format!(
+            "{} Log Replay",
+            egui_phosphor::regular::CLOCK_COUNTER_CLOCKWISE
+        ))
+        .open(replay_window)
+        .default_width(700.)
+        .show(ctx, |ui| match replay {
+            Some(Ok(replay)) => replay_ui(ui, replay, tags),
+            Some(Err(error)) => {
+                ui.colored_label(Color32::DARK_RED, error.as_str());
+            }
+            None => {}
+        });
+        egui::Window::new(format!("{} Logger", egui_phosphor::regular::FILE_CSV))
+            .open(logger_window)
+            .show(ctx, |ui| {
+                let draft = logger_draft.get_or_insert_with(|| logger.clone());
+                if logger_ui(ui, draft, logger, logger_status, tags) {
+                    *logger = draft.clone();
+                    if let Some(services) = services.as_ref() {
+                        let _ = services
+                            .feed
+                            .logger
+                            .send(ServiceCommand::Config(logger.clone()));
+                    }
+                }
+            });
+        egui::Window::new(format!("{} Historian", egui_phosphor::regular::DATABASE))
+            .open(historian_window)
+            .show(ctx, |ui| {
+                if historian_ui(
+                    ui,
+                    historian_config,
+                    historian.as_ref(),
+                    historian_error.as_ref(),
+                ) {
+                    if let Some(historian) =
+                        services.as_ref().and_then(|s| s.feed.historian.as_ref())
+                    {
+                        let _ = historian.send(ServiceCommand::Config(historian_config.clone()));
+                    }
+                }
+            });
+        let connected: Vec<&str> = devices
+            .iter()
+            .filter(|device| device.run_state.is_loop_running)
+            .map(|device| device.config.device_name.as_str())
+            .collect();
+        let mut setpoint_open = setpoint.is_some();
+        if let Some(dialog) = setpoint.as_mut() {
+            egui::Window::new(format!(
+                "{} {}",
+                egui_phosphor::regular::PENCIL_SIMPLE,
+                dialog.title()
+            ))
+            .id(egui::Id::new("setpoint_dialog"))
+            .open(&mut setpoint_open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                if let Some(request) =
+                    setpoint_ui(ui, dialog, tags, signals, signal_states, &connected)
+                {
+                    if let Some(device) = devices
+                        .iter()
+                        .find(|device| device.config.device_name == request.device)
+                    {
+                        device.send(DeviceCommand::Write(request));
+                    }
+                }
+            });
+        }
+        if !setpoint_open {
+            *setpoint = None;
+        }
         egui::TopBottomPanel::bottom("bottom-panel").show(ctx, |ui| {
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 ui.horizontal(|ui| {
                     ui.spacing_mut().item_spacing.x = 30.0;
                     ui.colored_label(Color32::GRAY, "Carbon v0.1");
+                    if let Some(error) = &logger_status.error {
+                        ui.colored_label(Color32::DARK_RED, error);
+                    }
+                    if let Some(error) = historian_error {
+                        ui.colored_label(Color32::DARK_RED, error.as_str());
+                    }
+                    if let Some(error) = server_error {
+                        ui.colored_label(Color32::DARK_RED, error.as_str());
+                    }
 
-                    if let Some(data) = mutex.try_lock() {
-                        let achieved_scan_time = data.achieved_scan_time;
-                        let error_msg = &data.error_msg;
+                    let mut names: Vec<&String> = device_status.keys().collect();
+                    names.sort();
+                    // Laid out right to left, so the first device ends up leftmost.
+                    for name in names.into_iter().rev() {
+                        let status = &device_status[name];
+                        ui.colored_label(Color32::GRAY, &status.error_msg);
                         ui.colored_label(
                             Color32::GRAY,
-                            format!("Achieved scan time: {} μs", achieved_scan_time),
+                            format!("Achieved scan time: {} μs", status.achieved_scan_time),
+                        );
+                        ui.colored_label(
+                            status.health.state.color(),
+                            format!("{} {}", name, status.health.state),
+                        )
+                        .on_hover_text(
+                            std::iter::once(status.health.stats.to_string())
+                                .chain(status.scan_stats.iter().map(ToString::to_string))
+                                .collect::<Vec<_>>()
+                                .join("\n"),
                         );
-                        ui.colored_label(Color32::GRAY, format!("{}", error_msg));
                     }
                 });
             });
         });
 
         egui::Window::new("Options").open(options).show(ctx, |ui| {
+            if devices.is_empty() {
+                devices.push(Device::default());
+            }
+            *selected_device = (*selected_device).min(devices.len() - 1);
+
+            ui.label(format!("{} Devices", egui_phosphor::regular::GEAR_SIX));
+            ui.horizontal(|ui| {
+                ComboBox::from_label("Device")
+                    .selected_text(&devices[*selected_device].config.device_name)
+                    .show_ui(ui, |ui| {
+                        for (i, device) in devices.iter().enumerate() {
+                            let text =
+                                format!("{} ({})", device.config.device_name, device.protocol);
+                            ui.selectable_value(selected_device, i, text);
+                        }
+                    });
+                if ui
+                    .button(format!("{} Add device", egui_phosphor::regular::PLUS))
+                    .clicked()
+                {
+                    devices.push(Device::new(&format!("PLC-{}", devices.len() + 1)));
+                    *selected_device = devices.len() - 1;
+                }
+                if ui
+                    .add_enabled(
+                        devices.len() > 1 && !devices[*selected_device].run_state.is_loop_running,
+                        Button::new(egui_phosphor::regular::TRASH.to_string()),
+                    )
+                    .on_hover_text("Remove device")
+                    .clicked()
+                {
+                    devices.remove(*selected_device);
+                    *selected_device = selected_device.saturating_sub(1);
+                }
+            });
+            let mut names: Vec<&String> = devices
+                .iter()
+                .map(|device| &device.config.device_name)
+                .collect();
+            names.sort();
+            // The status, commands, tag routing and alarms of a device are all
+            // keyed by its name, so none connects while two share one.
+            let names_unique = !names.windows(2).any(|pair| pair[0] == pair[1]);
+            if !names_unique {
+                ui.colored_label(Color32::DARK_RED, "Device names must be unique.");
+            }
+            ui.separator();
+
+            let Device {
+                protocol,
+                config: device_config_buffer,
+                run_state: app_run_state,
+                commands,
+            } = &mut devices[*selected_device];
+
             ui.label(format!(
                 "{} Protocol Configuration",
                 egui_phosphor::regular::GEAR_SIX
@@ -581,7 +1634,7 @@ impl eframe::App for CarbonApp {
                         ui.selectable_value(protocol, Protocol::ModbusRtuProtocol, "Modbus Serial");
                         ui.selectable_value(protocol, Protocol::EthernetIpProtocol, "EthernetIP");
                         ui.selectable_value(protocol, Protocol::S7Protocol, "Siemens S7");
-                        ui.selectable_value(protocol, Protocol::Datascan, "Datascan");
+                        ui.selectable_value(protocol, Protocol::Datascan, "Datascan (stand-in)");
                     });
                 match protocol {
                     Protocol::ModbusTcpProtocol => {
@@ -601,12 +1654,77 @@ impl eframe::App for CarbonApp {
                 }
             });
 
+            ui.add_enabled_ui(app_run_state.enable_device_opt_edit, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Device Name");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut device_config_buffer.device_name)
+                            .desired_width(120.),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Stale After").on_hover_text(
+                        "The failed scans in a row after which the last values are stale.",
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut device_config_buffer.stale_after_scans)
+                            .clamp_range(1..=100)
+                            .suffix(" scans"),
+                    );
+                });
+            });
+
             match protocol {
-                Protocol::EthernetIpProtocol => {}
-                Protocol::Datascan => {}
+                Protocol::EthernetIpProtocol => {
+                    ui.group(|ui| {
+                        ui.set_enabled(app_run_state.enable_device_opt_edit);
+                        ui.label(format!("{} Device Options", egui_phosphor::regular::WRENCH));
+
+                        ethernet_ip_device_ui(ui, device_config_buffer);
+                    });
+                    ui.separator();
+                    ui.group(|ui| {
+                        ui.set_enabled(
+                            app_run_state.is_ui_apply_clicked || !app_run_state.is_loop_running,
+                        );
+                        ui.label(format!("{} Tag List", egui_phosphor::regular::WRENCH));
+
+                        logix_tags_ui(ui, &mut device_config_buffer.ethernet_ip_buffer.tags);
+                    });
+                }
+                Protocol::Datascan => {
+                    ui.group(|ui| {
+                        ui.set_enabled(app_run_state.enable_device_opt_edit);
+                        ui.label(format!("{} Device Options", egui_phosphor::regular::WRENCH));
+
+                        datascan_device_ui(ui, device_config_buffer);
+                    });
+                    ui.separator();
+                    ui.group(|ui| {
+                        ui.set_enabled(
+                            app_run_state.is_ui_apply_clicked || !app_run_state.is_loop_running,
+                        );
+                        ui.label(format!("{} Point List", egui_phosphor::regular::WRENCH));
+
+                        datascan_points_ui(ui, &mut device_config_buffer.datascan_buffer.points);
+                    });
+                }
                 Protocol::S7Protocol => {
-                    s7_device_ui(ui, device_config_buffer);
-                    *device_config = DeviceConfig::S7(device_config_buffer.s7_buffer.clone());
+                    ui.group(|ui| {
+                        ui.set_enabled(app_run_state.enable_device_opt_edit);
+                        ui.label(format!("{} Device Options", egui_phosphor::regular::WRENCH));
+
+                        s7_device_ui(ui, device_config_buffer);
+                    });
+                    ui.separator();
+                    ui.group(|ui| {
+                        ui.set_enabled(
+                            app_run_state.is_ui_apply_clicked || !app_run_state.is_loop_running,
+                        );
+                        ui.label(format!("{} Tag List", egui_phosphor::regular::WRENCH));
+
+                        s7_tags_ui(ui, &mut device_config_buffer.s7_buffer.tags);
+                    });
                 }
                 Protocol::ModbusTcpProtocol => {
                     // Modbus TCP UI
@@ -627,8 +1745,13 @@ impl eframe::App for CarbonApp {
                             egui_phosphor::regular::WRENCH
                         ));
 
+                        let blocks = device_config_buffer
+                            .modbus_tcp_buffer
+                            .protocol_definitions
+                            .read_blocks(tags, signals, &device_config_buffer.device_name);
                         modbus_protocol_ui(
                             &mut device_config_buffer.modbus_tcp_buffer.protocol_definitions,
+                            &blocks,
                             ui,
                         );
                     });
@@ -637,11 +1760,12 @@ impl eframe::App for CarbonApp {
                         ui.set_enabled(false);
                         modbus_request_details_ui(
                             ui,
-                            &mut device_config_buffer.modbus_tcp_buffer.protocol_definitions,
+                            &device_config_buffer
+                                .modbus_tcp_buffer
+                                .protocol_definitions
+                                .read_blocks(tags, signals, &device_config_buffer.device_name),
                         );
                     });
-                    *device_config =
-                        DeviceConfig::ModbusTcp(device_config_buffer.modbus_tcp_buffer.clone());
                 }
                 Protocol::ModbusRtuProtocol => {
                     // Modbus serial UI
@@ -662,10 +1786,15 @@ impl eframe::App for CarbonApp {
                             egui_phosphor::regular::WRENCH
                         ));
 
+                        let blocks = device_config_buffer
+                            .modbus_serial_buffer
+                            .protocol_definitions
+                            .read_blocks(tags, signals, &device_config_buffer.device_name);
                         modbus_protocol_ui(
                             &mut device_config_buffer
                                 .modbus_serial_buffer
                                 .protocol_definitions,
+                            &blocks,
                             ui,
                         );
                     });
@@ -674,14 +1803,12 @@ impl eframe::App for CarbonApp {
                         ui.set_enabled(false);
                         modbus_request_details_ui(
                             ui,
-                            &mut device_config_buffer
+                            &device_config_buffer
                                 .modbus_serial_buffer
-                                .protocol_definitions,
+                                .protocol_definitions
+                                .read_blocks(tags, signals, &device_config_buffer.device_name),
                         );
                     });
-                    *device_config = DeviceConfig::ModbusSerial(
-                        device_config_buffer.modbus_serial_buffer.clone(),
-                    );
                 }
             }
 
@@ -692,8 +1819,8 @@ impl eframe::App for CarbonApp {
                 .show(ui, |ui| {
                     if ui
                         .add_enabled(
-                            !app_run_state.is_loop_running,
-                            Button::new(format!("Connect")).min_size(Vec2::new(100., 10.)),
+                            !app_run_state.is_loop_running && names_unique,
+                            Button::new("Connect").min_size(Vec2::new(100., 10.)),
                         )
                         //.button(format!("{} Connect", egui_phosphor::regular::PLUGS))
                         .clicked()
@@ -701,27 +1828,148 @@ impl eframe::App for CarbonApp {
                         app_run_state.enable_device_opt_edit = false;
                         app_run_state.enable_proto_opt_edit = true;
                         app_run_state.is_loop_running = true;
-                        let mutex = Arc::clone(&mutex);
-
-                        let res = rfd::FileDialog::new()
-                            .set_file_name("LOGGER.txt")
-                            .set_directory(&logger_path.parent().unwrap())
-                            .set_can_create_directories(true)
-                            .save_file();
-                        // Spawn the data polling thread.
-                        if let Some(path) = res {
-                            *logger_path = path;
-                            spawn_polling_thread(device_config, mutex, &logger_path);
-                        }
-
-                        thread::spawn(move || {
-                            let server_future = run_app();
-                            rt::System::new().block_on(server_future)
+                        let device_name = &device_config_buffer.device_name;
+                        device_status.insert(device_name.clone(), DeviceStatus::default());
+                        let (sender, receiver) = unbounded_channel();
+                        *commands = Some(sender);
+                        journal.record(
+                            Event::new(
+                                EventKind::Connection,
+                                &device_config_buffer.device_name,
+                                None,
+                                "Connect requested",
+                            )
+                            .by_operator(),
+                        );
+                        // The logger, historian and HTTP server are shared by
+                        // all the devices, so they start with the first one.
+                        let services = services.get_or_insert_with(|| {
+                            let (logger_sender, receiver) = channel();
+                            spawn_logger_thread(logger, receiver, updates.sender.clone());
+                            let historian = historian
+                                .as_ref()
+                                .and_then(|historian| historian.as_ref().ok())
+                                .map(Arc::clone);
+                            let historian_sender = historian.as_ref().map(|historian| {
+                                let (sender, receiver) = channel();
+                                spawn_historian_thread(
+                                    Arc::clone(historian),
+                                    historian_config,
+                                    receiver,
+                                    updates.sender.clone(),
+                                );
+                                sender
+                            });
+                            let (server_sender, server) = channel();
+                            let (live, live_receiver) = watch::channel(Vec::new());
+                            *server_error = None;
+                            let previous = server_stopping.take();
+                            let errors = updates.sender.clone();
+                            let server_thread = thread::spawn(move || {
+                                // The port is only free once the previous server is gone.
+                                if let Some(previous) = previous {
+                                    let _ = previous.join();
+                                }
+                                let server_future =
+                                    run_app(historian, server_sender, live_receiver);
+                                if let Err(e) = rt::System::new().block_on(server_future) {
+                                    let _ = errors.send(Update::ServerError(format!(
+                                        "Could not serve on http://localhost:8080. {}",
+                                        e
+                                    )));
+                                }
+                            });
+                            Services {
+                                feed: ServiceFeed {
+                                    logger: logger_sender,
+                                    historian: historian_sender,
+                                },
+                                server,
+                                server_thread,
+                                live,
+                            }
+                        });
+                        let link = DriverLink::new(
+                            &device_config_buffer.device_name,
+                            device_config_buffer.stale_after_scans,
+                            receiver,
+                            updates.sender.clone(),
+                            services.feed.clone(),
+                        );
+                        let runtime = runtime.get_or_insert_with(|| {
+                            runtime::Builder::new_multi_thread()
+                                .enable_all()
+                                .thread_name("polling")
+                                .build()
+                                .expect("the polling runtime could not be built")
                         });
+                        spawn_polling_task(
+                            runtime,
+                            &mut device_config_buffer.device_config(protocol),
+                            tags,
+                            signals,
+                            link,
+                        );
                     }
-                    if !app_run_state.is_ui_apply_clicked {
-                    } else {
-                        if ui
+                    if ui
+                        .add_enabled(
+                            app_run_state.is_loop_running,
+                            Button::new("Disconnect").min_size(Vec2::new(100., 10.)),
+                        )
+                        .clicked()
+                    {
+                        let device_name = &device_config_buffer.device_name;
+                        if let Some(commands) = commands.take() {
+                            let _ = commands.send(DeviceCommand::Stop);
+                        }
+                        *app_run_state = AppRunState::default();
+                        device_status.remove(device_name);
+                        // The values of the device are no longer updated.
+                        let now = chrono::Local::now();
+                        for tag in tags.iter_mut().filter(|tag| tag.device == *device_name) {
+                            tag.quality = Quality::CommFail;
+                            trend_buffer.set_quality(&tag.name, now, Quality::CommFail);
+                        }
+                        if let Some(services) = services.as_ref() {
+                            services
+                                .live
+                                .send_replace(tags.iter().map(TagReading::new).collect());
+                        }
+                        alarms.update(&comm_fail_alarm(device_name), false, Severity::High);
+                        journal.record(
+                            Event::new(
+                                EventKind::Connection,
+                                device_name,
+                                None,
+                                "Disconnect requested",
+                            )
+                            .by_operator(),
+                        );
+                        // The last device takes the shared services down with it.
+                        if device_status.is_empty() {
+                            if let Some(services) = services.take() {
+                                *server_stopping = Some(services.stop());
+                            }
+                        }
+                    }
+                    let is_modbus = matches!(
+                        protocol,
+                        Protocol::ModbusTcpProtocol | Protocol::ModbusRtuProtocol
+                    );
+                    if ui
+                        .add_enabled(
+                            app_run_state.is_loop_running && is_modbus,
+                            Button::new("Read On Demand").min_size(Vec2::new(100., 10.)),
+                        )
+                        .on_hover_text("Reads the blocks of the on-demand scan class once.")
+                        .clicked()
+                    {
+                        if let Some(commands) = commands {
+                            let _ = commands.send(DeviceCommand::Scan);
+                        }
+                    }
+                    if app_run_state.is_ui_apply_clicked
+                        && ui
                             .add_enabled(
                                 app_run_state.enable_proto_opt_edit
                                     && app_run_state.is_loop_running,
@@ -729,11 +1977,13 @@ impl eframe::App for CarbonApp {
                             )
                             //.button(format!("{} Connect", egui_phosphor::regular::PLUGS))
                             .clicked()
-                        {
-                            let mutex = Arc::clone(&mutex);
-                            mutex.lock().new_config = Some(device_config_buffer.clone());
-                            app_run_state.is_ui_apply_clicked = false;
+                    {
+                        if let Some(commands) = commands {
+                            let _ = commands.send(DeviceCommand::Config(Box::new(
+                                device_config_buffer.clone(),
+                            )));
                         }
+                        app_run_state.is_ui_apply_clicked = false;
                     }
                 });
         });
@@ -749,177 +1999,116 @@ impl eframe::App for CarbonApp {
 
                 ui.separator();
                 ui.separator();
+                // Unacknowledged alarms flash at 1 Hz.
+                let blink = ui.input(|i| i.time).fract() < 0.5;
                 ui.vertical(|ui| {
-                    digital_values(ui, *digital_inputs, 0, "ESD PUSH BUTTON".to_string());
-                    digital_values(ui, *digital_inputs, 1, "TANK LVL 10%".to_string());
-                    digital_values(ui, *digital_inputs, 2, "TANK LVL 5%".to_string());
-                    digital_values(ui, *digital_inputs, 3, "PT3-1 LOW".to_string());
-                    digital_values(ui, *digital_inputs, 4, "HP1-1 MTNCE REQ".to_string());
-                    digital_values(ui, *digital_inputs, 5, "REGU FAULT HP".to_string());
-                    digital_values(ui, *digital_inputs, 6, "SCSSV PRES LOW".to_string());
-                    digital_values(ui, *digital_inputs, 7, "MV PRES LOW".to_string());
-                    digital_values(ui, *digital_inputs, 8, "ESDV PRES LOW".to_string());
-                    digital_values(ui, *digital_inputs, 9, "PT1-1 PRES HIGH".to_string());
-                    digital_values(ui, *digital_inputs, 10, "PLC-1 COM FAIL".to_string());
-                    digital_values(ui, *digital_inputs, 11, "PLC-2 COM FAIL".to_string());
-                    digital_values(ui, *digital_inputs2, 0, "ESD-1 FIRE EMG".to_string());
-                    digital_values(ui, *digital_inputs2, 1, "ESD-3 SHUTDOWN".to_string());
-                    digital_values(ui, *digital_inputs2, 2, "DIESEL LVL".to_string());
-                    digital_values(ui, *digital_inputs2, 3, "WI PUMP OFF".to_string());
-                    digital_values(ui, *digital_inputs2, 4, "WATER PUMP TEMP".to_string());
-                    digital_values(ui, *digital_inputs2, 5, "WATER TNK LVL".to_string());
-                    digital_values(ui, *digital_inputs2, 6, "CHEMICAL TNK LVL1".to_string());
-                    digital_values(ui, *digital_inputs2, 7, "CHEMICAL TNK LVL2".to_string());
-                    digital_values(ui, *digital_inputs2, 8, "CHEMICAL TNK LVL3".to_string());
-                    digital_values(ui, *digital_inputs2, 9, "CHEMICAL TNK LVL4".to_string());
-                    digital_values(ui, *digital_inputs2, 10, "DIFF PRES FILTRATION".to_string());
-                    digital_values(ui, *digital_inputs2, 11, "HIGH PRES FLOWLINE".to_string());
-                    digital_values(ui, *digital_inputs2, 12, "LOW PRES FLOWLINE".to_string());
-                    digital_values(ui, *digital_inputs2, 14, "UNHEALTHY RESET".to_string());
+                    for signal in signals.iter() {
+                        // A signal never read, or whose device is down, has no
+                        // state to show.
+                        let live = signal_states.contains_key(&signal.label)
+                            && device_status.get(&signal.device).is_some_and(|status| {
+                                matches!(
+                                    status.health.state,
+                                    LinkState::Online | LinkState::Degraded
+                                )
+                            });
+                        let state = live.then(|| alarms.state(&signal.label));
+                        let response =
+                            digital_values(ui, &signal.label, signal.severity, state, blink);
+                        if signal.command_coil().is_some()
+                            && response
+                                .interact(egui::Sense::click())
+                                .on_hover_text("Click to switch")
+                                .clicked()
+                        {
+                            *setpoint = Some(SetpointDialog::signal(signal));
+                        }
+                    }
+                    ui.separator();
+                    for device in devices.iter() {
+                        let name = comm_fail_alarm(&device.config.device_name);
+                        let state = alarms.state(&name);
+                        digital_values(ui, &name, Severity::High, Some(state), blink);
+                    }
+                    ui.separator();
+                    for tag in tags.iter() {
+                        for (kind, limit) in tag.limits.enabled() {
+                            let name = alarm_name(&tag.name, kind);
+                            let state = alarms.state(&name);
+                            digital_values(ui, &name, limit.severity, Some(state), blink);
+                        }
+                    }
                 });
             });
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.separator();
-            {
-                if let Some(data) = mutex.try_lock() {
-                    if data.data.len() > 36 {
-                        tags[0].value = u16_to_float(data.data[0], data.data[1]);
-                        tags[1].value = u16_to_float(data.data[2], data.data[3]);
-                        tags[2].value = u16_to_float(data.data[4], data.data[5]);
-                        tags[3].value = u16_to_float(data.data[6], data.data[7]);
-                        tags[4].value = u16_to_float(data.data[8], data.data[9]);
-                        tags[5].value = u16_to_float(data.data[10], data.data[11]);
-                        tags[6].value = u16_to_float(data.data[12], data.data[13]);
-                        tags[7].value = u16_to_float(data.data[14], data.data[15]);
-                        *digital_inputs = data.data[31];
-                        *digital_inputs2 = data.data[36];
-                    }
-                    // *tag1 = data.s7_read_data.tag1;
-                    // *tag2 = data.s7_read_data.tag2;
-                    // *tag3 = data.s7_read_data.tag3;
-                }
-            }
             egui::Image::new("file://background.jpg").paint_at(ui, ui.ctx().available_rect());
             // egui::Image::new(egui::include_image!("../assets/sample.png"))
             //     .paint_at(ui, ui.ctx().available_rect());
 
-            // hello_button(ui, widgets_pos, edit_pos, mutex);
-            // close_button(ui, widgets_pos, edit_pos, mutex);
-
-            // tag1_func(ui, widgets_pos, edit_pos, tag1);
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[0],
-                "%".to_string(),
-                "Hydr Oil Lvl".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[1],
-                "Barg".to_string(),
-                "WHCP Oil Pressure".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[2],
-                "Barg".to_string(),
-                "MP Pressure".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[3],
-                "Barg".to_string(),
-                "SCSSV Pressure".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[4],
-                "Barg".to_string(),
-                "MV Hydr Oil Pressure".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[5],
-                "Barg".to_string(),
-                "ESDV Hydr Oil Pressure".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[6],
-                "Barg".to_string(),
-                "Fusible Plug Hydr Oil".to_owned(),
-            );
-            tag_func(
-                ui,
-                edit_pos,
-                &mut tags[7],
-                "Barg".to_string(),
-                "ESDV Status Wtr Injection".to_owned(),
-            );
+            let blink = ui.input(|i| i.time).fract() < 0.5;
+            // A replayed log file is drawn over the mimic, the live tags
+            // keep their values.
+            let replayed = match replay {
+                Some(Ok(replay)) if *replay_window && replay.on_mimic => Some(&*replay),
+                _ => None,
+            };
+            for tag in tags.iter_mut() {
+                if let Some(replay) = replayed {
+                    let mut shown = tag.clone();
+                    replay.apply(&mut shown);
+                    tag_func(ui, edit_pos, &mut shown, None, blink);
+                    tag.pos = shown.pos;
+                    continue;
+                }
+                // The tag takes the colour of its most severe limit alarm.
+                let alarm = tag
+                    .limits
+                    .enabled()
+                    .map(|(kind, limit)| {
+                        (limit.severity, alarms.state(&alarm_name(&tag.name, kind)))
+                    })
+                    .filter(|(_, state)| *state != AlarmState::Normal)
+                    .max_by_key(|(severity, _)| *severity);
+                if tag_func(ui, edit_pos, tag, alarm, blink) && tag.write.enabled {
+                    *setpoint = Some(SetpointDialog::tag(tag));
+                }
+            }
         });
     }
 }
 
-fn tag1_func(ui: &mut egui::Ui, widgets_pos: &mut WidgetsPos, edit_pos: &mut bool, tag1: &mut f32) {
-    ui.put(
-        egui::Rect {
-            min: Pos2::new(widgets_pos.tag1_pos.x, widgets_pos.tag1_pos.y - 40.),
-            max: Pos2::new(widgets_pos.tag1_pos.x + 150., widgets_pos.tag1_pos.y + 30.),
-        },
-        Label::new(
-            RichText::new(format!("  PIT 0001  "))
-                .size(14.)
-                .strong()
-                .color(Color32::BLACK)
-                .background_color(Color32::GRAY),
-        )
-        .sense(egui::Sense {
-            click: true,
-            drag: *edit_pos,
-            focusable: true,
-        }),
-    );
-    let tag1_widget = ui.put(
-        egui::Rect {
-            min: widgets_pos.tag1_pos,
-            max: Pos2::new(widgets_pos.tag1_pos.x + 150., widgets_pos.tag1_pos.y + 30.),
-        },
-        Label::new(
-            RichText::new(format!("  {:.02} barg  ", tag1))
-                .size(14.)
-                .strong()
-                .color(Color32::WHITE)
-                .background_color(Color32::BLACK),
-        )
-        .sense(egui::Sense {
-            click: true,
-            drag: *edit_pos,
-            focusable: true,
-        }),
-    );
-
-    if tag1_widget.dragged() {
-        let delta = tag1_widget.drag_delta();
-        widgets_pos.tag1_pos.x += delta.x;
-        widgets_pos.tag1_pos.y += delta.y;
-    }
-}
-
-fn tag_func(ui: &mut egui::Ui, edit_pos: &mut bool, tag: &mut Tag, unit: String, desc: String) {
+fn tag_func(
+    ui: &mut egui::Ui,
+    edit_pos: &mut bool,
+    tag: &mut Tag,
+    alarm: Option<(Severity, AlarmState)>,
+    blink: bool,
+) -> bool {
+    let (text_color, background_color) = match alarm {
+        None | Some((_, AlarmState::Normal)) => (Color32::WHITE, Color32::BLACK),
+        Some((_, AlarmState::ActiveUnacked)) if blink => (Color32::WHITE, Color32::BLACK),
+        Some((severity, AlarmState::ActiveUnacked | AlarmState::ActiveAcked)) => {
+            (Color32::WHITE, severity.color())
+        }
+        Some((severity, AlarmState::ClearedUnacked)) => (severity.color(), Color32::BLACK),
+    };
+    let mut value_text = RichText::new(format!("  {}  {}   ", tag.display_value(), tag.unit))
+        .size(14.)
+        .strong()
+        .background_color(background_color);
+    // A value that isn't good is drawn in the colour of its quality.
+    value_text = match tag.quality.color() {
+        Some(color) => value_text.color(color).italics(),
+        None => value_text.color(text_color),
+    };
     ui.put(
         egui::Rect {
             min: Pos2::new(tag.pos.x, tag.pos.y - 45.),
             max: Pos2::new(tag.pos.x + 150., tag.pos.y + 0.),
         },
         Label::new(
-            RichText::new(format!("{}", desc))
+            RichText::new(&tag.description)
                 .size(12.)
                 .color(Color32::BLACK)
                 .background_color(Color32::GRAY),
@@ -954,14 +2143,7 @@ fn tag_func(ui: &mut egui::Ui, edit_pos: &mut bool, tag: &mut Tag, unit: String,
             min: tag.pos,
             max: Pos2::new(tag.pos.x + 150., tag.pos.y + 30.),
         },
-        Label::new(
-            RichText::new(format!("  {:.02}  {}   ", tag.value, unit))
-                .size(14.)
-                .strong()
-                .color(Color32::WHITE)
-                .background_color(Color32::BLACK),
-        )
-        .sense(egui::Sense {
+        Label::new(value_text).sense(egui::Sense {
             click: true,
             drag: *edit_pos,
             focusable: true,
@@ -979,202 +2161,56 @@ fn tag_func(ui: &mut egui::Ui, edit_pos: &mut bool, tag: &mut Tag, unit: String,
         tag.pos.x += delta.x;
         tag.pos.y += delta.y;
     }
-}
-fn digital_values(ui: &mut egui::Ui, reg: u16, bit: usize, label: String) {
-    if check_bit(reg, bit) {
-        ui.add(Label::new(
-            RichText::new(format!("  {}  ", label))
-                .size(12.)
-                .strong()
-                .color(Color32::GRAY),
-        ));
-    } else {
-        ui.add(Label::new(
-            RichText::new(format!("  {}  ", label))
-                .size(12.)
-                .strong()
-                .color(Color32::WHITE)
-                .background_color(Color32::DARK_RED),
+    let mut hover = format!("{}", tag.quality);
+    if let Some(timestamp) = tag.timestamp {
+        hover.push_str(&format!(
+            ", read at {}",
+            timestamp.format("%d/%m/%Y %H:%M:%S")
         ));
     }
+    // A click on a writable value opens its setpoint dialog.
+    if tag.write.enabled && !*edit_pos {
+        hover.push_str("\nClick to write a setpoint");
+    }
+    let tag1_widget = tag1_widget.on_hover_text(hover);
+    tag1_widget.clicked() && !*edit_pos
 }
-
-fn check_bit(value: u16, n: usize) -> bool {
-    if n < 16 {
-        value & (1 << n) != 0
-    } else {
-        false
-    }
-}
-fn hello_button(
-    ui: &mut egui::Ui,
-    widgets_pos: &mut WidgetsPos,
-    edit_pos: &mut bool,
-    mutex: &mut Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, MutexData>>,
-) {
-    let hello_button = ui.put(
-        egui::Rect {
-            min: widgets_pos.hello_button_pos,
-            max: Pos2::new(
-                widgets_pos.hello_button_pos.x + 100.,
-                widgets_pos.hello_button_pos.y + 30.,
-            ),
-        },
-        Button::new("Open").fill(Color32::GREEN).sense(egui::Sense {
-            click: !*edit_pos,
-            drag: *edit_pos,
-            focusable: true,
-        }),
-    );
-
-    if hello_button.dragged() {
-        let delta = hello_button.drag_delta();
-
-        widgets_pos.hello_button_pos.x += delta.x;
-        widgets_pos.hello_button_pos.y += delta.y;
-    }
-
-    if hello_button.clicked() {
-        if let Some(mut data) = mutex.try_lock() {
-            data.s7_message = Some(S7MessageTag {
-                message: S7Message::S7Bool(true),
-                db: DB,
-                offset: 2.1,
-            });
-        }
-    }
-}
-fn close_button(
+/// Shows an alarm of the panel. `None` is a signal without data, which
+/// mustn't pass for a healthy one: it keeps the colour of its severity, struck through.
+fn digital_values(
     ui: &mut egui::Ui,
-    widgets_pos: &mut WidgetsPos,
-    edit_pos: &mut bool,
-    mutex: &mut Arc<parking_lot::lock_api::Mutex<parking_lot::RawMutex, MutexData>>,
-) {
-    let close_button = ui.put(
-        egui::Rect {
-            min: widgets_pos.close_button_pos,
-            max: Pos2::new(
-                widgets_pos.close_button_pos.x + 100.,
-                widgets_pos.close_button_pos.y + 30.,
-            ),
-        },
-        Button::new("Close").fill(Color32::RED).sense(egui::Sense {
-            click: !*edit_pos,
-            drag: *edit_pos,
-            focusable: true,
-        }),
-    );
-
-    if close_button.dragged() {
-        let delta = close_button.drag_delta();
-
-        widgets_pos.close_button_pos.x += delta.x;
-        widgets_pos.close_button_pos.y += delta.y;
-    }
-
-    if close_button.clicked() {
-        if let Some(mut data) = mutex.try_lock() {
-            data.s7_message = Some(S7MessageTag {
-                message: S7Message::S7Bool(true),
-                db: DB,
-                offset: 2.1,
-            });
-        }
+    label: &str,
+    severity: Severity,
+    state: Option<AlarmState>,
+    blink: bool,
+) -> egui::Response {
+    let text = RichText::new(format!("  {}  ", label)).size(12.).strong();
+    let text = match state {
+        None => text.color(severity.color()).strikethrough(),
+        Some(AlarmState::Normal) => text.color(Color32::GRAY),
+        Some(AlarmState::ActiveUnacked) if blink => text.color(Color32::GRAY),
+        Some(AlarmState::ActiveUnacked | AlarmState::ActiveAcked) => text
+            .color(Color32::WHITE)
+            .background_color(severity.color()),
+        Some(AlarmState::ClearedUnacked) => text.color(severity.color()),
+    };
+    let response = ui.add(Label::new(text));
+    if state.is_none() {
+        response.on_hover_text("No data")
+    } else {
+        response
     }
 }
 
-fn modbus_request_details_ui(
-    ui: &mut egui::Ui,
-    modbus_protocol_definitions: &mut ModbusDefinitions,
-) -> egui::InnerResponse<()> {
-    ui.horizontal(|ui| {
-        let mut modbus_request_vec = Vec::new();
-        ui.label("Request:");
-        let mut modbus_request = ModbusRequest::new(1, ModbusProto::TcpUdp);
-        match modbus_protocol_definitions.register_type {
-            RegisterType::Holding => {
-                if modbus_request
-                    .generate_get_holdings(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
-            }
-            RegisterType::Inputs => {
-                if modbus_request
-                    .generate_get_inputs(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
-            }
-            RegisterType::Coils => {
-                if modbus_request
-                    .generate_get_coils(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
-            }
-        }
-        for i in 0..modbus_request_vec.len() {
-            ui.label(format!("{:02X}", modbus_request_vec[i]));
-        }
-        modbus_protocol_definitions.request_function_vec = modbus_request_vec;
-    })
-}
-
-fn _modbus_serial_request_details_ui(
-    ui: &mut egui::Ui,
-    modbus_protocol_definitions: &mut ModbusDefinitions,
-) -> egui::InnerResponse<()> {
-    ui.horizontal(|ui| {
-        let mut modbus_request_vec = Vec::new();
-        ui.label("Request:");
-        let mut modbus_request = ModbusRequest::new(1, ModbusProto::TcpUdp);
-        match modbus_protocol_definitions.register_type {
-            RegisterType::Holding => {
-                if modbus_request
-                    .generate_get_holdings(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
-            }
-            RegisterType::Inputs => {
-                if modbus_request
-                    .generate_get_inputs(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
-            }
-            RegisterType::Coils => {
-                if modbus_request
-                    .generate_get_coils(
-                        modbus_protocol_definitions.start_address,
-                        modbus_protocol_definitions.register_count,
-                        &mut modbus_request_vec,
-                    )
-                    .is_ok()
-                {}
+fn modbus_request_details_ui(ui: &mut egui::Ui, blocks: &[ReadBlock]) {
+    for block in blocks {
+        ui.horizontal(|ui| {
+            ui.label("Request:");
+            for byte in block.request() {
+                ui.label(format!("{:02X}", byte));
             }
-        }
-        for i in 0..modbus_request_vec.len() {
-            ui.label(format!("{:02X}", modbus_request_vec[i]));
-        }
-        modbus_protocol_definitions.request_function_vec = modbus_request_vec;
-    })
+        });
+    }
 }
 
 fn modbus_serial_device_ui(device_config_buffer: &mut DeviceConfigUiBuffer, ui: &mut egui::Ui) {
@@ -1186,7 +2222,7 @@ fn modbus_serial_device_ui(device_config_buffer: &mut DeviceConfigUiBuffer, ui:
                     ui.selectable_value(
                         &mut device_config_buffer.modbus_serial_buffer.port,
                         port.clone().port_name,
-                        format!("{}", port.port_name),
+                        &port.port_name,
                     );
                 }
             }
@@ -1249,34 +2285,40 @@ fn modbus_serial_device_ui(device_config_buffer: &mut DeviceConfigUiBuffer, ui:
     }
 }
 
-fn modbus_protocol_ui(modbus_protocol_definitions: &mut ModbusDefinitions, ui: &mut egui::Ui) {
-    ComboBox::from_label("Register Type")
-        .selected_text(format!("{}", modbus_protocol_definitions.register_type))
-        .show_ui(ui, |ui| {
-            ui.selectable_value(
-                &mut modbus_protocol_definitions.register_type,
-                RegisterType::Coils,
-                "Coils",
-            );
-            ui.selectable_value(
-                &mut modbus_protocol_definitions.register_type,
-                RegisterType::Inputs,
-                "Input registers",
-            );
-            ui.selectable_value(
-                &mut modbus_protocol_definitions.register_type,
-                RegisterType::Holding,
-                "Holding registers",
-            );
-        });
-
+fn modbus_protocol_ui(
+    modbus_protocol_definitions: &mut ModbusDefinitions,
+    generated: &[ReadBlock],
+    ui: &mut egui::Ui,
+) {
+    ui.checkbox(
+        &mut modbus_protocol_definitions.auto_blocks,
+        "Generate the read blocks from the tag database",
+    );
+    if modbus_protocol_definitions.auto_blocks {
+        ui.add(
+            Slider::new(&mut modbus_protocol_definitions.max_gap, 0..=100)
+                .text("Max Gap (addresses)"),
+        );
+        for block in generated {
+            ui.label(format!("{}  ({})", block, block.scan_class));
+        }
+        if generated.is_empty() {
+            ui.colored_label(Color32::GRAY, "No tag or signal uses this device.");
+        }
+    } else {
+        blocks_ui(ui, &mut modbus_protocol_definitions.blocks);
+    }
+    ui.add(
+        Slider::new(&mut modbus_protocol_definitions.fast_period, 50..=2000)
+            .text("Fast Scan Period (ms)"),
+    );
     ui.add(
-        Slider::new(&mut modbus_protocol_definitions.start_address, 0..=9999).text("Start Address"),
+        Slider::new(&mut modbus_protocol_definitions.scan_period, 200..=10000)
+            .text("Normal Scan Period (ms)"),
     );
-    ui.add(Slider::new(&mut modbus_protocol_definitions.register_count, 1..=1000).text("Quantity"));
     ui.add(
-        Slider::new(&mut modbus_protocol_definitions.scan_delay, 200..=10000)
-            .text("Scan Delay (ms)"),
+        Slider::new(&mut modbus_protocol_definitions.slow_period, 1000..=60000)
+            .text("Slow Scan Period (ms)"),
     );
 }
 
@@ -1289,319 +2331,789 @@ fn modbus_tcp_device_ui(ui: &mut egui::Ui, device_config_buffer: &mut DeviceConf
     ui.add(Slider::new(&mut device_config_buffer.modbus_tcp_buffer.port, 0..=10000).text("Port"));
 }
 
+fn datascan_device_ui(ui: &mut egui::Ui, device_config_buffer: &mut DeviceConfigUiBuffer) {
+    let config = &mut device_config_buffer.datascan_buffer;
+    ui.colored_label(
+        Color32::DARK_RED,
+        "The Datascan framing isn't verified against real modules yet.",
+    )
+    .on_hover_text("Only the simulator is known to answer it.");
+    // No serial port is offered, so the stand-in frames never reach a real bus.
+    ComboBox::from_label(format!("{} Port", egui_phosphor::regular::USB))
+        .selected_text(config.port.clone())
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut config.port, SIMULATOR_PORT.to_string(), SIMULATOR_PORT);
+        });
+    ComboBox::from_label("Baudrate")
+        .selected_text(format!("{}", config.baudrate))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut config.baudrate, Baudrate::Baud38400, "38400");
+            ui.selectable_value(&mut config.baudrate, Baudrate::Baud9600, "9600");
+        });
+    ComboBox::from_label("Parity")
+        .selected_text(format!("{}", config.parity))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut config.parity, Parity::Even, "Even");
+            ui.selectable_value(&mut config.parity, Parity::Odd, "Odd");
+            ui.selectable_value(&mut config.parity, Parity::NoneParity, "None");
+        });
+    ui.add(
+        egui::DragValue::new(&mut config.timeout)
+            .clamp_range(100..=60_000)
+            .prefix("Timeout: ")
+            .suffix(" ms"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut config.scan_period)
+            .clamp_range(10..=60_000)
+            .prefix("Scan period: ")
+            .suffix(" ms"),
+    );
+}
+
+fn ethernet_ip_device_ui(ui: &mut egui::Ui, device_config_buffer: &mut DeviceConfigUiBuffer) {
+    let config = &mut device_config_buffer.ethernet_ip_buffer;
+    ui.label("Controller IP Address");
+    ui.add(egui::TextEdit::singleline(&mut config.ip).desired_width(120.));
+    ui.add(Slider::new(&mut config.slot, 0..=16).text("Backplane Slot"));
+    ui.add(
+        egui::DragValue::new(&mut config.timeout)
+            .clamp_range(100..=60_000)
+            .prefix("Timeout: ")
+            .suffix(" ms"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut config.scan_period)
+            .clamp_range(10..=60_000)
+            .prefix("Scan period: ")
+            .suffix(" ms"),
+    );
+}
+
 fn s7_device_ui(ui: &mut egui::Ui, device_config_buffer: &mut DeviceConfigUiBuffer) {
+    let config = &mut device_config_buffer.s7_buffer;
     ui.label("PLC IP Address");
-    ui.add(egui::TextEdit::singleline(&mut device_config_buffer.s7_buffer.ip).desired_width(120.));
+    ui.add(egui::TextEdit::singleline(&mut config.ip).desired_width(120.));
+    ui.horizontal(|ui| {
+        for (family, rack, slot) in S7_FAMILIES {
+            if ui
+                .selectable_label(config.rack == rack && config.slot == slot, family)
+                .on_hover_text(format!("Rack {}, slot {}", rack, slot))
+                .clicked()
+            {
+                config.rack = rack;
+                config.slot = slot;
+            }
+        }
+    });
+    ui.add(Slider::new(&mut config.rack, 0..=7).text("Rack"));
+    ui.add(Slider::new(&mut config.slot, 0..=31).text("Slot"));
+    ComboBox::from_label("Connection Type")
+        .selected_text(format!("{}", config.connection_type))
+        .show_ui(ui, |ui| {
+            for connection_type in [
+                S7ConnectionType::PG,
+                S7ConnectionType::OP,
+                S7ConnectionType::S7Basic,
+            ] {
+                ui.selectable_value(
+                    &mut config.connection_type,
+                    connection_type,
+                    format!("{}", connection_type),
+                );
+            }
+        });
+    ui.add(
+        egui::DragValue::new(&mut config.timeout)
+            .clamp_range(100..=60_000)
+            .prefix("Timeout: ")
+            .suffix(" ms"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut config.scan_period)
+            .clamp_range(10..=60_000)
+            .prefix("Scan period: ")
+            .suffix(" ms"),
+    );
+}
+/// Writes the latest tag values to the log files at the logger's own interval.
+/// The thread ends once the app drops its end of `commands`.
+fn spawn_logger_thread(
+    config: &LoggerConfig,
+    commands: Receiver<ServiceCommand<LoggerConfig>>,
+    updates: Sender<Update>,
+) {
+    let mut logger = Logger::new(config.clone());
+    thread::spawn(move || {
+        let mut next_row = Instant::now();
+        let mut samples = HashMap::new();
+        let mut sampled_at = None;
+        let mut last_sample = None;
+        loop {
+            match commands.recv_timeout(next_row.saturating_duration_since(Instant::now())) {
+                Ok(ServiceCommand::Config(config)) => logger.set_config(config),
+                Ok(ServiceCommand::Snapshot(snapshot)) => {
+                    sampled_at = Some(snapshot.timestamp);
+                    samples.extend(snapshot.samples);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            let now = Instant::now();
+            if now < next_row {
+                continue;
+            }
+            next_row = (next_row + Duration::from_millis(logger.config().interval)).max(now);
+
+            // A row is written for each new scan result. The values of failed
+            // scans are logged with their quality.
+            if !logger.config().enabled || sampled_at == last_sample {
+                continue;
+            }
+            if let Some(time) = sampled_at {
+                last_sample = sampled_at;
+                logger.write(time, &samples);
+                let _ = updates.send(Update::LoggerStatus(logger.status.clone()));
+            }
+        }
+    });
 }
-fn spawn_polling_thread(
+
+/// Records the latest tag values in the historian and applies its retention.
+/// The thread ends once the app drops its end of `commands`.
+fn spawn_historian_thread(
+    historian: Arc<Historian>,
+    config: &HistorianConfig,
+    commands: Receiver<ServiceCommand<HistorianConfig>>,
+    updates: Sender<Update>,
+) {
+    let mut config = config.clone();
+    thread::spawn(move || {
+        let mut next_sample = Instant::now();
+        let mut next_purge = Instant::now();
+        let mut samples = HashMap::new();
+        let mut sampled_at = None;
+        let mut last_sample = None;
+        loop {
+            match commands.recv_timeout(next_sample.saturating_duration_since(Instant::now())) {
+                Ok(ServiceCommand::Config(new_config)) => {
+                    config = new_config;
+                    // A shorter retention applies right away.
+                    next_purge = Instant::now();
+                }
+                Ok(ServiceCommand::Snapshot(snapshot)) => {
+                    sampled_at = Some(snapshot.timestamp);
+                    samples.extend(snapshot.samples);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            let now = Instant::now();
+            if now < next_sample {
+                continue;
+            }
+            next_sample = (next_sample + Duration::from_millis(config.interval)).max(now);
+
+            let fresh = config.enabled && sampled_at != last_sample;
+            let mut result = Ok(());
+            if let (true, Some(time)) = (fresh, sampled_at) {
+                last_sample = sampled_at;
+                // Only good values are recorded.
+                result = historian.record(time, &good_values(&samples));
+            }
+            if result.is_ok() && Instant::now() >= next_purge {
+                next_purge = Instant::now() + Duration::from_secs(3600);
+                result = historian.purge(chrono::Local::now(), &config);
+            }
+            if fresh || result.is_err() {
+                let _ = updates.send(Update::HistorianError(result.err()));
+            }
+        }
+    });
+}
+
+/// The answer to the read request of a Modbus device.
+enum BlockData {
+    Registers(Vec<u16>),
+    Bits(Vec<bool>),
+}
+
+/// Gives up on a Modbus request after `timeout`.
+async fn within<T>(
+    timeout: Duration,
+    request: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    tokio::time::timeout(timeout, request)
+        .await
+        .unwrap_or_else(|elapsed| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, elapsed)))
+}
+
+/// Tells an exception, which the device answered, from a transport error.
+/// tokio-modbus hands exceptions over as the only `Other` errors that wrap
+/// an error of their own, as its exception type isn't public.
+fn modbus_failure(error: &std::io::Error) -> Failure {
+    match (error.kind(), error.get_ref()) {
+        (std::io::ErrorKind::Other, Some(_)) => Failure::Exception,
+        (kind, _) => kind.into(),
+    }
+}
+
+/// Reads the blocks one request each. The scan fails with the first block
+/// the device doesn't answer.
+async fn read_blocks(
+    ctx: &mut client::Context,
+    blocks: &[ReadBlock],
+    timeout: Duration,
+) -> Result<Vec<(ReadBlock, BlockData)>, ScanError> {
+    let mut answers = Vec::new();
+    for block in blocks {
+        let (start, count) = (block.start_address, block.count);
+        let data = match block.register_type {
+            RegisterType::Coils => within(timeout, ctx.read_coils(start, count))
+                .await
+                .map(BlockData::Bits),
+            RegisterType::DiscreteInputs => within(timeout, ctx.read_discrete_inputs(start, count))
+                .await
+                .map(BlockData::Bits),
+            RegisterType::Inputs => within(timeout, ctx.read_input_registers(start, count))
+                .await
+                .map(BlockData::Registers),
+            RegisterType::Holding => within(timeout, ctx.read_holding_registers(start, count))
+                .await
+                .map(BlockData::Registers),
+        };
+        match data {
+            Ok(data) => answers.push((block.clone(), data)),
+            Err(e) => {
+                return Err(ScanError::new(
+                    modbus_failure(&e),
+                    format!("{}: {}", block, e),
+                ))
+            }
+        }
+    }
+    Ok(answers)
+}
+
+/// How a poll loop ended.
+enum PollEnd {
+    Stopped,
+    LinkLost,
+}
+
+/// Polls the read blocks of a Modbus device until the link is lost or the
+/// device is stopped. Each scan class is scanned at its own fixed rate, and
+/// the on-demand one when the operator asks for it.
+async fn poll_modbus(
+    ctx: &mut client::Context,
+    link: &mut DriverLink,
+    definitions: &mut ModbusDefinitions,
+    edited: fn(DeviceConfigUiBuffer) -> ModbusDefinitions,
+    tags: &mut Vec<Tag>,
+    signals: &mut Vec<DigitalSignal>,
+    timeout: Duration,
+) -> PollEnd {
+    let mut plan = definitions.scan_plan(tags, signals, &link.device);
+    loop {
+        let due = tokio::select! {
+            _ = link.receive_command() => None,
+            index = plan.scheduler.tick() => Some(index),
+        };
+        let pending = link.take_pending();
+        let mut edited_blocks = false;
+        // We check for any pending new modbus configuration
+        if let Some(new_config) = pending.config {
+            *definitions = edited(new_config);
+            edited_blocks = true;
+        }
+        // We check for an edited tag database, which the blocks may be generated from
+        if let Some(new_tags) = pending.tags {
+            *tags = new_tags;
+            edited_blocks = true;
+        }
+        if let Some(new_signals) = pending.signals {
+            *signals = new_signals;
+            edited_blocks = true;
+        }
+        // We check for a pending stop request
+        if pending.stop {
+            // Releases the serial port or socket.
+            let _ = ctx.disconnect().await;
+            return PollEnd::Stopped;
+        }
+
+        for request in pending.writes {
+            let result =
+                tokio::time::timeout(timeout, request.command.send(&request.source, tags, ctx))
+                    .await
+                    .unwrap_or_else(|elapsed| Err(elapsed.to_string()));
+            link.report_write(request, result);
+        }
+
+        // Editing a description or position leaves the blocks as they are,
+        // so the classes keep their due times.
+        let mut rescheduled = false;
+        if edited_blocks {
+            rescheduled = plan.update(definitions.scan_plan(tags, signals, &link.device));
+        }
+        // The reads asked for by the operator and the scan class due.
+        let mut scans = Vec::new();
+        if pending.scan && !plan.on_demand.is_empty() {
+            scans.push(&plan.on_demand);
+        }
+        if let (Some(index), false) = (due, rescheduled) {
+            scans.push(&plan.blocks[index]);
+        }
+
+        for blocks in scans {
+            let now = Instant::now();
+            match read_blocks(ctx, blocks, timeout).await {
+                Ok(blocks) => {
+                    let elapsed_time = now.elapsed().as_micros();
+                    let snapshot = decode_blocks(blocks, tags, signals, &link.device);
+                    link.publish(snapshot, elapsed_time, &plan.scheduler.stats);
+                }
+                Err(e) => {
+                    let error_code = 2;
+                    let error_msg = format!("{:#02x}: Could not read registers. {}", error_code, e);
+                    if link.scan_failed(e.failure, error_msg) {
+                        return PollEnd::LinkLost;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Starts polling a device on the shared runtime.
+fn spawn_polling_task(
+    runtime: &Runtime,
     device_config: &mut DeviceConfig,
-    mutex: Arc<Mutex<MutexData>>,
-    logger_path: &PathBuf,
+    tags: &[Tag],
+    signals: &[DigitalSignal],
+    mut link: DriverLink,
 ) {
-    let paths = std::fs::read_dir(".").unwrap();
-
-    let matches = paths
-        .map(|path| path.unwrap())
-        .filter(|path| path.metadata().unwrap().len() < 100_000)
-        .filter(|path| path.file_name().is_ascii().to_string().starts_with("LOG"));
-    let mut logger = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(logger_path)
-        .unwrap();
+    let device_name = link.device.clone();
+    let mut tags = tags.to_vec();
+    let mut signals = signals.to_vec();
 
     match device_config {
         DeviceConfig::ModbusSerial(config) => {
-            let baudrate_match = match config.baudrate {
-                Baudrate::Baud38400 => 38400,
-                Baudrate::Baud9600 => 9600,
-            };
-            let parity = match config.parity {
-                Parity::Even => serialport::Parity::Even,
-                Parity::Odd => serialport::Parity::Odd,
-                Parity::NoneParity => serialport::Parity::None,
-            };
-
-            //spawn_serial_polling_thread(, , , , , )
+            let baudrate_match = config.baudrate.bauds();
+            let parity = config.parity.serial();
             let mut config = config.clone();
-            thread::spawn(move || {
-                let serial = serialport::new(config.port, baudrate_match)
-                    .parity(parity)
-                    .timeout(Duration::from_millis(1500));
-                let ctx = connect_slave(&serial, Slave(config.slave));
-                if let Ok(mut ctx) = ctx {
-                    loop {
-                        thread::sleep(Duration::from_millis(
-                            config.protocol_definitions.scan_delay,
-                        ));
-                        if let Some(mut mutex) = mutex.try_lock() {
-                            // We check for any pending new modbus configuration
-                            if let Some(new_config) = mutex.new_config.clone() {
-                                // We update the modbus config
-                                config.protocol_definitions =
-                                    new_config.modbus_serial_buffer.protocol_definitions;
-
-                                // We clean the mutex
-                                mutex.new_config = None;
-                            }
-
-                            // We check for a pending thread kill request
-                            if mutex.kill_thread {
-                                // We clean the mutex
-                                mutex.kill_thread = false;
-
-                                // We return from the thread
+            runtime.spawn(async move {
+                let timeout = Duration::from_millis(1500);
+                loop {
+                    link.connecting();
+                    let serial = serialport::new(&config.port, baudrate_match)
+                        .parity(parity)
+                        .timeout(timeout);
+                    match SerialStream::open(&serial) {
+                        Ok(port) => {
+                            let mut ctx =
+                                tokio_modbus::client::rtu::attach_slave(port, Slave(config.slave));
+                            link.connected();
+                            let end = poll_modbus(
+                                &mut ctx,
+                                &mut link,
+                                &mut config.protocol_definitions,
+                                |config| config.modbus_serial_buffer.protocol_definitions,
+                                &mut tags,
+                                &mut signals,
+                                timeout,
+                            )
+                            .await;
+                            if let PollEnd::Stopped = end {
                                 return;
                             }
                         }
-
-                        match config.protocol_definitions.register_type {
-                            RegisterType::Coils => {}
-                            RegisterType::Inputs => {
-                                let now = Instant::now();
-                                let result = ctx.read_input_registers(
-                                    config.protocol_definitions.start_address,
-                                    config.protocol_definitions.register_count,
-                                );
-                                if let Ok(res) = result {
-                                    let elapsed_time = now.elapsed().as_micros();
-                                    let mut data = mutex.lock();
-                                    data.data = res;
-                                    data.achieved_scan_time = elapsed_time;
-                                }
-                            }
-                            RegisterType::Holding => {
-                                let now = Instant::now();
-                                let result = ctx.read_holding_registers(
-                                    config.protocol_definitions.start_address,
-                                    config.protocol_definitions.register_count,
-                                );
-                                if let Ok(res) = result {
-                                    let elapsed_time = now.elapsed().as_micros();
-                                    let mut log = true;
-                                    {
-                                        let mut data = mutex.lock();
-                                        data.data = res.clone();
-                                        data.achieved_scan_time = elapsed_time;
-                                        log = data.log;
-                                    }
-                                }
-                            }
+                        Err(e) => {
+                            let error_code = 1;
+                            let error_msg = format!(
+                                "{:#02x}: Could not open the serial port. {}",
+                                error_code, e
+                            );
+                            link.connect_failed(error_msg);
                         }
                     }
+                    if !link.wait_to_reconnect().await {
+                        return;
+                    }
                 }
             });
         }
         DeviceConfig::S7(s7_config) => {
-            let s7_config = s7_config.clone();
-
-            if let Ok(addr) = s7_config.ip.parse::<Ipv4Addr>() {
-                thread::spawn(move || {
-                    //let addr = Ipv4Addr::new(127, 0, 0, 1);
-                    let mut opts = tcp::Options::new(IpAddr::from(addr), 5, 5, Connection::PG);
-                    opts.read_timeout = Duration::from_secs(5);
-                    opts.write_timeout = Duration::from_secs(5);
-
-                    if let Ok(t) = tcp::Transport::connect(opts) {
-                        let mut cl = Client::new(t).unwrap();
-                        let offset1 = 4.0;
-                        let offset2 = 8.0;
-                        let db = DB;
-
-                        loop {
-                            thread::sleep(Duration::from_millis(1000));
-
-                            let mut s7_message = None;
-                            let mut buffer1 = vec![0u8; Float::size() as usize];
-                            let mut buffer2 = vec![0u8; Float::size() as usize];
-                            let now = Instant::now();
-                            cl.ag_read(db, offset1 as i32, Float::size(), buffer1.as_mut())
-                                .unwrap();
-                            cl.ag_read(db, offset2 as i32, Float::size(), buffer2.as_mut())
-                                .unwrap();
-
-                            let elapsed_time = now.elapsed().as_micros();
-                            let tag1 = Float::new(db, offset1, buffer1).unwrap();
-                            let value1: f32 = tag1.value();
-
-                            let tag2 = Float::new(db, offset2, buffer2).unwrap();
-                            let value2: f32 = tag2.value();
+            let mut s7_config = s7_config.clone();
+            runtime.spawn(async move {
+                let Ok(addr) = s7_config.ip.parse::<Ipv4Addr>() else {
+                    let error_code = 3;
+                    let error_msg = format!(
+                        "{:#02x}: \"{}\" is not an IP address.",
+                        error_code, s7_config.ip
+                    );
+                    link.connect_failed(error_msg);
+                    return;
+                };
+                let mut opts = tcp::Options::new(
+                    IpAddr::from(addr),
+                    s7_config.rack,
+                    s7_config.slot,
+                    s7_config.connection_type.connection(),
+                );
+                opts.read_timeout = Duration::from_millis(s7_config.timeout);
+                opts.write_timeout = Duration::from_millis(s7_config.timeout);
+
+                loop {
+                    link.connecting();
+                    // The S7 client blocks, so it runs off the async workers.
+                    let connection = block_in_place(|| {
+                        tcp::Transport::connect(opts.clone()).and_then(|mut transport| {
+                            transport.negotiate()?;
+                            Ok(transport)
+                        })
+                    });
+                    let mut transport = match connection {
+                        Ok(transport) => transport,
+                        Err(e) => {
+                            let error_code = 1;
+                            let error_msg =
+                                format!("{:#02x}: Could not connect to the PLC. {}", error_code, e);
+                            link.connect_failed(error_msg);
+                            if !link.wait_to_reconnect().await {
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    link.connected();
 
-                            {
-                                let mut data = mutex.lock();
-                                data.s7_read_data.tag1 = value1;
-                                data.s7_read_data.tag2 = value2;
-                                s7_message = data.s7_message.clone();
-                                data.achieved_scan_time = elapsed_time;
-                                data.s7_message = None;
+                    let mut scheduler = Scheduler::new(&[(
+                        ScanClass::Normal,
+                        Duration::from_millis(s7_config.scan_period),
+                    )]);
+                    loop {
+                        let due = tokio::select! {
+                            _ = link.receive_command() => false,
+                            _ = scheduler.tick() => true,
+                        };
+                        let pending = link.take_pending();
+                        // We check for an edited S7 tag list and scan period
+                        if let Some(new_config) = pending.config {
+                            s7_config.tags = new_config.s7_buffer.tags;
+                            if s7_config.scan_period != new_config.s7_buffer.scan_period {
+                                s7_config.scan_period = new_config.s7_buffer.scan_period;
+                                scheduler = Scheduler::new(&[(
+                                    ScanClass::Normal,
+                                    Duration::from_millis(s7_config.scan_period),
+                                )]);
                             }
+                        }
+                        // We check for an edited tag database
+                        if let Some(new_tags) = pending.tags {
+                            tags = new_tags;
+                        }
+                        // We check for a pending stop request
+                        if pending.stop {
+                            return;
+                        }
+
+                        for request in pending.writes {
+                            let result = match &request.command {
+                                WriteCommand::Setpoint(value) => block_in_place(|| {
+                                    write_s7_tag(
+                                        &mut transport,
+                                        &s7_config.tags,
+                                        &request.source,
+                                        value,
+                                    )
+                                }),
+                                WriteCommand::Coil { .. } => {
+                                    Err("Coils only exist on Modbus devices.".to_string())
+                                }
+                            };
+                            link.report_write(request, result);
+                        }
+                        if !due {
+                            continue;
+                        }
 
-                            if let Some(msg) = s7_message {
-                                match msg.message {
-                                    S7Message::S7Bool(value) => {
-                                        let mut buffer = vec![0u8; Bool::size() as usize];
-                                        if cl
-                                            .ag_read(
-                                                msg.db,
-                                                msg.offset as i32,
-                                                Bool::size(),
-                                                &mut buffer,
-                                            )
-                                            .is_ok()
-                                        {
-                                            let mut v =
-                                                Bool::new(msg.db, msg.offset, buffer.to_vec())
-                                                    .unwrap();
-                                            v.set_value(!v.value());
-
-                                            let fields: Fields = vec![Box::new(v)];
-                                            for field in fields.iter() {
-                                                cl.ag_write(
-                                                    field.data_block(),
-                                                    field.offset(),
-                                                    field.to_bytes().len() as i32,
-                                                    field.to_bytes().as_mut(),
-                                                )
-                                                .unwrap();
-                                            }
-                                        }
-                                    }
-                                    S7Message::S7Real(value) => {}
-                                    S7Message::S7Int(value) => {}
+                        let now = Instant::now();
+                        match block_in_place(|| read_s7_tags(&mut transport, &s7_config.tags)) {
+                            Ok(mut values) => {
+                                let elapsed_time = now.elapsed().as_micros();
+                                scale_tags(&tags, &device_name, &mut values);
+                                let snapshot =
+                                    Snapshot::new(&device_name, &tags, values, HashMap::new());
+                                link.publish(snapshot, elapsed_time, &scheduler.stats);
+                            }
+                            Err(e) => {
+                                let error_code = 2;
+                                let error_msg =
+                                    format!("{:#02x}: Could not read the PLC. {}", error_code, e);
+                                if link.scan_failed(e.failure, error_msg) {
+                                    break;
                                 }
-                                s7_message = None;
                             }
                         }
-                    } else {
-                        println!("Could not connect tcp.");
                     }
-                });
-            }
+                    if !link.wait_to_reconnect().await {
+                        return;
+                    }
+                }
+            });
         }
         DeviceConfig::ModbusTcp(config) => {
             let mut config = config.clone();
             let tcp_string = format!("{}:{}", config.ip_address, config.port);
-            thread::spawn(move || {
-                if let Ok(sock_addr) = tcp_string.parse::<SocketAddr>() {
-                    let ctx = connect_with_timeout(sock_addr, Some(Duration::from_millis(5000)));
-                    if let Ok(mut ctx) = ctx {
-                        loop {
-                            thread::sleep(Duration::from_millis(
-                                config.protocol_definitions.scan_delay,
-                            ));
-                            if let Some(mut mutex) = mutex.try_lock() {
-                                // We check for any pending new modbus configuration
-                                if let Some(new_config) = mutex.new_config.clone() {
-                                    // We update the modbus config
-                                    config.protocol_definitions =
-                                        new_config.modbus_tcp_buffer.protocol_definitions;
-                                    // We clean the mutex
-                                    mutex.new_config = None;
+            runtime.spawn(async move {
+                let Ok(sock_addr) = tcp_string.parse::<SocketAddr>() else {
+                    let error_code = 3;
+                    let error_msg = format!("{:#02x}: Error parsing the address IP.", error_code);
+                    link.connect_failed(error_msg);
+                    return;
+                };
+                let timeout = Duration::from_millis(5000);
+                loop {
+                    link.connecting();
+                    let ctx = tokio::time::timeout(
+                        timeout,
+                        tokio_modbus::client::tcp::connect(sock_addr),
+                    )
+                    .await;
+                    if let Ok(Ok(mut ctx)) = ctx {
+                        link.connected();
+                        let end = poll_modbus(
+                            &mut ctx,
+                            &mut link,
+                            &mut config.protocol_definitions,
+                            |config| config.modbus_tcp_buffer.protocol_definitions,
+                            &mut tags,
+                            &mut signals,
+                            timeout,
+                        )
+                        .await;
+                        if let PollEnd::Stopped = end {
+                            return;
+                        }
+                    } else {
+                        let error_code = 1;
+                        let error_msg =
+                            format!("{:#02x}: Could not connect to server.", error_code);
+                        link.connect_failed(error_msg);
+                    }
+                    if !link.wait_to_reconnect().await {
+                        return;
+                    }
+                }
+            });
+        }
+        DeviceConfig::Datascan(datascan_config) => {
+            let mut datascan_config = datascan_config.clone();
+            runtime.spawn(async move {
+                // The framing isn't checked against the vendor specification, so a
+                // port saved before it was the only choice isn't opened either.
+                if datascan_config.port != SIMULATOR_PORT {
+                    let error_code = 3;
+                    let error_msg = format!(
+                        "{:#02x}: Datascan modules are only polled through the {} until the framing is checked.",
+                        error_code, SIMULATOR_PORT
+                    );
+                    link.connect_failed(error_msg);
+                    return;
+                }
+                loop {
+                    link.connecting();
+                    let mut port: Box<dyn DatascanPort> = Box::<Simulator>::default();
+                    link.connected();
+
+                    let mut scheduler = Scheduler::new(&[(
+                        ScanClass::Normal,
+                        Duration::from_millis(datascan_config.scan_period),
+                    )]);
+                    loop {
+                        let due = tokio::select! {
+                            _ = link.receive_command() => false,
+                            _ = scheduler.tick() => true,
+                        };
+                        let pending = link.take_pending();
+                        // We check for an edited point list and scan period
+                        if let Some(new_config) = pending.config {
+                            datascan_config.points = new_config.datascan_buffer.points;
+                            let scan_period = new_config.datascan_buffer.scan_period;
+                            if datascan_config.scan_period != scan_period {
+                                datascan_config.scan_period = scan_period;
+                                scheduler = Scheduler::new(&[(
+                                    ScanClass::Normal,
+                                    Duration::from_millis(scan_period),
+                                )]);
+                            }
+                        }
+                        // We check for an edited tag database
+                        if let Some(new_tags) = pending.tags {
+                            tags = new_tags;
+                        }
+                        // We check for a pending stop request
+                        if pending.stop {
+                            return;
+                        }
+
+                        for request in pending.writes {
+                            let result = match &request.command {
+                                // The serial port blocks, so it runs off the async workers.
+                                WriteCommand::Setpoint(value) => block_in_place(|| {
+                                    write_point(
+                                        &mut port,
+                                        &datascan_config.points,
+                                        &request.source,
+                                        value,
+                                    )
+                                }),
+                                WriteCommand::Coil { .. } => {
+                                    Err("Coils only exist on Modbus devices.".to_string())
                                 }
-                                // We check for a pending thread kill request
-                                if mutex.kill_thread {
-                                    // We clean the mutex
-                                    mutex.kill_thread = false;
-                                    // We return from the thread
-                                    return;
+                            };
+                            link.report_write(request, result);
+                        }
+                        if !due {
+                            continue;
+                        }
+
+                        let now = Instant::now();
+                        match block_in_place(|| read_points(&mut port, &datascan_config.points)) {
+                            Ok(mut values) => {
+                                let elapsed_time = now.elapsed().as_micros();
+                                scale_tags(&tags, &device_name, &mut values);
+                                let snapshot =
+                                    Snapshot::new(&device_name, &tags, values, HashMap::new());
+                                link.publish(snapshot, elapsed_time, &scheduler.stats);
+                            }
+                            Err(e) => {
+                                let error_code = 2;
+                                let error_msg = format!(
+                                    "{:#02x}: Could not read the modules. {}",
+                                    error_code, e
+                                );
+                                if link.scan_failed(e.failure, error_msg) {
+                                    break;
                                 }
                             }
+                        }
+                    }
+                    if !link.wait_to_reconnect().await {
+                        return;
+                    }
+                }
+            });
+        }
+        DeviceConfig::EthernetIp(eip_config) => {
+            let mut eip_config = eip_config.clone();
+            runtime.spawn(async move {
+                let timeout = Duration::from_millis(eip_config.timeout);
+                loop {
+                    link.connecting();
+                    let mut client = match logix_client(&eip_config.ip, eip_config.slot).await {
+                        Ok(client) => client,
+                        Err(e) => {
+                            let error_code = 1;
+                            let error_msg = format!("{:#02x}: {}", error_code, e);
+                            link.connect_failed(error_msg);
+                            if !link.wait_to_reconnect().await {
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    // The session is only opened by the first request.
+                    let mut connected = false;
+
+                    let mut scheduler = Scheduler::new(&[(
+                        ScanClass::Normal,
+                        Duration::from_millis(eip_config.scan_period),
+                    )]);
+                    loop {
+                        let due = tokio::select! {
+                            _ = link.receive_command() => false,
+                            _ = scheduler.tick() => true,
+                        };
+                        let pending = link.take_pending();
+                        // We check for an edited tag list and scan period
+                        if let Some(new_config) = pending.config {
+                            eip_config.tags = new_config.ethernet_ip_buffer.tags;
+                            let scan_period = new_config.ethernet_ip_buffer.scan_period;
+                            if eip_config.scan_period != scan_period {
+                                eip_config.scan_period = scan_period;
+                                scheduler = Scheduler::new(&[(
+                                    ScanClass::Normal,
+                                    Duration::from_millis(scan_period),
+                                )]);
+                            }
+                        }
+                        // We check for an edited tag database
+                        if let Some(new_tags) = pending.tags {
+                            tags = new_tags;
+                        }
+                        // We check for a pending stop request
+                        if pending.stop {
+                            close_logix_client(&mut client).await;
+                            return;
+                        }
+
+                        for request in pending.writes {
+                            let result = match &request.command {
+                                WriteCommand::Setpoint(value) => {
+                                    write_logix_tag(
+                                        &mut client,
+                                        &eip_config.tags,
+                                        &request.source,
+                                        value,
+                                        timeout,
+                                    )
+                                    .await
+                                }
+                                WriteCommand::Coil { .. } => {
+                                    Err("Coils only exist on Modbus devices.".to_string())
+                                }
+                            };
+                            link.report_write(request, result);
+                        }
+                        if !due {
+                            continue;
+                        }
 
-                            match config.protocol_definitions.register_type {
-                                RegisterType::Coils => {}
-                                RegisterType::Inputs => {
-                                    let now = Instant::now();
-                                    let result = ctx.read_input_registers(
-                                        config.protocol_definitions.start_address,
-                                        config.protocol_definitions.register_count,
-                                    );
-
-                                    if let Ok(res) = result {
-                                        let elapsed_time = now.elapsed().as_micros();
-                                        let mut data = mutex.lock();
-                                        data.data = res;
-                                        data.achieved_scan_time = elapsed_time;
-                                    }
+                        let now = Instant::now();
+                        match read_logix_tags(&mut client, &eip_config.tags, timeout).await {
+                            Ok((mut values, errors)) => {
+                                let elapsed_time = now.elapsed().as_micros();
+                                scale_tags(&tags, &device_name, &mut values);
+                                if !connected {
+                                    connected = true;
+                                    link.connected();
                                 }
-                                RegisterType::Holding => {
-                                    let now = Instant::now();
-                                    let result = ctx.read_holding_registers(
-                                        config.protocol_definitions.start_address,
-                                        config.protocol_definitions.register_count,
-                                    );
-                                    match result {
-                                        Ok(res) => {
-                                            let elapsed_time = now.elapsed().as_micros();
-                                            {
-                                                let mut data = mutex.lock();
-                                                data.data = res.clone();
-                                                data.error_msg = "".to_string();
-                                                data.achieved_scan_time = elapsed_time;
-                                            }
-                                            let tag_list = [
-                                                "LT1-1", "PT1-1", "PT2-1", "PT1-2", "PT2-2",
-                                                "PT2-3", "PT3-1",
-                                            ];
-                                            if true {
-                                                if res.len() >= (tag_list.len() * 2) {
-                                                    let mut line = String::new();
-                                                    let datetime = chrono::Utc::now();
-                                                    let datetime =
-                                                        datetime.format("%d/%m/%Y\t %H:%M:%S\t");
-                                                    line.push_str(&datetime.to_string());
-                                                    let mut i = 0;
-                                                    for _tag in tag_list.iter() {
-                                                        let fmt = format!(
-                                                            "{:.2}\t",
-                                                            u16_to_float(
-                                                                res[i * 2],
-                                                                res[(i * 2) + 1]
-                                                            )
-                                                        );
-                                                        line.push_str(&format!("{}", &fmt));
-                                                        i += 1;
-                                                    }
-
-                                                    line.push_str("\r\n");
-                                                    logger.write_all(line.as_bytes()).unwrap();
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            let error_code = 2;
-                                            let error_msg = format!(
-                                                "{:#02x}: Could not read registers. {}",
-                                                error_code, e
-                                            );
-                                            let mut data = mutex.lock();
-                                            data.error_msg = error_msg;
-                                            data.achieved_scan_time = 0;
-                                        }
-                                    }
+                                // The tags left out are published as bad.
+                                let snapshot =
+                                    Snapshot::new(&device_name, &tags, values, HashMap::new());
+                                link.publish(snapshot, elapsed_time, &scheduler.stats);
+                                link.report_tag_errors(errors);
+                            }
+                            Err(e) => {
+                                let error_code = 2;
+                                let error_msg = format!(
+                                    "{:#02x}: Could not read the controller. {}",
+                                    error_code, e
+                                );
+                                if link.scan_failed(e.failure, error_msg) {
+                                    break;
                                 }
                             }
                         }
-                    } else {
-                        let error_code = 1;
-                        let error_msg =
-                            format!("{:#02x}: Could not connect to server.", error_code);
-                        let mut data = mutex.lock();
-                        data.error_msg = error_msg;
-                        data.achieved_scan_time = 0;
+                    }
+                    if !link.wait_to_reconnect().await {
                         return;
                     }
-                } else {
-                    let error_code = 3;
-                    let error_msg = format!("{:#02x}: Error parsing the address IP.", error_code);
-                    let mut data = mutex.lock();
-                    data.error_msg = error_msg;
-                    data.achieved_scan_time = 0;
-                    return;
                 }
             });
         }
-        _ => {}
     }
 }
 
@@ -1610,29 +3122,391 @@ async fn index(req: HttpRequest) -> &'static str {
     "Hello world!"
 }
 
-async fn run_app() -> std::io::Result<()> {
+/// A tag value served by `/tags`, with its OPC DA quality.
+#[derive(serde::Serialize, Clone)]
+struct TagReading {
+    name: String,
+    value: Option<Value>,
+    unit: String,
+    quality: Quality,
+    /// The OPC DA quality code, 192 when good.
+    quality_code: u8,
+    /// When the device returned the value, RFC 3339.
+    timestamp: Option<String>,
+}
+
+impl TagReading {
+    fn new(tag: &Tag) -> Self {
+        let value = tag.timestamp.map(|_| {
+            if tag.data_type.is_text() {
+                Value::Text(tag.text.clone())
+            } else {
+                Value::Number(tag.value as f64)
+            }
+        });
+        Self {
+            name: tag.name.clone(),
+            value,
+            unit: tag.unit.clone(),
+            quality: tag.quality,
+            quality_code: tag.quality.opc_code(),
+            timestamp: tag.timestamp.map(|time| time.to_rfc3339()),
+        }
+    }
+}
+
+async fn live_tags(
+    live: web::Data<watch::Receiver<Vec<TagReading>>>,
+) -> web::Json<Vec<TagReading>> {
+    web::Json(live.borrow().clone())
+}
+
+/// The parameters of `/history`. Times are RFC 3339, the last hour by default.
+#[derive(serde::Deserialize)]
+struct HistoryQuery {
+    tag: String,
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    aggregation: Aggregation,
+}
+
+fn parse_query_time(
+    text: &Option<String>,
+    default: chrono::DateTime<chrono::Local>,
+) -> Option<f64> {
+    match text {
+        Some(text) => chrono::DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|time| time.timestamp_millis() as f64 / 1000.),
+        None => Some(timestamp(default)),
+    }
+}
+
+async fn history(
+    historian: web::Data<Historian>,
+    query: web::Query<HistoryQuery>,
+) -> actix_web::Result<web::Json<Vec<Point>>> {
+    let now = chrono::Local::now();
+    let (Some(start), Some(end)) = (
+        parse_query_time(&query.from, now - chrono::Duration::hours(1)),
+        parse_query_time(&query.to, now),
+    ) else {
+        return Err(actix_web::error::ErrorBadRequest(
+            "from and to must be RFC 3339 times",
+        ));
+    };
+    let points = web::block(move || historian.query(&query.tag, start, end, query.aggregation))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(web::Json(points))
+}
+
+async fn run_app(
+    historian: Option<Arc<Historian>>,
+    handle: Sender<ServerHandle>,
+    live: watch::Receiver<Vec<TagReading>>,
+) -> std::io::Result<()> {
     log::info!("starting HTTP server at http://localhost:8080");
 
     // srv is server controller type, `dev::Server`
-    let server = HttpServer::new(|| {
-        App::new()
+    let server = HttpServer::new(move || {
+        let mut app = App::new()
             // enable logger
             .wrap(middleware::Logger::default())
             .service(web::resource("/index.html").to(|| async { "Hello world!" }))
             .service(web::resource("/").to(index))
+            .app_data(web::Data::new(live.clone()))
+            .service(web::resource("/tags").to(live_tags));
+        if let Some(historian) = &historian {
+            app = app
+                .app_data(web::Data::from(Arc::clone(historian)))
+                .service(web::resource("/history").to(history));
+        }
+        app
     })
     .bind(("127.0.0.1", 8080))?
     .workers(2)
     .run();
 
     // Send server handle back to the main thread
-    //let _ = tx.send(server.handle());
+    let _ = handle.send(server.handle());
 
     server.await
 }
 
-fn u16_to_float(reg1: u16, reg2: u16) -> f32 {
-    let data_32bit_rep = ((reg1 as u32) << 16) | reg2 as u32;
-    let data_array = data_32bit_rep.to_ne_bytes();
-    f32::from_ne_bytes(data_array)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn the_single_block_of_old_definitions_is_kept() {
+        let saved = "(register_type: Inputs, start_address: 100, register_count: 20, \
+                     scan_delay: 500, request_function_vec: [])";
+        let definitions: ModbusDefinitions = ron::from_str(saved).unwrap();
+        assert_eq!(
+            definitions.blocks,
+            vec![ReadBlock {
+                register_type: RegisterType::Inputs,
+                start_address: 100,
+                count: 20,
+                ..Default::default()
+            }]
+        );
+        assert_eq!(definitions.scan_period, 500);
+
+        let saved = ron::to_string(&definitions).unwrap();
+        assert_eq!(
+            ron::from_str::<ModbusDefinitions>(&saved).unwrap(),
+            definitions
+        );
+    }
+
+    #[test]
+    fn the_device_of_an_old_state_becomes_the_first_device() {
+        let saved = r#"(
+            device_config_buffer: (
+                modbus_serial_buffer: (
+                    port: "/dev/ttyUSB0",
+                    baudrate: Baud9600,
+                    slave: 7,
+                    slave_buffer: "7",
+                    parity: Even,
+                    protocol_definitions: (register_type: Holding, start_address: 10,
+                        register_count: 16, scan_delay: 1000, request_function_vec: []),
+                ),
+                modbus_tcp_buffer: (
+                    ip_address: "10.0.0.5",
+                    port: 5020,
+                    protocol_definitions: (register_type: Inputs, start_address: 0,
+                        register_count: 8, scan_delay: 250, request_function_vec: []),
+                ),
+                ethernet_ip_buffer: (),
+                s7_buffer: (ip: "10.0.0.9"),
+            ),
+            protocol: ModbusTcpProtocol,
+            tags: [],
+            widgets_pos: (hello_button_pos: (x: 0.0, y: 0.0),
+                close_button_pos: (x: 0.0, y: 0.0), tag1_pos: (x: 0.0, y: 0.0)),
+            blink_flag: false,
+            logger_path: "./",
+        )"#;
+        let mut app: CarbonApp = ron::from_str(saved).unwrap();
+        app.adopt_legacy_device();
+
+        assert_eq!(app.devices.len(), 1);
+        let device = &app.devices[0];
+        assert_eq!(device.protocol, Protocol::ModbusTcpProtocol);
+        assert_eq!(device.config.device_name, DEFAULT_DEVICE);
+        assert_eq!(device.config.stale_after_scans, STALE_AFTER_SCANS);
+        let tcp = &device.config.modbus_tcp_buffer;
+        assert_eq!((tcp.ip_address.as_str(), tcp.port), ("10.0.0.5", 5020));
+        assert_eq!(
+            tcp.protocol_definitions.blocks[0].register_type,
+            RegisterType::Inputs
+        );
+        assert_eq!(tcp.protocol_definitions.scan_period, 250);
+        let serial = &device.config.modbus_serial_buffer;
+        assert_eq!((serial.port.as_str(), serial.slave), ("/dev/ttyUSB0", 7));
+        assert_eq!(serial.protocol_definitions.blocks[0].start_address, 10);
+        assert_eq!(device.config.s7_buffer.ip, "10.0.0.9");
+
+        // The device list is saved from then on, without the old fields.
+        let saved = ron::to_string(&app).unwrap();
+        assert!(!saved.contains("device_config_buffer"));
+    }
+
+    #[test]
+    fn editing_a_description_keeps_the_scan_schedule() {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut definitions = ModbusDefinitions::default();
+            let mut tags = default_tags();
+            let signals = default_signals();
+            let mut plan = definitions.scan_plan(&tags, &signals, DEFAULT_DEVICE);
+            assert!(!plan.classes.is_empty());
+            plan.scheduler.tick().await;
+
+            tags[0].description = "Edited".to_string();
+            let edited = definitions.scan_plan(&tags, &signals, DEFAULT_DEVICE);
+            assert!(!plan.update(edited));
+            assert_eq!(plan.scheduler.stats[0].scans, 1);
+
+            definitions.scan_period += 100;
+            let edited = definitions.scan_plan(&tags, &signals, DEFAULT_DEVICE);
+            assert!(plan.update(edited));
+            assert_eq!(plan.scheduler.stats[0].scans, 0);
+        });
+    }
+
+    #[test]
+    fn a_modbus_exception_is_an_answer() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let exception = runtime.block_on(async {
+            let (port, mut device) = tokio::io::duplex(64);
+            // Illegal data address, in answer to a read of holding registers.
+            device
+                .write_all(&[0x01, 0x83, 0x02, 0xC0, 0xF1])
+                .await
+                .unwrap();
+            let mut ctx = rtu::attach_slave(port, Slave(1));
+            ctx.read_holding_registers(0, 1).await.unwrap_err()
+        });
+        assert_eq!(modbus_failure(&exception), Failure::Exception);
+        let elapsed = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert_eq!(modbus_failure(&elapsed), Failure::Timeout);
+        let garbled = std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid CRC");
+        assert_eq!(modbus_failure(&garbled), Failure::Crc);
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(modbus_failure(&reset), Failure::Other);
+    }
+
+    #[test]
+    fn register_bits_are_only_read_from_the_holding_registers() {
+        let tag = |name: &str, register_type| Tag {
+            name: name.to_string(),
+            register_type,
+            address: 100,
+            data_type: crate::codec::DataType::UInt16,
+            ..Default::default()
+        };
+        let tags = vec![
+            tag("HOLDING", RegisterType::Holding),
+            tag("INPUT", RegisterType::Inputs),
+        ];
+        let signals = vec![DigitalSignal {
+            label: "PUMP RUN".to_string(),
+            source: BitSource::RegisterBit {
+                address: 100,
+                bit: 0,
+            },
+            ..Default::default()
+        }];
+        // The input block covers the same addresses and comes last.
+        let blocks = vec![
+            (
+                ReadBlock {
+                    register_type: RegisterType::Holding,
+                    start_address: 100,
+                    count: 2,
+                    ..Default::default()
+                },
+                BlockData::Registers(vec![1, 0]),
+            ),
+            (
+                ReadBlock {
+                    register_type: RegisterType::Inputs,
+                    start_address: 100,
+                    count: 2,
+                    ..Default::default()
+                },
+                BlockData::Registers(vec![6, 0]),
+            ),
+        ];
+
+        let snapshot = decode_blocks(blocks, &tags, &signals, DEFAULT_DEVICE);
+        assert_eq!(
+            snapshot.signal_states,
+            HashMap::from([("PUMP RUN".to_string(), true)])
+        );
+        assert_eq!(snapshot.samples["HOLDING"].value, Some(Value::Number(1.)));
+        assert_eq!(snapshot.samples["INPUT"].value, Some(Value::Number(6.)));
+    }
+
+    #[test]
+    fn the_services_stop_taking_the_values_of_a_stopped_device_as_good() {
+        let tags: Vec<Tag> = ["A", "B"]
+            .into_iter()
+            .map(|device| Tag {
+                name: format!("{}-PT", device),
+                device: device.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let (logger, logged) = channel();
+        let services = ServiceFeed {
+            logger,
+            historian: None,
+        };
+        let (updates, _ui) = channel();
+        let (stop_a, commands_a) = unbounded_channel();
+        let (_stop_b, commands_b) = unbounded_channel();
+        let mut a = DriverLink::new(
+            "A",
+            STALE_AFTER_SCANS,
+            commands_a,
+            updates.clone(),
+            services.clone(),
+        );
+        let mut b = DriverLink::new("B", STALE_AFTER_SCANS, commands_b, updates, services);
+        let scan = |device: &str| {
+            let values = HashMap::from([(format!("{}-PT", device), Value::Number(1.))]);
+            Snapshot::new(device, &tags, values, HashMap::new())
+        };
+
+        a.publish(scan("A"), 0, &[]);
+        b.publish(scan("B"), 0, &[]);
+        stop_a.send(DeviceCommand::Stop).unwrap();
+        assert!(a.take_pending().stop);
+        b.publish(scan("B"), 0, &[]);
+
+        // The service threads merge the snapshots of all the devices.
+        let mut samples = HashMap::new();
+        for command in logged.try_iter() {
+            if let ServiceCommand::Snapshot(snapshot) = command {
+                samples.extend(snapshot.samples);
+            }
+        }
+        assert_eq!(samples["A-PT"].quality, Quality::CommFail);
+        assert_eq!(samples["B-PT"].quality, Quality::Good);
+        assert!(!good_values(&samples).contains_key("A-PT"));
+    }
+
+    #[test]
+    fn values_go_stale_after_the_configured_failed_scans() {
+        let tags = vec![Tag::default()];
+        let (logger, logged) = channel();
+        let services = ServiceFeed {
+            logger,
+            historian: None,
+        };
+        let (updates, _ui) = channel();
+        let (_commands, receiver) = unbounded_channel();
+        let mut link = DriverLink::new(DEFAULT_DEVICE, 3, receiver, updates, services);
+        let values = HashMap::from([(tags[0].name.clone(), Value::Number(1.))]);
+        link.publish(
+            Snapshot::new(DEFAULT_DEVICE, &tags, values, HashMap::new()),
+            0,
+            &[],
+        );
+        let qualities = || -> Vec<Quality> {
+            logged
+                .try_iter()
+                .filter_map(|command| match command {
+                    ServiceCommand::Snapshot(snapshot) => {
+                        Some(snapshot.samples[&tags[0].name].quality)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(qualities(), [Quality::Good]);
+
+        // Exceptions, so the link isn't deemed lost.
+        for _ in 0..2 {
+            link.scan_failed(Failure::Exception, "Refused".to_string());
+        }
+        assert_eq!(qualities(), []);
+        link.scan_failed(Failure::Exception, "Refused".to_string());
+        assert_eq!(qualities(), [Quality::Stale]);
+    }
 }
@@ -0,0 +1,441 @@
+use egui::{Button, ComboBox, DragValue, Grid};
+use rmodbus::{client::ModbusRequest, ModbusProto};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::scan::{scan_class_ui, ScanClass};
+use crate::signals::{BitSource, DigitalSignal};
+use crate::tags::Tag;
+
+//#################################################### Modbus read blocks.
+
+/// The Modbus table a block is read from.
+#[derive(
+    serde::Deserialize, serde::Serialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy,
+)]
+pub enum RegisterType {
+    Coils,
+    DiscreteInputs,
+    Inputs,
+    Holding,
+}
+
+impl RegisterType {
+    pub fn all() -> [RegisterType; 4] {
+        [
+            RegisterType::Coils,
+            RegisterType::DiscreteInputs,
+            RegisterType::Inputs,
+            RegisterType::Holding,
+        ]
+    }
+
+    /// Whether the table holds single bits rather than 16 bit registers.
+    pub fn is_bit(&self) -> bool {
+        matches!(self, RegisterType::Coils | RegisterType::DiscreteInputs)
+    }
+
+    /// The most addresses a single request can read, set by the 253 byte PDU.
+    pub fn max_count(&self) -> u16 {
+        if self.is_bit() {
+            2000
+        } else {
+            125
+        }
+    }
+
+    /// The short name used in the tag grid.
+    pub fn short(&self) -> &'static str {
+        match self {
+            RegisterType::Coils => "CO",
+            RegisterType::DiscreteInputs => "DI",
+            RegisterType::Inputs => "IR",
+            RegisterType::Holding => "HR",
+        }
+    }
+}
+
+impl Display for RegisterType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterType::Coils => write!(f, "Coils"),
+            RegisterType::DiscreteInputs => write!(f, "Discrete inputs"),
+            RegisterType::Inputs => write!(f, "Input registers"),
+            RegisterType::Holding => write!(f, "Holding registers"),
+        }
+    }
+}
+
+impl Default for RegisterType {
+    fn default() -> Self {
+        Self::Holding
+    }
+}
+
+/// A contiguous range of addresses read with a single request.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct ReadBlock {
+    pub register_type: RegisterType,
+    pub start_address: u16,
+    pub count: u16,
+    pub scan_class: ScanClass,
+}
+
+impl Default for ReadBlock {
+    fn default() -> Self {
+        Self {
+            register_type: RegisterType::default(),
+            start_address: 0,
+            count: 38,
+            scan_class: ScanClass::default(),
+        }
+    }
+}
+
+impl Display for ReadBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}..{}",
+            self.register_type,
+            self.start_address,
+            self.end() - 1
+        )
+    }
+}
+
+impl ReadBlock {
+    fn new(register_type: RegisterType, start_address: u16, count: u16) -> Self {
+        Self {
+            register_type,
+            start_address,
+            count,
+            scan_class: ScanClass::default(),
+        }
+    }
+
+    /// The address after the last one of the block.
+    fn end(&self) -> u32 {
+        self.start_address as u32 + self.count as u32
+    }
+
+    /// Whether the block reads all the `count` addresses of `register_type`
+    /// from `address` on.
+    pub fn covers(&self, register_type: RegisterType, address: u16, count: u16) -> bool {
+        self.register_type == register_type
+            && address >= self.start_address
+            && address as u32 + count as u32 <= self.end()
+    }
+
+    /// The Modbus TCP frame of the read request, as shown in the options.
+    pub fn request(&self) -> Vec<u8> {
+        let mut request = Vec::new();
+        let mut modbus_request = ModbusRequest::new(1, ModbusProto::TcpUdp);
+        let (start, count) = (self.start_address, self.count);
+        let result = match self.register_type {
+            RegisterType::Coils => modbus_request.generate_get_coils(start, count, &mut request),
+            RegisterType::DiscreteInputs => {
+                modbus_request.generate_get_discretes(start, count, &mut request)
+            }
+            RegisterType::Inputs => modbus_request.generate_get_inputs(start, count, &mut request),
+            RegisterType::Holding => {
+                modbus_request.generate_get_holdings(start, count, &mut request)
+            }
+        };
+        if result.is_err() {
+            request.clear();
+        }
+        request
+    }
+}
+
+/// Cuts a range longer than a single request of its table into requests
+/// that fit. A value split this way can't be decoded, so its tag stays bad,
+/// but the other addresses are still read.
+fn split(range: &ReadBlock) -> Vec<ReadBlock> {
+    let max_count = range.register_type.max_count() as u32;
+    (range.start_address as u32..range.end().min(u16::MAX as u32 + 1))
+        .step_by(max_count as usize)
+        .map(|start| ReadBlock {
+            start_address: start as u16,
+            count: (range.end() - start).min(max_count) as u16,
+            ..range.clone()
+        })
+        .collect()
+}
+
+/// Merges the address ranges into as few requests as possible.
+///
+/// Ranges of the same table and scan class are read together when at
+/// most `max_gap` unused addresses lie between them and the block stays
+/// within the PDU limit of the table.
+pub fn coalesce(ranges: &[ReadBlock], max_gap: u16) -> Vec<ReadBlock> {
+    let mut ranges: Vec<ReadBlock> = ranges
+        .iter()
+        .filter(|range| range.count > 0)
+        .flat_map(split)
+        .collect();
+    ranges.sort_by_key(|range| (range.scan_class, range.register_type, range.start_address));
+
+    let mut blocks: Vec<ReadBlock> = Vec::new();
+    for range in ranges {
+        let max_count = range.register_type.max_count() as u32;
+        if let Some(block) = blocks.last_mut() {
+            let end = block.end().max(range.end());
+            if block.register_type == range.register_type
+                && block.scan_class == range.scan_class
+                && range.start_address as u32 <= block.end() + max_gap as u32
+                && end - block.start_address as u32 <= max_count
+            {
+                block.count = (end - block.start_address as u32) as u16;
+                continue;
+            }
+        }
+        blocks.push(range);
+    }
+    blocks
+}
+
+/// The read blocks of `device`, generated from the addresses its tags and
+/// signals use. The tags are read in their own scan class, the signals in
+/// the normal one.
+pub fn generate_blocks(
+    tags: &[Tag],
+    signals: &[DigitalSignal],
+    device: &str,
+    max_gap: u16,
+) -> Vec<ReadBlock> {
+    let tag_ranges = tags
+        .iter()
+        .filter(|tag| tag.device == device)
+        .map(|tag| ReadBlock {
+            scan_class: tag.scan_class,
+            ..ReadBlock::new(
+                tag.register_type,
+                tag.address,
+                tag.data_type.register_count() as u16,
+            )
+        });
+    let signal_ranges = signals
+        .iter()
+        .filter(|signal| signal.device == device)
+        .map(|signal| match signal.source {
+            BitSource::RegisterBit { address, .. } => {
+                ReadBlock::new(RegisterType::Holding, address, 1)
+            }
+            BitSource::Coil(address) => ReadBlock::new(RegisterType::Coils, address, 1),
+            BitSource::DiscreteInput(address) => {
+                ReadBlock::new(RegisterType::DiscreteInputs, address, 1)
+            }
+        });
+    let ranges: Vec<ReadBlock> = tag_ranges.chain(signal_ranges).collect();
+    coalesce(&ranges, max_gap)
+}
+
+/// The blocks of each scan class, in the order of the classes.
+pub fn group_by_class(blocks: Vec<ReadBlock>) -> BTreeMap<ScanClass, Vec<ReadBlock>> {
+    let mut classes: BTreeMap<ScanClass, Vec<ReadBlock>> = BTreeMap::new();
+    for block in blocks {
+        classes.entry(block.scan_class).or_default().push(block);
+    }
+    classes
+}
+//####################################################
+
+//#################################################### Block list editor.
+
+/// The editor of the read blocks of a device. Returns true when they changed.
+pub fn blocks_ui(ui: &mut egui::Ui, blocks: &mut Vec<ReadBlock>) -> bool {
+    let before = blocks.clone();
+    let mut remove = None;
+
+    Grid::new("read_blocks")
+        .num_columns(5)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Table");
+            ui.strong("Start");
+            ui.strong("Quantity");
+            ui.strong("Scan class");
+            ui.end_row();
+
+            for (i, block) in blocks.iter_mut().enumerate() {
+                ComboBox::from_id_source(("read_block_table", i))
+                    .selected_text(format!("{}", block.register_type))
+                    .show_ui(ui, |ui| {
+                        for register_type in RegisterType::all() {
+                            let text = format!("{}", register_type);
+                            ui.selectable_value(&mut block.register_type, register_type, text);
+                        }
+                    });
+                ui.add(DragValue::new(&mut block.start_address).clamp_range(0..=65535));
+                block.count = block.count.min(block.register_type.max_count());
+                ui.add(
+                    DragValue::new(&mut block.count)
+                        .clamp_range(1..=block.register_type.max_count()),
+                );
+                scan_class_ui(ui, ("read_block_class", i), &mut block.scan_class);
+                if ui
+                    .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                    .on_hover_text("Remove block")
+                    .clicked()
+                {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+
+    if let Some(i) = remove {
+        blocks.remove(i);
+    }
+    if ui
+        .button(format!("{} Add block", egui_phosphor::regular::PLUS))
+        .clicked()
+    {
+        let start_address = blocks
+            .last()
+            .map_or(0, |block| block.end().min(u16::MAX as u32) as u16);
+        blocks.push(ReadBlock {
+            start_address,
+            ..Default::default()
+        });
+    }
+
+    *blocks != before
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::DataType;
+
+    #[test]
+    fn nearby_ranges_are_merged_within_the_gap() {
+        let ranges = [
+            ReadBlock::new(RegisterType::Holding, 10, 2),
+            ReadBlock::new(RegisterType::Holding, 0, 2),
+            ReadBlock::new(RegisterType::Holding, 14, 1),
+            ReadBlock::new(RegisterType::Holding, 40, 2),
+        ];
+        assert_eq!(
+            coalesce(&ranges, 8),
+            vec![
+                ReadBlock::new(RegisterType::Holding, 0, 15),
+                ReadBlock::new(RegisterType::Holding, 40, 2),
+            ]
+        );
+        assert_eq!(coalesce(&ranges, 0).len(), 4);
+    }
+
+    #[test]
+    fn blocks_honour_the_pdu_limits() {
+        let registers: Vec<ReadBlock> = (0..200)
+            .map(|address| ReadBlock::new(RegisterType::Inputs, address, 1))
+            .collect();
+        assert_eq!(
+            coalesce(&registers, 0),
+            vec![
+                ReadBlock::new(RegisterType::Inputs, 0, 125),
+                ReadBlock::new(RegisterType::Inputs, 125, 75),
+            ]
+        );
+
+        let coils = [
+            ReadBlock::new(RegisterType::Coils, 0, 1),
+            ReadBlock::new(RegisterType::Coils, 1999, 1),
+            ReadBlock::new(RegisterType::Coils, 2000, 1),
+        ];
+        assert_eq!(
+            coalesce(&coils, 2000),
+            vec![
+                ReadBlock::new(RegisterType::Coils, 0, 2000),
+                ReadBlock::new(RegisterType::Coils, 2000, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_longer_than_a_request_are_split() {
+        let ranges = [ReadBlock::new(RegisterType::Holding, 10, 128)];
+        assert_eq!(
+            coalesce(&ranges, 0),
+            vec![
+                ReadBlock::new(RegisterType::Holding, 10, 125),
+                ReadBlock::new(RegisterType::Holding, 135, 3),
+            ]
+        );
+        // Merging them again would exceed the limit.
+        assert_eq!(coalesce(&ranges, 100).len(), 2);
+    }
+
+    #[test]
+    fn tables_are_never_mixed() {
+        let ranges = [
+            ReadBlock::new(RegisterType::Holding, 0, 1),
+            ReadBlock::new(RegisterType::Inputs, 1, 1),
+            ReadBlock::new(RegisterType::Coils, 2, 1),
+        ];
+        assert_eq!(coalesce(&ranges, 100).len(), 3);
+    }
+
+    #[test]
+    fn scan_classes_are_never_mixed() {
+        let fast = ReadBlock {
+            scan_class: ScanClass::Fast,
+            ..ReadBlock::new(RegisterType::Holding, 2, 1)
+        };
+        let ranges = [
+            ReadBlock::new(RegisterType::Holding, 0, 2),
+            fast.clone(),
+            ReadBlock::new(RegisterType::Holding, 3, 1),
+        ];
+        assert_eq!(
+            coalesce(&ranges, 10),
+            vec![fast.clone(), ReadBlock::new(RegisterType::Holding, 0, 4)]
+        );
+        let classes = group_by_class(coalesce(&ranges, 10));
+        assert_eq!(classes[&ScanClass::Fast], vec![fast]);
+        assert_eq!(classes[&ScanClass::Normal].len(), 1);
+    }
+
+    #[test]
+    fn blocks_are_generated_from_the_tag_database() {
+        let tags = vec![
+            Tag {
+                address: 100,
+                data_type: DataType::Float64,
+                ..Default::default()
+            },
+            Tag {
+                address: 105,
+                ..Default::default()
+            },
+            Tag {
+                address: 0,
+                register_type: RegisterType::Inputs,
+                ..Default::default()
+            },
+            Tag {
+                device: "PLC-2".to_string(),
+                address: 500,
+                ..Default::default()
+            },
+        ];
+        let signals = vec![DigitalSignal {
+            source: BitSource::Coil(7),
+            ..Default::default()
+        }];
+        let device = &tags[0].device;
+        assert_eq!(
+            generate_blocks(&tags, &signals, device, 4),
+            vec![
+                ReadBlock::new(RegisterType::Coils, 7, 1),
+                ReadBlock::new(RegisterType::Inputs, 0, 2),
+                ReadBlock::new(RegisterType::Holding, 100, 7),
+            ]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use chrono::{DateTime, Local};
+use egui::Color32;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::codec::Value;
+use crate::health::Health;
+use crate::journal::Event;
+use crate::logger::LoggerStatus;
+use crate::scan::ScanStats;
+use crate::tags::Tag;
+use crate::writes::WriteResult;
+
+//#################################################### Messages of the background threads.
+
+/// The number of failed scans in a row after which the last values of a
+/// device are stale, unless its configuration says otherwise.
+pub const STALE_AFTER_SCANS: u32 = 2;
+
+/// How far a value read from a device can be trusted.
+#[derive(serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum Quality {
+    Good,
+    /// There is no usable value: the device answered without it, or it was
+    /// never read.
+    Bad,
+    /// The last good value, kept while the scans of the device fail.
+    Stale,
+    /// The last value, kept while the link to the device is down.
+    CommFail,
+}
+
+impl Quality {
+    /// The OPC DA quality code.
+    pub fn opc_code(&self) -> u8 {
+        match self {
+            Quality::Good => 0xC0,
+            Quality::Bad => 0x00,
+            // Uncertain, last usable value.
+            Quality::Stale => 0x44,
+            // Bad, comm failure.
+            Quality::CommFail => 0x18,
+        }
+    }
+
+    /// The colour of the value on the mimic, `None` when good.
+    pub fn color(&self) -> Option<Color32> {
+        match self {
+            Quality::Good => None,
+            Quality::Bad => Some(Color32::from_gray(110)),
+            Quality::Stale => Some(Color32::from_rgb(200, 120, 0)),
+            Quality::CommFail => Some(Color32::from_rgb(130, 0, 130)),
+        }
+    }
+}
+
+impl Default for Quality {
+    fn default() -> Self {
+        Self::Bad
+    }
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Good => write!(f, "Good"),
+            Quality::Bad => write!(f, "Bad"),
+            Quality::Stale => write!(f, "Stale"),
+            Quality::CommFail => write!(f, "Comm fail"),
+        }
+    }
+}
+
+/// A tag value stamped by the polling task.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Sample {
+    /// `None` when the value is bad.
+    pub value: Option<Value>,
+    pub quality: Quality,
+    /// When the device returned the value.
+    pub timestamp: DateTime<Local>,
+}
+
+/// The values of one scan of a device.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Snapshot {
+    pub device: String,
+    /// When the scan ended, whether it succeeded or not.
+    pub timestamp: DateTime<Local>,
+    /// Keyed by tag name.
+    pub samples: HashMap<String, Sample>,
+    /// The raw bit states of the signals of the device, keyed by label.
+    pub signal_states: HashMap<String, bool>,
+}
+
+impl Snapshot {
+    /// Stamps the values read from `device`. The tags of the device that the
+    /// scan didn't return are bad.
+    pub fn new(
+        device: &str,
+        tags: &[Tag],
+        values: HashMap<String, Value>,
+        signal_states: HashMap<String, bool>,
+    ) -> Self {
+        let timestamp = Local::now();
+        let missing: Vec<(String, Sample)> = tags
+            .iter()
+            .filter(|tag| tag.device == device && !values.contains_key(&tag.name))
+            .map(|tag| {
+                let sample = Sample {
+                    value: None,
+                    quality: Quality::Bad,
+                    timestamp,
+                };
+                (tag.name.clone(), sample)
+            })
+            .collect();
+        let good = values.into_iter().map(|(name, value)| {
+            let sample = Sample {
+                value: Some(value),
+                quality: Quality::Good,
+                timestamp,
+            };
+            (name, sample)
+        });
+        Self {
+            device: device.to_string(),
+            timestamp,
+            samples: good.chain(missing).collect(),
+            signal_states,
+        }
+    }
+
+    /// The last values again after a failed scan. The good values take
+    /// `quality`, and all of them are comm fail once the link is down. The
+    /// samples keep the time they were read at.
+    pub fn degraded(&self, quality: Quality) -> Self {
+        let mut snapshot = self.clone();
+        snapshot.timestamp = Local::now();
+        for sample in snapshot.samples.values_mut() {
+            if quality == Quality::CommFail || sample.quality == Quality::Good {
+                sample.quality = quality;
+            }
+        }
+        snapshot
+    }
+}
+
+/// The good values among `samples`, keyed by tag name.
+pub fn good_values(samples: &HashMap<String, Sample>) -> HashMap<String, Value> {
+    samples
+        .iter()
+        .filter(|(_, sample)| sample.quality == Quality::Good)
+        .filter_map(|(name, sample)| Some((name.clone(), sample.value.clone()?)))
+        .collect()
+}
+
+/// The link state and scan statistics of a device.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct DeviceStatus {
+    pub health: Health,
+    pub error_msg: String,
+    pub achieved_scan_time: u128,
+    /// The timing of each scan class of the device.
+    pub scan_stats: Vec<ScanStats>,
+}
+
+/// What the background threads tell the UI, in the order it happened.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Update {
+    Snapshot(Snapshot),
+    Status {
+        device: String,
+        status: DeviceStatus,
+    },
+    /// A connection event, comms error or write for the journal.
+    Event(Event),
+    WriteResult(WriteResult),
+    LoggerStatus(LoggerStatus),
+    HistorianError(Option<String>),
+    /// The HTTP server could not start, or stopped on an error.
+    ServerError(String),
+}
+
+/// What the UI hands the logger and historian threads.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ServiceCommand<C> {
+    Config(C),
+    Snapshot(Snapshot),
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_missing_from_a_scan_are_bad() {
+        let tags = vec![
+            Tag {
+                name: "PT1-1".to_string(),
+                ..Default::default()
+            },
+            Tag {
+                name: "PT1-2".to_string(),
+                ..Default::default()
+            },
+            Tag {
+                name: "PT2-1".to_string(),
+                device: "PLC-2".to_string(),
+                ..Default::default()
+            },
+        ];
+        let device = tags[0].device.clone();
+        let values = HashMap::from([("PT1-1".to_string(), Value::Number(52.5))]);
+        let snapshot = Snapshot::new(&device, &tags, values.clone(), HashMap::new());
+
+        assert_eq!(snapshot.samples.len(), 2);
+        assert_eq!(snapshot.samples["PT1-1"].quality, Quality::Good);
+        assert_eq!(snapshot.samples["PT1-2"].quality, Quality::Bad);
+        assert_eq!(snapshot.samples["PT1-2"].value, None);
+        assert_eq!(good_values(&snapshot.samples), values);
+    }
+
+    #[test]
+    fn failed_scans_degrade_the_last_values() {
+        let tags = vec![
+            Tag {
+                name: "PT1-1".to_string(),
+                ..Default::default()
+            },
+            Tag {
+                name: "PT1-2".to_string(),
+                ..Default::default()
+            },
+        ];
+        let device = tags[0].device.clone();
+        let values = HashMap::from([("PT1-1".to_string(), Value::Number(52.5))]);
+        let snapshot = Snapshot::new(&device, &tags, values, HashMap::new());
+
+        let stale = snapshot.degraded(Quality::Stale);
+        assert_eq!(stale.samples["PT1-1"].quality, Quality::Stale);
+        assert_eq!(stale.samples["PT1-1"].value, Some(Value::Number(52.5)));
+        assert_eq!(
+            stale.samples["PT1-1"].timestamp,
+            snapshot.samples["PT1-1"].timestamp
+        );
+        assert_eq!(stale.samples["PT1-2"].quality, Quality::Bad);
+        assert!(good_values(&stale.samples).is_empty());
+
+        let comm_fail = snapshot.degraded(Quality::CommFail);
+        assert!(comm_fail
+            .samples
+            .values()
+            .all(|sample| sample.quality == Quality::CommFail));
+    }
+}
@@ -0,0 +1,568 @@
+use std::fmt::Display;
+
+//#################################################### Register decoding.
+
+/// How a value is laid out in the registers of a field device.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub enum DataType {
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Float32,
+    Float64,
+    /// Four packed BCD digits in one register.
+    Bcd16,
+    /// Eight packed BCD digits in two registers.
+    Bcd32,
+    /// An ASCII string of the given number of characters, two per register.
+    Text(u16),
+}
+
+impl DataType {
+    /// The number of 16 bit registers the value spans.
+    pub fn register_count(&self) -> usize {
+        match self {
+            DataType::Int16 | DataType::UInt16 | DataType::Bcd16 => 1,
+            DataType::Int32 | DataType::UInt32 | DataType::Float32 | DataType::Bcd32 => 2,
+            DataType::Int64 | DataType::UInt64 | DataType::Float64 => 4,
+            DataType::Text(length) => (*length as usize + 1) / 2,
+        }
+    }
+
+    /// The data types offered in the tag editor, strings with a default length.
+    pub fn all() -> [DataType; 11] {
+        [
+            DataType::Int16,
+            DataType::UInt16,
+            DataType::Int32,
+            DataType::UInt32,
+            DataType::Int64,
+            DataType::UInt64,
+            DataType::Float32,
+            DataType::Float64,
+            DataType::Bcd16,
+            DataType::Bcd32,
+            DataType::Text(16),
+        ]
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, DataType::Text(_))
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Int16 => write!(f, "Int16"),
+            DataType::UInt16 => write!(f, "UInt16"),
+            DataType::Int32 => write!(f, "Int32"),
+            DataType::UInt32 => write!(f, "UInt32"),
+            DataType::Int64 => write!(f, "Int64"),
+            DataType::UInt64 => write!(f, "UInt64"),
+            DataType::Float32 => write!(f, "Float32"),
+            DataType::Float64 => write!(f, "Float64"),
+            DataType::Bcd16 => write!(f, "BCD16"),
+            DataType::Bcd32 => write!(f, "BCD32"),
+            DataType::Text(_) => write!(f, "String"),
+        }
+    }
+}
+
+impl Default for DataType {
+    fn default() -> Self {
+        Self::Float32
+    }
+}
+
+/// The byte and word order of a multi byte value, named after the position
+/// of the bytes `A B C D` of a big endian 32 bit value on the wire.
+///
+/// The word swap reverses the order of the registers and the byte swap swaps
+/// the two bytes of every register, so the same setting also applies to 16 and
+/// 64 bit values. Strings are only affected by the byte swap.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum ByteOrder {
+    Abcd,
+    Cdab,
+    Badc,
+    Dcba,
+}
+
+impl ByteOrder {
+    pub fn all() -> [ByteOrder; 4] {
+        [
+            ByteOrder::Abcd,
+            ByteOrder::Cdab,
+            ByteOrder::Badc,
+            ByteOrder::Dcba,
+        ]
+    }
+
+    fn word_swap(&self) -> bool {
+        matches!(self, ByteOrder::Cdab | ByteOrder::Dcba)
+    }
+
+    fn byte_swap(&self) -> bool {
+        matches!(self, ByteOrder::Badc | ByteOrder::Dcba)
+    }
+}
+
+impl Display for ByteOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteOrder::Abcd => write!(f, "ABCD (big endian)"),
+            ByteOrder::Cdab => write!(f, "CDAB (word swap)"),
+            ByteOrder::Badc => write!(f, "BADC (byte swap)"),
+            ByteOrder::Dcba => write!(f, "DCBA (little endian)"),
+        }
+    }
+}
+
+impl Default for ByteOrder {
+    fn default() -> Self {
+        Self::Abcd
+    }
+}
+
+/// A decoded register value.
+#[derive(serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+/// Decodes a value of `data_type` from the start of `registers`.
+/// Returns `None` if there are not enough registers or the value is not valid
+/// for its type, like a BCD nibble above 9.
+pub fn decode(registers: &[u16], data_type: &DataType, byte_order: ByteOrder) -> Option<Value> {
+    let words = registers.get(..data_type.register_count())?;
+
+    if let DataType::Text(length) = data_type {
+        let mut bytes = to_bytes(words, byte_order.byte_swap(), false);
+        bytes.truncate(*length as usize);
+        let text = bytes
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect::<String>();
+        return Some(Value::Text(text.trim_end().to_string()));
+    }
+
+    let bytes = to_bytes(words, byte_order.byte_swap(), byte_order.word_swap());
+    let value = match data_type {
+        DataType::Int16 => i16::from_be_bytes(bytes[..2].try_into().ok()?) as f64,
+        DataType::UInt16 => u16::from_be_bytes(bytes[..2].try_into().ok()?) as f64,
+        DataType::Int32 => i32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+        DataType::UInt32 => u32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+        DataType::Int64 => i64::from_be_bytes(bytes[..8].try_into().ok()?) as f64,
+        DataType::UInt64 => u64::from_be_bytes(bytes[..8].try_into().ok()?) as f64,
+        DataType::Float32 => f32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+        DataType::Float64 => f64::from_be_bytes(bytes[..8].try_into().ok()?),
+        DataType::Bcd16 | DataType::Bcd32 => bcd_to_number(&bytes)? as f64,
+        DataType::Text(_) => unreachable!(),
+    };
+    Some(Value::Number(value))
+}
+
+/// Lays the registers out as big endian bytes, undoing the device's swaps.
+fn to_bytes(words: &[u16], byte_swap: bool, word_swap: bool) -> Vec<u8> {
+    let mut words = words.to_vec();
+    if word_swap {
+        words.reverse();
+    }
+    words
+        .iter()
+        .map(|word| if byte_swap { word.swap_bytes() } else { *word })
+        .flat_map(|word| word.to_be_bytes())
+        .collect()
+}
+
+/// Encodes `value` into the registers of `data_type`, the inverse of [`decode`].
+/// Numbers are rounded for the integer types. Returns an error if the value
+/// doesn't fit the type.
+pub fn encode(
+    value: &Value,
+    data_type: &DataType,
+    byte_order: ByteOrder,
+) -> Result<Vec<u16>, String> {
+    let value = match (data_type, value) {
+        (DataType::Text(length), Value::Text(text)) => {
+            if !text.is_ascii() {
+                return Err("Only ASCII text can be written.".to_string());
+            }
+            if text.len() > *length as usize {
+                return Err(format!("The text is longer than {} characters.", length));
+            }
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.resize(data_type.register_count() * 2, 0);
+            return Ok(from_bytes(&bytes, byte_order.byte_swap(), false));
+        }
+        (DataType::Text(_), Value::Number(_)) => {
+            return Err("A string tag needs a text value.".to_string())
+        }
+        (_, Value::Text(_)) => return Err(format!("A {} tag needs a number.", data_type)),
+        (_, Value::Number(value)) if !value.is_finite() => {
+            return Err("The value is not a number.".to_string())
+        }
+        (_, Value::Number(value)) => *value,
+    };
+
+    let bytes = match data_type {
+        DataType::Int16 => (integer(value, data_type, i16::MIN as f64, i16::MAX as f64)? as i16)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::UInt16 => (integer(value, data_type, 0., u16::MAX as f64)? as u16)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::Int32 => (integer(value, data_type, i32::MIN as f64, i32::MAX as f64)? as i32)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::UInt32 => (integer(value, data_type, 0., u32::MAX as f64)? as u32)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::Int64 => (integer(value, data_type, i64::MIN as f64, i64::MAX as f64)? as i64)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::UInt64 => (integer(value, data_type, 0., u64::MAX as f64)? as u64)
+            .to_be_bytes()
+            .to_vec(),
+        DataType::Float32 => {
+            if value.abs() > f32::MAX as f64 {
+                return Err(format!("{} is out of range for a Float32 tag.", value));
+            }
+            (value as f32).to_be_bytes().to_vec()
+        }
+        DataType::Float64 => value.to_be_bytes().to_vec(),
+        DataType::Bcd16 => number_to_bcd(integer(value, data_type, 0., 9_999.)? as u64, 2),
+        DataType::Bcd32 => number_to_bcd(integer(value, data_type, 0., 99_999_999.)? as u64, 4),
+        DataType::Text(_) => unreachable!(),
+    };
+    Ok(from_bytes(
+        &bytes,
+        byte_order.byte_swap(),
+        byte_order.word_swap(),
+    ))
+}
+
+/// Rounds `value`, checking it fits the integer `data_type`.
+fn integer(value: f64, data_type: &DataType, min: f64, max: f64) -> Result<f64, String> {
+    let value = value.round();
+    if value < min || value > max {
+        return Err(format!(
+            "{} is out of range for a {} tag.",
+            value, data_type
+        ));
+    }
+    Ok(value)
+}
+
+/// Splits big endian bytes into registers, applying the device's swaps.
+fn from_bytes(bytes: &[u8], byte_swap: bool, word_swap: bool) -> Vec<u16> {
+    let mut words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .map(|word| if byte_swap { word.swap_bytes() } else { word })
+        .collect();
+    if word_swap {
+        words.reverse();
+    }
+    words
+}
+
+fn number_to_bcd(mut number: u64, length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    for byte in bytes.iter_mut().rev() {
+        let low = (number % 10) as u8;
+        let high = (number / 10 % 10) as u8;
+        *byte = high << 4 | low;
+        number /= 100;
+    }
+    bytes
+}
+
+fn bcd_to_number(bytes: &[u8]) -> Option<u64> {
+    let mut number = 0;
+    for byte in bytes {
+        for digit in [byte >> 4, byte & 0x0F] {
+            if digit > 9 {
+                return None;
+            }
+            number = number * 10 + digit as u64;
+        }
+    }
+    Some(number)
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lays big endian `bytes` out in registers the way a device using `order` sends them.
+    fn registers(bytes: &[u8], order: ByteOrder) -> Vec<u16> {
+        let mut words: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .map(|word| {
+                if order.byte_swap() {
+                    word.swap_bytes()
+                } else {
+                    word
+                }
+            })
+            .collect();
+        if order.word_swap() {
+            words.reverse();
+        }
+        words
+    }
+
+    fn number(bytes: &[u8], data_type: DataType, order: ByteOrder) -> f64 {
+        match decode(&registers(bytes, order), &data_type, order) {
+            Some(Value::Number(value)) => value,
+            other => panic!("{data_type} {order}: unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn byte_orders_of_a_32_bit_float() {
+        // 123.456 as a big endian f32 is 42 F6 E9 79.
+        let cases = [
+            (ByteOrder::Abcd, [0x42F6, 0xE979]),
+            (ByteOrder::Cdab, [0xE979, 0x42F6]),
+            (ByteOrder::Badc, [0xF642, 0x79E9]),
+            (ByteOrder::Dcba, [0x79E9, 0xF642]),
+        ];
+        for (order, words) in cases {
+            assert_eq!(
+                decode(&words, &DataType::Float32, order),
+                Some(Value::Number(123.456_f32 as f64)),
+                "{order}"
+            );
+        }
+    }
+
+    #[test]
+    fn int16_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&(-1234_i16).to_be_bytes(), DataType::Int16, order),
+                -1234.
+            );
+        }
+    }
+
+    #[test]
+    fn uint16_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&0xBEEF_u16.to_be_bytes(), DataType::UInt16, order),
+                48879.
+            );
+        }
+    }
+
+    #[test]
+    fn int32_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&(-123_456_789_i32).to_be_bytes(), DataType::Int32, order),
+                -123_456_789.
+            );
+        }
+    }
+
+    #[test]
+    fn uint32_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&3_000_000_000_u32.to_be_bytes(), DataType::UInt32, order),
+                3_000_000_000.
+            );
+        }
+    }
+
+    #[test]
+    fn int64_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(
+                    &(-1_234_567_890_123_i64).to_be_bytes(),
+                    DataType::Int64,
+                    order
+                ),
+                -1_234_567_890_123.
+            );
+        }
+    }
+
+    #[test]
+    fn uint64_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&9_876_543_210_u64.to_be_bytes(), DataType::UInt64, order),
+                9_876_543_210.
+            );
+        }
+    }
+
+    #[test]
+    fn float32_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&(-27.5_f32).to_be_bytes(), DataType::Float32, order),
+                -27.5
+            );
+        }
+    }
+
+    #[test]
+    fn float64_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&1013.25_f64.to_be_bytes(), DataType::Float64, order),
+                1013.25
+            );
+        }
+    }
+
+    #[test]
+    fn bcd16_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(number(&[0x12, 0x34], DataType::Bcd16, order), 1234.);
+        }
+    }
+
+    #[test]
+    fn bcd32_all_orders() {
+        for order in ByteOrder::all() {
+            assert_eq!(
+                number(&[0x87, 0x65, 0x43, 0x21], DataType::Bcd32, order),
+                87654321.
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_bcd_is_rejected() {
+        assert_eq!(decode(&[0x12A4], &DataType::Bcd16, ByteOrder::Abcd), None);
+    }
+
+    #[test]
+    fn text_all_orders() {
+        // Strings keep their register order, only the byte swap applies.
+        for order in ByteOrder::all() {
+            let words: Vec<u16> = b"PUMP-01\0"
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .map(|word| {
+                    if order.byte_swap() {
+                        word.swap_bytes()
+                    } else {
+                        word
+                    }
+                })
+                .collect();
+            assert_eq!(
+                decode(&words, &DataType::Text(8), order),
+                Some(Value::Text("PUMP-01".to_string())),
+                "{order}"
+            );
+        }
+    }
+
+    #[test]
+    fn odd_text_length_ignores_the_pad_byte() {
+        let words = [u16::from_be_bytes(*b"AB"), u16::from_be_bytes(*b"CX")];
+        assert_eq!(
+            decode(&words, &DataType::Text(3), ByteOrder::Abcd),
+            Some(Value::Text("ABC".to_string()))
+        );
+    }
+
+    #[test]
+    fn short_block_is_rejected() {
+        for data_type in DataType::all() {
+            let words = vec![0; data_type.register_count() - 1];
+            assert_eq!(
+                decode(&words, &data_type, ByteOrder::Abcd),
+                None,
+                "{data_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn encode_round_trips_all_types_and_orders() {
+        let cases = [
+            (DataType::Int16, -1234.),
+            (DataType::UInt16, 48879.),
+            (DataType::Int32, -123_456_789.),
+            (DataType::UInt32, 3_000_000_000.),
+            (DataType::Int64, -1_234_567_890_123.),
+            (DataType::UInt64, 9_876_543_210_987.),
+            (DataType::Float32, 123.456_f32 as f64),
+            (DataType::Float64, -0.000_123_456_789),
+            (DataType::Bcd16, 1234.),
+            (DataType::Bcd32, 12_345_678.),
+        ];
+        for order in ByteOrder::all() {
+            for (data_type, value) in cases.iter() {
+                let words = encode(&Value::Number(*value), data_type, order).unwrap();
+                assert_eq!(
+                    words.len(),
+                    data_type.register_count(),
+                    "{data_type} {order}"
+                );
+                assert_eq!(
+                    decode(&words, data_type, order),
+                    Some(Value::Number(*value)),
+                    "{data_type} {order}"
+                );
+            }
+            let text = Value::Text("PUMP".to_string());
+            let words = encode(&text, &DataType::Text(5), order).unwrap();
+            assert_eq!(words.len(), 3, "{order}");
+            assert_eq!(decode(&words, &DataType::Text(5), order), Some(text));
+        }
+    }
+
+    #[test]
+    fn encode_rounds_integers() {
+        assert_eq!(
+            encode(&Value::Number(41.6), &DataType::UInt16, ByteOrder::Abcd),
+            Ok(vec![42])
+        );
+    }
+
+    #[test]
+    fn encode_rejects_values_out_of_range() {
+        let rejected = [
+            (DataType::Int16, 32768.),
+            (DataType::UInt16, -1.),
+            (DataType::UInt32, 4_294_967_296.),
+            (DataType::Float32, 1e39),
+            (DataType::Bcd16, 10_000.),
+            (DataType::Bcd32, -1.),
+            (DataType::Float64, f64::NAN),
+        ];
+        for (data_type, value) in rejected {
+            assert!(
+                encode(&Value::Number(value), &data_type, ByteOrder::Abcd).is_err(),
+                "{data_type} {value}"
+            );
+        }
+        assert!(encode(
+            &Value::Text("TOO LONG".to_string()),
+            &DataType::Text(4),
+            ByteOrder::Abcd
+        )
+        .is_err());
+        assert!(encode(&Value::Number(1.), &DataType::Text(4), ByteOrder::Abcd).is_err());
+    }
+}
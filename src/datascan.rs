@@ -0,0 +1,531 @@
+use egui::{Button, ComboBox, DragValue, Grid, ScrollArea, TextEdit};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use crate::codec::Value;
+use crate::health::{Failure, ScanError};
+
+//#################################################### Datascan points.
+
+/// The channels of a Datascan module.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum PointKind {
+    AnalogueInput,
+    AnalogueOutput,
+    DigitalInput,
+    DigitalOutput,
+}
+
+impl PointKind {
+    pub fn all() -> [PointKind; 4] {
+        [
+            PointKind::AnalogueInput,
+            PointKind::AnalogueOutput,
+            PointKind::DigitalInput,
+            PointKind::DigitalOutput,
+        ]
+    }
+
+    fn read_command(&self) -> u8 {
+        match self {
+            PointKind::AnalogueInput => READ_AI,
+            PointKind::AnalogueOutput => READ_AO,
+            PointKind::DigitalInput => READ_DI,
+            PointKind::DigitalOutput => READ_DO,
+        }
+    }
+
+    fn is_digital(&self) -> bool {
+        matches!(self, PointKind::DigitalInput | PointKind::DigitalOutput)
+    }
+}
+
+impl Display for PointKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointKind::AnalogueInput => write!(f, "AI"),
+            PointKind::AnalogueOutput => write!(f, "AO"),
+            PointKind::DigitalInput => write!(f, "DI"),
+            PointKind::DigitalOutput => write!(f, "DO"),
+        }
+    }
+}
+
+/// A channel of a module, published under the name of a tag. Analogue
+/// channels give raw counts, scaled by the tag of the same name.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub struct DatascanPoint {
+    pub name: String,
+    /// The address of the module on the bus.
+    pub module: u8,
+    pub kind: PointKind,
+    pub channel: u8,
+}
+
+impl DatascanPoint {
+    fn new(name: &str, module: u8, kind: PointKind, channel: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            module,
+            kind,
+            channel,
+        }
+    }
+}
+
+pub fn default_datascan_points() -> Vec<DatascanPoint> {
+    vec![
+        DatascanPoint::new("DS-AI-1", 1, PointKind::AnalogueInput, 0),
+        DatascanPoint::new("DS-AI-2", 1, PointKind::AnalogueInput, 1),
+        DatascanPoint::new("DS-DI-1", 2, PointKind::DigitalInput, 0),
+    ]
+}
+//####################################################
+
+//#################################################### Datascan frames.
+//
+// Every frame starts with SOH, the module address and the command, and ends
+// with a longitudinal check byte, so that all the bytes of a frame sum to 0.
+//
+//   request:   SOH  module  command  channel  count  [data]  check
+//   response:  SOH  module  command  count    [data]         check
+//   exception: SOH  module  command | 0x80    code           check
+//
+// Analogue channels are signed 16 bit counts, high byte first. Digital
+// channels are packed 8 to a byte, the first channel in the lowest bit.
+//
+// This layout and the command codes below are a stand-in: they aren't taken
+// from the vendor specification and haven't been checked against frames
+// captured from a real module. Until they are, the driver only talks to the
+// `Simulator`, which implements the same layout, and never opens a serial port.
+
+const SOH: u8 = 0x01;
+const READ_AI: u8 = 0x10;
+const READ_AO: u8 = 0x11;
+const READ_DI: u8 = 0x12;
+const READ_DO: u8 = 0x13;
+const WRITE_AO: u8 = 0x20;
+const WRITE_DO: u8 = 0x21;
+const EXCEPTION: u8 = 0x80;
+/// The most channels a module answers in one frame.
+const MAX_CHANNELS: u8 = 32;
+
+fn check(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+fn frame(module: u8, command: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = vec![SOH, module, command];
+    frame.extend_from_slice(body);
+    frame.push(check(&frame));
+    frame
+}
+
+/// The number of data bytes of `count` channels.
+fn data_length(command: u8, count: u8) -> usize {
+    match command {
+        READ_AI | READ_AO => count as usize * 2,
+        READ_DI | READ_DO => (count as usize + 7) / 8,
+        _ => 0,
+    }
+}
+
+fn exception(code: u8) -> String {
+    match code {
+        0x01 => "Unknown command".to_string(),
+        0x02 => "Channel out of range".to_string(),
+        0x03 => "Module fault".to_string(),
+        code => format!("Exception {:#04x}", code),
+    }
+}
+
+/// Sends a request and returns the data of the answer.
+fn transact(
+    port: &mut (impl Read + Write),
+    module: u8,
+    command: u8,
+    body: &[u8],
+) -> Result<Vec<u8>, ScanError> {
+    port.write_all(&frame(module, command, body))
+        .and_then(|_| port.flush())
+        .map_err(|e| ScanError::from(&e))?;
+
+    let mut header = [0; 4];
+    port.read_exact(&mut header)
+        .map_err(|e| ScanError::from(&e))?;
+    if header[0] != SOH || header[1] != module {
+        return Err(ScanError::new(
+            Failure::Crc,
+            format!("Module {} sent a garbled frame.", module),
+        ));
+    }
+    let length = if header[2] == command | EXCEPTION {
+        0
+    } else if header[2] == command {
+        data_length(command, header[3])
+    } else {
+        return Err(ScanError::new(
+            Failure::Crc,
+            format!("Module {} answered another command.", module),
+        ));
+    };
+    let mut rest = vec![0; length + 1];
+    port.read_exact(&mut rest)
+        .map_err(|e| ScanError::from(&e))?;
+    let (check_byte, data) = rest.split_last().unwrap_or((&0, &[]));
+    let mut received = header.to_vec();
+    received.extend_from_slice(data);
+    if check(&received) != *check_byte {
+        return Err(ScanError::new(
+            Failure::Crc,
+            format!("Module {} sent a frame with a bad check.", module),
+        ));
+    }
+    if header[2] & EXCEPTION != 0 {
+        return Err(ScanError::new(
+            Failure::Exception,
+            format!("Module {}: {}.", module, exception(header[3])),
+        ));
+    }
+    Ok(data.to_vec())
+}
+//####################################################
+
+//#################################################### Datascan services.
+
+/// Reads all the points, one request per module and kind of channel,
+/// covering the channels in between.
+pub fn read_points(
+    port: &mut (impl Read + Write),
+    points: &[DatascanPoint],
+) -> Result<HashMap<String, Value>, ScanError> {
+    let mut groups: HashMap<(u8, PointKind), Vec<&DatascanPoint>> = HashMap::new();
+    for point in points.iter() {
+        groups
+            .entry((point.module, point.kind))
+            .or_default()
+            .push(point);
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_key(|((module, kind), _)| (*module, kind.read_command()));
+
+    let mut values = HashMap::new();
+    for ((module, kind), mut points) in groups {
+        points.sort_by_key(|point| point.channel);
+        let mut i = 0;
+        while i < points.len() {
+            let first = points[i].channel;
+            let block: Vec<&DatascanPoint> = points[i..]
+                .iter()
+                .take_while(|point| point.channel - first < MAX_CHANNELS)
+                .copied()
+                .collect();
+            i += block.len();
+            let count = block.last().map_or(1, |point| point.channel - first + 1);
+
+            let data = transact(port, module, kind.read_command(), &[first, count])?;
+            for point in block {
+                let index = (point.channel - first) as usize;
+                let value = if kind.is_digital() {
+                    data.get(index / 8)
+                        .map(|byte| (byte >> (index % 8) & 1) as f64)
+                } else {
+                    data.get(index * 2..index * 2 + 2)
+                        .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]) as f64)
+                };
+                if let Some(value) = value {
+                    values.insert(point.name.clone(), Value::Number(value));
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Writes the output point `name`, raw counts for an analogue output and 0
+/// or 1 for a digital one.
+pub fn write_point(
+    port: &mut (impl Read + Write),
+    points: &[DatascanPoint],
+    name: &str,
+    value: &Value,
+) -> Result<(), String> {
+    let point = points
+        .iter()
+        .find(|point| point.name == name)
+        .ok_or(format!("{} is not in the Datascan point list.", name))?;
+    let Value::Number(value) = value else {
+        return Err("A Datascan point needs a number.".to_string());
+    };
+    let (command, data) = match point.kind {
+        PointKind::AnalogueOutput => {
+            let v = value.round();
+            if !(i16::MIN as f64..=i16::MAX as f64).contains(&v) {
+                return Err(format!("{} is out of range for an analogue output.", v));
+            }
+            (WRITE_AO, (v as i16).to_be_bytes().to_vec())
+        }
+        PointKind::DigitalOutput => match *value {
+            v if v == 0.0 => (WRITE_DO, vec![0]),
+            v if v == 1.0 => (WRITE_DO, vec![1]),
+            v => return Err(format!("A digital output is 0 or 1, not {}.", v)),
+        },
+        kind => return Err(format!("{} is an input ({}).", point.name, kind)),
+    };
+    let mut body = vec![point.channel, 1];
+    body.extend_from_slice(&data);
+    transact(port, point.module, command, &body)?;
+    Ok(())
+}
+//####################################################
+
+//#################################################### Datascan simulator.
+
+/// A serial port, or the simulator.
+pub trait DatascanPort: Read + Write + Send {}
+
+impl<T: Read + Write + Send> DatascanPort for T {}
+
+/// The name of the port that talks to the simulator instead of a serial port.
+pub const SIMULATOR_PORT: &str = "Simulator";
+
+/// A bus of Datascan modules in memory, answering frames written to it in
+/// the stand-in layout above. Channels start at their number times 100, digital
+/// channels alternate, and outputs keep what was written to them.
+#[derive(Default)]
+pub struct Simulator {
+    channels: HashMap<(u8, PointKind, u8), i16>,
+    answer: VecDeque<u8>,
+}
+
+impl Simulator {
+    fn value(&self, module: u8, kind: PointKind, channel: u8) -> i16 {
+        self.channels
+            .get(&(module, kind, channel))
+            .copied()
+            .unwrap_or(if kind.is_digital() {
+                (channel % 2) as i16
+            } else {
+                channel as i16 * 100
+            })
+    }
+
+    /// Carries out a request, `None` if the frame is garbled.
+    fn answer(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let (&check_byte, request) = request.split_last()?;
+        if check(request) != check_byte {
+            return None;
+        }
+        let &[SOH, module, command, channel, count, ref data @ ..] = request else {
+            return None;
+        };
+        let kind = match command {
+            READ_AI => PointKind::AnalogueInput,
+            READ_AO | WRITE_AO => PointKind::AnalogueOutput,
+            READ_DI => PointKind::DigitalInput,
+            READ_DO | WRITE_DO => PointKind::DigitalOutput,
+            _ => return Some(frame(module, command | EXCEPTION, &[0x01])),
+        };
+        if count == 0 || count > MAX_CHANNELS || channel as u16 + count as u16 > 64 {
+            return Some(frame(module, command | EXCEPTION, &[0x02]));
+        }
+        let mut body = vec![count];
+        match (command, data) {
+            (WRITE_AO, &[high, low]) => {
+                let value = i16::from_be_bytes([high, low]);
+                self.channels.insert((module, kind, channel), value);
+            }
+            (WRITE_DO, &[state]) => {
+                self.channels.insert((module, kind, channel), state as i16);
+            }
+            (WRITE_AO | WRITE_DO, _) => {
+                return Some(frame(module, command | EXCEPTION, &[0x01]));
+            }
+            _ if kind.is_digital() => {
+                let mut bytes = vec![0u8; data_length(command, count)];
+                for i in 0..count {
+                    if self.value(module, kind, channel + i) != 0 {
+                        bytes[i as usize / 8] |= 1 << (i % 8);
+                    }
+                }
+                body.extend_from_slice(&bytes);
+            }
+            _ => {
+                for i in 0..count {
+                    body.extend_from_slice(&self.value(module, kind, channel + i).to_be_bytes());
+                }
+            }
+        }
+        Some(frame(module, command, &body))
+    }
+}
+
+impl Write for Simulator {
+    fn write(&mut self, request: &[u8]) -> io::Result<usize> {
+        if let Some(answer) = self.answer(request) {
+            self.answer.extend(answer);
+        }
+        Ok(request.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Simulator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.answer.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "No answer"));
+        }
+        let length = buf.len().min(self.answer.len());
+        for (byte, answer) in buf.iter_mut().zip(self.answer.drain(..length)) {
+            *byte = answer;
+        }
+        Ok(length)
+    }
+}
+//####################################################
+
+//#################################################### Datascan point list editor.
+
+/// Shows the Datascan point list. Returns true if it was changed.
+pub fn datascan_points_ui(ui: &mut egui::Ui, points: &mut Vec<DatascanPoint>) -> bool {
+    let before = points.clone();
+    let mut remove = None;
+
+    ScrollArea::vertical().max_height(250.).show(ui, |ui| {
+        Grid::new("datascan_points")
+            .num_columns(5)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Tag");
+                ui.strong("Module");
+                ui.strong("Type");
+                ui.strong("Channel");
+                ui.end_row();
+
+                for (i, point) in points.iter_mut().enumerate() {
+                    ui.add(TextEdit::singleline(&mut point.name).desired_width(80.));
+                    ui.add(DragValue::new(&mut point.module).clamp_range(1..=255));
+                    ComboBox::from_id_source(("datascan_point_kind", i))
+                        .selected_text(format!("{}", point.kind))
+                        .show_ui(ui, |ui| {
+                            for kind in PointKind::all() {
+                                ui.selectable_value(&mut point.kind, kind, format!("{}", kind));
+                            }
+                        });
+                    ui.add(DragValue::new(&mut point.channel).clamp_range(0..=63));
+                    if ui
+                        .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                        .on_hover_text("Remove point")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    if let Some(i) = remove {
+        points.remove(i);
+    }
+    if ui
+        .button(format!("{} Add point", egui_phosphor::regular::PLUS))
+        .clicked()
+    {
+        points.push(DatascanPoint::new(
+            "NEW-POINT",
+            1,
+            PointKind::AnalogueInput,
+            0,
+        ));
+    }
+
+    *points != before
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_sum_to_zero() {
+        let request = frame(3, READ_AI, &[0, 4]);
+        assert_eq!(&request[..5], &[SOH, 3, READ_AI, 0, 4]);
+        assert_eq!(
+            request
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+            0
+        );
+        assert_eq!(check(&request[..5]), request[5]);
+    }
+
+    #[test]
+    fn reads_points_from_the_simulator() {
+        let mut bus = Simulator::default();
+        let points = vec![
+            DatascanPoint::new("AI-0", 1, PointKind::AnalogueInput, 0),
+            DatascanPoint::new("AI-5", 1, PointKind::AnalogueInput, 5),
+            DatascanPoint::new("AI-40", 1, PointKind::AnalogueInput, 40),
+            DatascanPoint::new("DI-9", 2, PointKind::DigitalInput, 9),
+            DatascanPoint::new("DI-10", 2, PointKind::DigitalInput, 10),
+        ];
+        let values = read_points(&mut bus, &points).unwrap();
+        assert_eq!(values.len(), 5);
+        assert_eq!(values["AI-0"], Value::Number(0.));
+        assert_eq!(values["AI-5"], Value::Number(500.));
+        assert_eq!(values["AI-40"], Value::Number(4000.));
+        assert_eq!(values["DI-9"], Value::Number(1.));
+        assert_eq!(values["DI-10"], Value::Number(0.));
+    }
+
+    #[test]
+    fn writes_outputs_to_the_simulator() {
+        let mut bus = Simulator::default();
+        let points = vec![
+            DatascanPoint::new("AO", 4, PointKind::AnalogueOutput, 2),
+            DatascanPoint::new("DO", 4, PointKind::DigitalOutput, 3),
+            DatascanPoint::new("AI", 4, PointKind::AnalogueInput, 0),
+        ];
+        write_point(&mut bus, &points, "AO", &Value::Number(-1234.)).unwrap();
+        write_point(&mut bus, &points, "DO", &Value::Number(0.)).unwrap();
+        let values = read_points(&mut bus, &points).unwrap();
+        assert_eq!(values["AO"], Value::Number(-1234.));
+        assert_eq!(values["DO"], Value::Number(0.));
+
+        assert!(write_point(&mut bus, &points, "AI", &Value::Number(1.)).is_err());
+        assert!(write_point(&mut bus, &points, "DO", &Value::Number(2.)).is_err());
+    }
+
+    #[test]
+    fn reports_exceptions_and_timeouts() {
+        let mut bus = Simulator::default();
+        assert_eq!(
+            transact(&mut bus, 1, READ_AI, &[60, 8]),
+            Err(ScanError::new(
+                Failure::Exception,
+                "Module 1: Channel out of range."
+            ))
+        );
+        assert_eq!(
+            transact(&mut bus, 1, 0x7F, &[0, 1]),
+            Err(ScanError::new(
+                Failure::Exception,
+                "Module 1: Unknown command."
+            ))
+        );
+
+        // A frame with a bad check is ignored, like on the bus.
+        bus.write_all(&[SOH, 1, READ_AI, 0, 1, 0]).unwrap();
+        let mut answer = [0; 4];
+        let error = bus.read_exact(&mut answer).unwrap_err();
+        assert_eq!(ScanError::from(&error).failure, Failure::Timeout);
+    }
+}
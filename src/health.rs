@@ -0,0 +1,290 @@
+use egui::Color32;
+use std::fmt::Display;
+use std::io;
+use std::time::Duration;
+
+//#################################################### Link supervision.
+
+/// The number of failed scans in a row after which the link is reopened.
+const MAX_FAILED_SCANS: u32 = 3;
+
+/// The state of the link to a device, as shown in the status bar.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LinkState {
+    /// Opening the port or socket.
+    Connecting,
+    Online,
+    /// Connected, but the last scans failed.
+    Degraded,
+    /// The link is down and waits for the next connection attempt.
+    Offline,
+}
+
+impl LinkState {
+    pub fn color(&self) -> Color32 {
+        match self {
+            LinkState::Connecting => Color32::GRAY,
+            LinkState::Online => Color32::DARK_GREEN,
+            LinkState::Degraded => Color32::from_rgb(200, 120, 0),
+            LinkState::Offline => Color32::DARK_RED,
+        }
+    }
+}
+
+impl Display for LinkState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkState::Connecting => write!(f, "Connecting"),
+            LinkState::Online => write!(f, "Online"),
+            LinkState::Degraded => write!(f, "Degraded"),
+            LinkState::Offline => write!(f, "Offline"),
+        }
+    }
+}
+
+/// What a failed request ran into, as reported by the driver.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Failure {
+    Timeout,
+    /// A garbled answer: bad check, short or unexpected frame.
+    Crc,
+    /// The device answered with an exception, so the link itself is fine.
+    Exception,
+    Other,
+}
+
+impl From<io::ErrorKind> for Failure {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::Timeout,
+            io::ErrorKind::InvalidData => Failure::Crc,
+            _ => Failure::Other,
+        }
+    }
+}
+
+/// A failed scan: what it ran into and the message for the journal.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScanError {
+    pub failure: Failure,
+    pub message: String,
+}
+
+impl ScanError {
+    pub fn new(failure: Failure, message: impl Into<String>) -> Self {
+        Self {
+            failure,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<&io::Error> for ScanError {
+    fn from(error: &io::Error) -> Self {
+        Self::new(error.kind().into(), error.to_string())
+    }
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Lets the write paths, which report plain messages, use `?` on the scan helpers.
+impl From<ScanError> for String {
+    fn from(error: ScanError) -> Self {
+        error.message
+    }
+}
+
+/// The error counters of a device since it was connected from the options.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct CommStats {
+    pub timeouts: u32,
+    pub crc_errors: u32,
+    pub exceptions: u32,
+    pub other_errors: u32,
+    pub reconnects: u32,
+}
+
+impl Display for CommStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "TO {}  CRC {}  EX {}  ERR {}  RECON {}",
+            self.timeouts, self.crc_errors, self.exceptions, self.other_errors, self.reconnects
+        )
+    }
+}
+
+/// The health of the link to a device, kept by its polling task.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Health {
+    pub state: LinkState,
+    pub stats: CommStats,
+    /// Failed scans since the last good one.
+    failed_scans: u32,
+    /// Whether the link was ever opened, so the next opening is a reconnection.
+    was_connected: bool,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            state: LinkState::Connecting,
+            stats: CommStats::default(),
+            failed_scans: 0,
+            was_connected: false,
+        }
+    }
+}
+
+impl Health {
+    pub fn connecting(&mut self) {
+        self.state = LinkState::Connecting;
+    }
+
+    pub fn connected(&mut self) {
+        if self.was_connected {
+            self.stats.reconnects += 1;
+        }
+        self.was_connected = true;
+        self.failed_scans = 0;
+        self.state = LinkState::Online;
+    }
+
+    pub fn connect_failed(&mut self) {
+        self.state = LinkState::Offline;
+    }
+
+    pub fn scan_ok(&mut self) {
+        self.failed_scans = 0;
+        self.state = LinkState::Online;
+    }
+
+    /// Counts a failed scan. Returns true when the link is deemed lost and
+    /// should be reopened.
+    pub fn scan_failed(&mut self, failure: Failure) -> bool {
+        match failure {
+            Failure::Timeout => self.stats.timeouts += 1,
+            Failure::Crc => self.stats.crc_errors += 1,
+            Failure::Exception => self.stats.exceptions += 1,
+            Failure::Other => self.stats.other_errors += 1,
+        }
+        // An exception is an answer: the device is there, only the request is wrong.
+        if failure != Failure::Exception {
+            self.failed_scans += 1;
+        }
+        if self.failed_scans >= MAX_FAILED_SCANS {
+            self.state = LinkState::Offline;
+            true
+        } else {
+            self.state = LinkState::Degraded;
+            false
+        }
+    }
+}
+
+/// The delay before the next connection attempt, doubled after each failure.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over from the shortest delay once the device answers again.
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+/// Starts the name of every comm fail alarm, so none can be taken for a
+/// digital signal such as "PLC-1 COM FAIL".
+pub const COMM_FAIL_ALARM_PREFIX: &str = "DEVICE ";
+
+/// The name of the alarm raised while the link to `device` is down, for
+/// example "DEVICE PLC-1 COMM FAIL".
+pub fn comm_fail_alarm(device: &str) -> String {
+    format!("{}{} COMM FAIL", COMM_FAIL_ALARM_PREFIX, device)
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn failures_are_told_apart_by_the_error_kind() {
+        assert_eq!(Failure::from(io::ErrorKind::TimedOut), Failure::Timeout);
+        assert_eq!(Failure::from(io::ErrorKind::WouldBlock), Failure::Timeout);
+        assert_eq!(Failure::from(io::ErrorKind::InvalidData), Failure::Crc);
+        assert_eq!(
+            Failure::from(io::ErrorKind::ConnectionReset),
+            Failure::Other
+        );
+        let error = io::Error::new(io::ErrorKind::TimedOut, "no answer");
+        assert_eq!(
+            ScanError::from(&error),
+            ScanError::new(Failure::Timeout, "no answer")
+        );
+    }
+
+    #[test]
+    fn the_link_is_reopened_after_repeated_failures() {
+        let mut health = Health::default();
+        health.connected();
+        assert!(!health.scan_failed(Failure::Timeout));
+        assert_eq!(health.state, LinkState::Degraded);
+        // Exceptions never take the link down.
+        assert!(!health.scan_failed(Failure::Exception));
+        assert!(!health.scan_failed(Failure::Timeout));
+        assert!(health.scan_failed(Failure::Other));
+        assert_eq!(health.state, LinkState::Offline);
+
+        health.connected();
+        health.scan_ok();
+        assert_eq!(health.state, LinkState::Online);
+        assert_eq!(
+            health.stats,
+            CommStats {
+                timeouts: 2,
+                crc_errors: 0,
+                exceptions: 1,
+                other_errors: 1,
+                reconnects: 1,
+            }
+        );
+    }
+}
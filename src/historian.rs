@@ -0,0 +1,560 @@
+use chrono::{DateTime, Local};
+use egui::{Color32, DragValue, Grid};
+use redb::{Database, ReadableTable, TableDefinition};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use crate::codec::Value;
+
+//#################################################### Historian configuration.
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct HistorianConfig {
+    pub enabled: bool,
+    /// Milliseconds between two raw samples of a tag.
+    pub interval: u64,
+    /// How long each table keeps its rows, in days. 0 keeps them forever.
+    pub raw_days: u32,
+    pub minute_days: u32,
+    pub hour_days: u32,
+}
+
+impl Default for HistorianConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 1000,
+            raw_days: 7,
+            minute_days: 90,
+            hour_days: 730,
+        }
+    }
+}
+
+/// The resolution a query is answered at.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    Raw,
+    Minute,
+    Hour,
+    /// The finest resolution that keeps the number of points reasonable.
+    #[default]
+    Auto,
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::Raw => write!(f, "Raw"),
+            Aggregation::Minute => write!(f, "1 min"),
+            Aggregation::Hour => write!(f, "1 h"),
+            Aggregation::Auto => write!(f, "Auto"),
+        }
+    }
+}
+
+impl Aggregation {
+    /// Resolves `Auto` for a query over `span` seconds.
+    fn resolve(self, span: f64) -> Self {
+        match self {
+            Aggregation::Auto if span <= 2. * 3600. => Aggregation::Raw,
+            Aggregation::Auto if span <= 2. * 86400. => Aggregation::Minute,
+            Aggregation::Auto => Aggregation::Hour,
+            aggregation => aggregation,
+        }
+    }
+}
+
+/// A value of a query result. Raw samples have the same min, max and average.
+#[derive(serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub struct Point {
+    /// Seconds since the Unix epoch, the start of the bucket for rollups.
+    pub time: f64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: u64,
+}
+//####################################################
+
+//#################################################### The historian.
+
+/// Raw samples keyed by tag and milliseconds since the Unix epoch.
+const RAW: TableDefinition<'_, (&str, i64), f64> = TableDefinition::new("raw");
+/// Rollups keyed by tag and bucket start, holding the min, max, sum and count.
+const MINUTE: TableDefinition<'_, (&str, i64), (f64, f64, f64, u64)> =
+    TableDefinition::new("minute");
+const HOUR: TableDefinition<'_, (&str, i64), (f64, f64, f64, u64)> = TableDefinition::new("hour");
+/// Every tag ever recorded, so the retention can purge them one by one.
+const TAGS: TableDefinition<'_, &str, ()> = TableDefinition::new("tags");
+
+const MINUTE_MS: i64 = 60_000;
+const HOUR_MS: i64 = 3_600_000;
+const DAY_MS: i64 = 86_400_000;
+
+/// Stores the tag values with their 1 minute and 1 hour rollups in a single
+/// database file. It is shared between the recording thread, the trend and
+/// the HTTP server.
+pub struct Historian {
+    db: Database,
+    path: PathBuf,
+}
+
+impl Historian {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let db =
+            Database::create(path).map_err(|e| format!("Could not open the historian. {}", e))?;
+        Ok(Self {
+            db,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records the numeric `values` sampled at `time` and updates their rollups.
+    pub fn record(
+        &self,
+        time: DateTime<Local>,
+        values: &HashMap<String, Value>,
+    ) -> Result<(), String> {
+        self.try_record(time, values)
+            .map_err(|e| format!("Could not write the historian. {}", e))
+    }
+
+    fn try_record(
+        &self,
+        time: DateTime<Local>,
+        values: &HashMap<String, Value>,
+    ) -> Result<(), redb::Error> {
+        let ms = time.timestamp_millis();
+        let txn = self.db.begin_write()?;
+        {
+            let mut raw = txn.open_table(RAW)?;
+            let mut minute = txn.open_table(MINUTE)?;
+            let mut hour = txn.open_table(HOUR)?;
+            let mut tags = txn.open_table(TAGS)?;
+            for (tag, value) in values.iter() {
+                let Value::Number(value) = value else {
+                    continue;
+                };
+                if !value.is_finite() {
+                    continue;
+                }
+                raw.insert((tag.as_str(), ms), *value)?;
+                for (table, bucket) in [(&mut minute, MINUTE_MS), (&mut hour, HOUR_MS)] {
+                    let key = (tag.as_str(), ms - ms.rem_euclid(bucket));
+                    let rollup = match table.get(key)? {
+                        Some(old) => {
+                            let (min, max, sum, count) = old.value();
+                            (min.min(*value), max.max(*value), sum + value, count + 1)
+                        }
+                        None => (*value, *value, *value, 1),
+                    };
+                    table.insert(key, rollup)?;
+                }
+                tags.insert(tag.as_str(), ())?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Deletes the rows older than the retention of each table.
+    pub fn purge(&self, now: DateTime<Local>, config: &HistorianConfig) -> Result<(), String> {
+        self.try_purge(now, config)
+            .map_err(|e| format!("Could not purge the historian. {}", e))
+    }
+
+    fn try_purge(&self, now: DateTime<Local>, config: &HistorianConfig) -> Result<(), redb::Error> {
+        let now = now.timestamp_millis();
+        let txn = self.db.begin_write()?;
+        {
+            let tags: Vec<String> = txn
+                .open_table(TAGS)?
+                .iter()?
+                .filter_map(|entry| entry.ok())
+                .map(|(tag, _)| tag.value().to_string())
+                .collect();
+            let mut raw = txn.open_table(RAW)?;
+            let mut minute = txn.open_table(MINUTE)?;
+            let mut hour = txn.open_table(HOUR)?;
+            for tag in tags.iter() {
+                let tag = tag.as_str();
+                if config.raw_days > 0 {
+                    let cutoff = now - config.raw_days as i64 * DAY_MS;
+                    raw.retain_in((tag, i64::MIN)..(tag, cutoff), |_, _| false)?;
+                }
+                for (table, days) in [
+                    (&mut minute, config.minute_days),
+                    (&mut hour, config.hour_days),
+                ] {
+                    if days > 0 {
+                        let cutoff = now - days as i64 * DAY_MS;
+                        table.retain_in((tag, i64::MIN)..(tag, cutoff), |_, _| false)?;
+                    }
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// The history of `tag` between the `start` and `end` timestamps, in
+    /// seconds since the Unix epoch, oldest first.
+    pub fn query(
+        &self,
+        tag: &str,
+        start: f64,
+        end: f64,
+        aggregation: Aggregation,
+    ) -> Result<Vec<Point>, String> {
+        self.try_query(tag, start, end, aggregation)
+            .map_err(|e| format!("Could not query the historian. {}", e))
+    }
+
+    fn try_query(
+        &self,
+        tag: &str,
+        start: f64,
+        end: f64,
+        aggregation: Aggregation,
+    ) -> Result<Vec<Point>, redb::Error> {
+        let start = (start * 1000.) as i64;
+        let end = (end * 1000.) as i64;
+        let txn = self.db.begin_read()?;
+        let rollups = match aggregation.resolve((end - start) as f64 / 1000.) {
+            Aggregation::Minute => (MINUTE, MINUTE_MS),
+            Aggregation::Hour => (HOUR, HOUR_MS),
+            _ => {
+                let raw = match txn.open_table(RAW) {
+                    Ok(table) => table,
+                    // Nothing was recorded yet.
+                    Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut points = Vec::new();
+                for entry in raw.range((tag, start)..=(tag, end))? {
+                    let (key, value) = entry?;
+                    let value = value.value();
+                    points.push(Point {
+                        time: key.value().1 as f64 / 1000.,
+                        min: value,
+                        max: value,
+                        avg: value,
+                        count: 1,
+                    });
+                }
+                return Ok(points);
+            }
+        };
+
+        let (definition, bucket) = rollups;
+        let table = match txn.open_table(definition) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        // The bucket holding `start` is included.
+        let first = start - start.rem_euclid(bucket);
+        let mut points = Vec::new();
+        for entry in table.range((tag, first)..=(tag, end))? {
+            let (key, value) = entry?;
+            let (min, max, sum, count) = value.value();
+            points.push(Point {
+                time: key.value().1 as f64 / 1000.,
+                min,
+                max,
+                avg: sum / count.max(1) as f64,
+                count,
+            });
+        }
+        Ok(points)
+    }
+}
+
+/// A tag and a time range, in steps of a hundredth of its span.
+type RangeKey = (String, i64, i64);
+
+/// The query of the current time range, so the trend doesn't hit the database
+/// every frame. Queries run on their own thread, the last answer is shown
+/// meanwhile.
+#[derive(Debug, Default)]
+pub struct HistoryCache {
+    key: Option<RangeKey>,
+    points: Vec<Point>,
+    pending: Option<PendingQuery>,
+    /// The error of the last query.
+    error: Option<String>,
+}
+
+/// A query running, with the range it is for.
+#[derive(Debug)]
+struct PendingQuery {
+    key: RangeKey,
+    answer: Receiver<Result<Vec<Point>, String>>,
+}
+
+impl HistoryCache {
+    /// The history of `tag` between `start` and `end`, queried again once the
+    /// range moved by more than a hundredth of its span.
+    pub fn get(&mut self, historian: &Arc<Historian>, tag: &str, start: f64, end: f64) -> &[Point] {
+        if let Some(query) = self.pending.take() {
+            match query.answer.try_recv() {
+                Ok(result) => {
+                    match result {
+                        Ok(points) => {
+                            self.points = points;
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                    self.key = Some(query.key);
+                }
+                // One query at a time, the range is checked again once it's done.
+                Err(TryRecvError::Empty) => {
+                    self.pending = Some(query);
+                    return &self.points;
+                }
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+
+        let step = ((end - start) / 100.).max(1.);
+        let key = (
+            tag.to_string(),
+            (start / step).floor() as i64,
+            (end / step).ceil() as i64,
+        );
+        if self.key.as_ref() != Some(&key) {
+            let (sender, answer) = channel();
+            let historian = Arc::clone(historian);
+            let tag = tag.to_string();
+            thread::spawn(move || {
+                let _ =
+                    sender.send(historian.query(&tag, start - step, end + step, Aggregation::Auto));
+            });
+            self.pending = Some(PendingQuery { key, answer });
+        }
+        &self.points
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+//####################################################
+
+//#################################################### Historian settings window.
+
+/// Shows the historian settings. Returns true if the configuration was changed.
+pub fn historian_ui(
+    ui: &mut egui::Ui,
+    config: &mut HistorianConfig,
+    historian: Option<&Result<Arc<Historian>, String>>,
+    error: Option<&String>,
+) -> bool {
+    let before = config.clone();
+
+    match historian {
+        Some(Ok(historian)) => {
+            let size = std::fs::metadata(historian.path())
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            ui.colored_label(
+                Color32::GRAY,
+                format!(
+                    "{}: {:.1} MB",
+                    historian.path().display(),
+                    size as f64 / 1_000_000.
+                ),
+            );
+        }
+        Some(Err(error)) => {
+            ui.colored_label(Color32::DARK_RED, error);
+        }
+        None => {}
+    }
+
+    Grid::new("historian_config").num_columns(2).show(ui, |ui| {
+        ui.label("Recording");
+        ui.checkbox(&mut config.enabled, "Enabled");
+        ui.end_row();
+
+        ui.label("Sample interval");
+        ui.add(
+            DragValue::new(&mut config.interval)
+                .clamp_range(100..=3_600_000)
+                .suffix(" ms"),
+        );
+        ui.end_row();
+
+        for (label, days) in [
+            ("Keep raw samples", &mut config.raw_days),
+            ("Keep 1 min rollups", &mut config.minute_days),
+            ("Keep 1 h rollups", &mut config.hour_days),
+        ] {
+            ui.label(label);
+            ui.add(DragValue::new(days).clamp_range(0..=36_500).suffix(" days"))
+                .on_hover_text("0 keeps everything");
+            ui.end_row();
+        }
+    });
+
+    if let Some(error) = error {
+        ui.separator();
+        ui.colored_label(Color32::DARK_RED, error);
+    }
+
+    *config != before
+}
+
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The start of an hour, in seconds since the Unix epoch.
+    const BASE: i64 = 1_699_999_200;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    fn values(value: f64) -> HashMap<String, Value> {
+        HashMap::from([("PT1-1".to_string(), Value::Number(value))])
+    }
+
+    fn times(points: &[Point]) -> Vec<f64> {
+        points.iter().map(|point| point.time).collect()
+    }
+
+    #[test]
+    fn samples_are_rolled_up_by_minute_and_hour() {
+        let directory = tempfile::tempdir().unwrap();
+        let historian = Historian::open(&directory.path().join("HISTORIAN.db")).unwrap();
+        for (offset, value) in [(0, 1.), (30, 3.), (60, 5.), (3600, 7.)] {
+            historian.record(at(BASE + offset), &values(value)).unwrap();
+        }
+        // Text and values that aren't finite are left out.
+        let others = HashMap::from([
+            ("PT1-1".to_string(), Value::Number(f64::NAN)),
+            ("Message".to_string(), Value::Text("Pump on".to_string())),
+        ]);
+        historian.record(at(BASE + 90), &others).unwrap();
+
+        let (start, end) = (BASE as f64, (BASE + 3600) as f64);
+        let raw = historian
+            .query("PT1-1", start, end, Aggregation::Raw)
+            .unwrap();
+        let averages: Vec<f64> = raw.iter().map(|point| point.avg).collect();
+        assert_eq!(averages, [1., 3., 5., 7.]);
+
+        let minutes = historian
+            .query("PT1-1", start, end, Aggregation::Minute)
+            .unwrap();
+        assert_eq!(times(&minutes), [start, start + 60., end]);
+        assert_eq!(
+            minutes[0],
+            Point {
+                time: start,
+                min: 1.,
+                max: 3.,
+                avg: 2.,
+                count: 2,
+            }
+        );
+
+        let hours = historian
+            .query("PT1-1", start, end, Aggregation::Hour)
+            .unwrap();
+        assert_eq!(times(&hours), [start, end]);
+        assert_eq!((hours[0].min, hours[0].max, hours[0].avg), (1., 5., 3.));
+        assert_eq!((hours[0].count, hours[1].count), (3, 1));
+
+        // The bucket holding the start of the range is part of it.
+        let minutes = historian
+            .query("PT1-1", start + 30., start + 59., Aggregation::Minute)
+            .unwrap();
+        assert_eq!(times(&minutes), [start]);
+        // A short span is answered with the raw samples.
+        let auto = historian
+            .query("PT1-1", start, start + 120., Aggregation::Auto)
+            .unwrap();
+        assert_eq!(auto.len(), 3);
+        assert!(historian
+            .query("Message", start, end, Aggregation::Raw)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn purge_keeps_each_table_for_its_retention() {
+        let directory = tempfile::tempdir().unwrap();
+        let historian = Historian::open(&directory.path().join("HISTORIAN.db")).unwrap();
+        let now = BASE + 10 * 86400;
+        historian.record(at(now - 2 * 86400), &values(1.)).unwrap();
+        historian.record(at(now), &values(2.)).unwrap();
+
+        let mut config = HistorianConfig {
+            raw_days: 1,
+            minute_days: 3,
+            hour_days: 0,
+            ..Default::default()
+        };
+        historian.purge(at(now), &config).unwrap();
+        let count = |aggregation| {
+            historian
+                .query("PT1-1", BASE as f64, now as f64, aggregation)
+                .unwrap()
+                .len()
+        };
+        assert_eq!(count(Aggregation::Raw), 1);
+        assert_eq!(count(Aggregation::Minute), 2);
+        assert_eq!(count(Aggregation::Hour), 2);
+
+        config.minute_days = 1;
+        historian.purge(at(now), &config).unwrap();
+        assert_eq!(count(Aggregation::Minute), 1);
+        assert_eq!(count(Aggregation::Hour), 2);
+    }
+
+    #[test]
+    fn the_cache_answers_once_the_query_is_done() {
+        let directory = tempfile::tempdir().unwrap();
+        let historian = Historian::open(&directory.path().join("HISTORIAN.db")).unwrap();
+        let historian = Arc::new(historian);
+        historian.record(at(BASE), &values(1.)).unwrap();
+
+        let mut cache = HistoryCache::default();
+        let (start, end) = ((BASE - 60) as f64, (BASE + 60) as f64);
+        assert!(cache.get(&historian, "PT1-1", start, end).is_empty());
+        for _ in 0..500 {
+            if !cache.is_pending() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+            cache.get(&historian, "PT1-1", start, end);
+        }
+        assert_eq!(
+            times(cache.get(&historian, "PT1-1", start, end)),
+            [BASE as f64]
+        );
+        assert_eq!(cache.error(), None);
+    }
+}
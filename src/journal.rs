@@ -0,0 +1,700 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use egui::{Color32, ComboBox, DragValue, Grid, RichText, ScrollArea, TextEdit};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::signals::Severity;
+
+//#################################################### Events.
+
+/// The user recorded for events raised by Carbon itself.
+const SYSTEM_USER: &str = "system";
+
+/// An entry of the event journal.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Event {
+    pub time: DateTime<Local>,
+    pub kind: EventKind,
+    /// The alarm, tag or device the event is about.
+    pub source: String,
+    pub severity: Option<Severity>,
+    pub message: String,
+    pub user: String,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, source: &str, severity: Option<Severity>, message: &str) -> Self {
+        Self {
+            time: Local::now(),
+            kind,
+            source: source.to_string(),
+            severity,
+            message: message.to_string(),
+            user: SYSTEM_USER.to_string(),
+        }
+    }
+
+    /// Marks the event as an action of the logged in operator.
+    pub fn by_operator(mut self) -> Self {
+        self.user = operator();
+        self
+    }
+
+    /// One tab separated line of the journal file, without the line ending.
+    fn to_line(&self) -> String {
+        [
+            self.time.to_rfc3339(),
+            self.kind.to_string(),
+            self.source.clone(),
+            self.severity
+                .map(|severity| severity.to_string())
+                .unwrap_or_default(),
+            self.user.clone(),
+            self.message.clone(),
+        ]
+        .iter()
+        .map(|field| field.replace(['\t', '\r', '\n'], " "))
+        .collect::<Vec<_>>()
+        .join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, '\t');
+        let time = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        let kind = fields.next()?;
+        let kind = EventKind::all()
+            .into_iter()
+            .find(|k| k.to_string() == kind)?;
+        let source = fields.next()?.to_string();
+        let severity = fields.next()?;
+        let severity = Severity::all()
+            .into_iter()
+            .find(|s| s.to_string() == severity);
+        let user = fields.next()?.to_string();
+        let message = fields.next()?.to_string();
+        Some(Self {
+            time: time.with_timezone(&Local),
+            kind,
+            source,
+            severity,
+            message,
+            user,
+        })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum EventKind {
+    Alarm,
+    Acknowledge,
+    Shelve,
+    Write,
+    Connection,
+    CommsError,
+}
+
+impl EventKind {
+    pub fn all() -> [EventKind; 6] {
+        [
+            EventKind::Alarm,
+            EventKind::Acknowledge,
+            EventKind::Shelve,
+            EventKind::Write,
+            EventKind::Connection,
+            EventKind::CommsError,
+        ]
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Alarm => write!(f, "Alarm"),
+            EventKind::Acknowledge => write!(f, "Acknowledge"),
+            EventKind::Shelve => write!(f, "Shelve"),
+            EventKind::Write => write!(f, "Write"),
+            EventKind::Connection => write!(f, "Connection"),
+            EventKind::CommsError => write!(f, "Comms error"),
+        }
+    }
+}
+
+/// The name of the user running Carbon.
+pub fn operator() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "operator".to_string())
+}
+//####################################################
+
+//#################################################### The journal.
+
+/// The size past which the journal file is set aside and a new one started.
+const MAX_FILE_BYTES: u64 = 8 * 1024 * 1024;
+/// The number of events kept in memory, the oldest being dropped first.
+const MAX_EVENTS: usize = 200_000;
+
+/// The append-only event journal, mirrored to a file with its latest events
+/// kept in memory.
+///
+/// Once the file grows past `MAX_FILE_BYTES` it is set aside under the next
+/// free index, "JOURNAL.1.txt", "JOURNAL.2.txt" and so on. Set aside files are
+/// only deleted when `retained_files` is not 0.
+pub struct Journal {
+    path: PathBuf,
+    events: Vec<Event>,
+    max_file_bytes: u64,
+    max_events: usize,
+    /// The number of set aside files kept, the oldest being deleted first.
+    /// 0 keeps them all.
+    retained_files: usize,
+    /// Whether older events are on disk but not in memory.
+    partial: bool,
+    /// The last error met while writing the file.
+    error: Option<String>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::open(Path::new("./JOURNAL.txt"))
+    }
+}
+
+impl Journal {
+    /// Loads the latest events of the journal at `path` and of the files set
+    /// aside before it, skipping the lines that can't be parsed.
+    pub fn open(path: &Path) -> Self {
+        let mut journal = Self {
+            path: path.to_path_buf(),
+            events: Vec::new(),
+            max_file_bytes: MAX_FILE_BYTES,
+            max_events: MAX_EVENTS,
+            retained_files: 0,
+            partial: false,
+            error: None,
+        };
+        let mut files = journal.files();
+        while journal.events.len() < journal.max_events {
+            let Some(file) = files.pop() else {
+                break;
+            };
+            let mut events = read_file(&file).unwrap_or_default();
+            events.append(&mut journal.events);
+            journal.events = events;
+        }
+        journal.partial = !files.is_empty();
+        journal.trim();
+        journal
+    }
+
+    /// Appends an event to the journal file.
+    pub fn record(&mut self, event: Event) {
+        let result = self.rotate().and_then(|_| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)?
+                .write_all(format!("{}\r\n", event.to_line()).as_bytes())
+        });
+        if let Err(e) = result {
+            self.error = Some(format!("Could not write the journal. {}", e));
+        }
+        self.events.push(event);
+        self.trim();
+    }
+
+    /// The latest events, all of them when `is_partial` is false.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Whether older events are only in the files on disk.
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    /// Sets the number of set aside files kept, 0 keeps them all. The extra
+    /// files are deleted on the next rotation.
+    pub fn set_retained_files(&mut self, retained_files: usize) {
+        self.retained_files = retained_files;
+    }
+
+    /// Reads every event of the journal files on disk, oldest first.
+    pub fn read_all(&self) -> std::io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+        for file in self.files() {
+            events.extend(read_file(&file)?);
+        }
+        Ok(events)
+    }
+
+    /// The set aside files by index, then the current one if it exists.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = rotated_files(&self.path)
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect();
+        if self.path.exists() {
+            files.push(self.path.clone());
+        }
+        files
+    }
+
+    /// Sets a full journal file aside under the next free index, then deletes
+    /// the oldest set aside files past `retained_files`.
+    fn rotate(&self) -> std::io::Result<()> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() >= self.max_file_bytes => {
+                let mut rotated = rotated_files(&self.path);
+                let index = rotated.last().map_or(1, |(index, _)| index + 1);
+                let file = rotated_file(&self.path, index);
+                std::fs::rename(&self.path, &file)?;
+                rotated.push((index, file));
+                if self.retained_files > 0 && rotated.len() > self.retained_files {
+                    for (_, file) in &rotated[..rotated.len() - self.retained_files] {
+                        std::fs::remove_file(file)?;
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops the oldest events past the limit, a tenth at a time so they
+    /// aren't shifted on every event.
+    fn trim(&mut self) {
+        if self.events.len() > self.max_events {
+            let excess = self.events.len() - self.max_events + self.max_events / 10;
+            self.events.drain(..excess.min(self.events.len()));
+            self.partial = true;
+        }
+    }
+}
+
+fn read_file(path: &Path) -> std::io::Result<Vec<Event>> {
+    Ok(BufReader::new(File::open(path)?)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| Event::from_line(&line))
+        .collect())
+}
+
+/// The file a full journal is set aside to, "JOURNAL.2.txt" for "JOURNAL.txt"
+/// and index 2.
+fn rotated_file(path: &Path, index: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!(
+            "{}.{}.{}",
+            stem,
+            index,
+            extension.to_string_lossy()
+        )),
+        None => path.with_file_name(format!("{}.{}", stem, index)),
+    }
+}
+
+/// The files set aside from the journal at `path`, by increasing index.
+fn rotated_files(path: &Path) -> Vec<(u64, PathBuf)> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut files: Vec<(u64, PathBuf)> = std::fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            let index = name
+                .to_str()?
+                .strip_prefix(&format!("{}.", stem))?
+                .strip_suffix(&extension)?
+                .parse()
+                .ok()?;
+            Some((index, rotated_file(path, index)))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes `events` to a CSV file with a header row.
+fn export_csv(events: &[&Event], path: &Path) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(b"Time,Type,Source,Severity,User,Message\r\n")?;
+    for event in events {
+        let fields = [
+            event.time.format("%d/%m/%Y %H:%M:%S").to_string(),
+            event.kind.to_string(),
+            event.source.clone(),
+            event
+                .severity
+                .map(|severity| severity.to_string())
+                .unwrap_or_default(),
+            event.user.clone(),
+            event.message.clone(),
+        ];
+        let line = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",");
+        file.write_all(format!("{}\r\n", line).as_bytes())?;
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+//####################################################
+
+//#################################################### Journal window.
+
+const TIME_FORMAT: &str = "%d/%m/%Y %H:%M";
+/// The number of rows shown at once, the export always has all the matches.
+const MAX_ROWS: usize = 1000;
+
+/// The filters of the journal window. Empty fields don't filter.
+#[derive(Default)]
+pub struct JournalFilter {
+    from: String,
+    to: String,
+    source: String,
+    min_severity: Option<Severity>,
+    kind: Option<EventKind>,
+    /// The result of the last export.
+    status: Option<Result<String, String>>,
+}
+
+impl JournalFilter {
+    fn matches(
+        &self,
+        event: &Event,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+    ) -> bool {
+        from.map_or(true, |from| event.time >= from)
+            && to.map_or(true, |to| event.time <= to)
+            && event
+                .source
+                .to_lowercase()
+                .contains(&self.source.to_lowercase())
+            && self
+                .min_severity
+                .map_or(true, |min| event.severity.is_some_and(|s| s >= min))
+            && self.kind.map_or(true, |kind| event.kind == kind)
+    }
+}
+
+/// Parses a filter date, `Err` if the text is not empty and not a date.
+fn parse_time(text: &str) -> Result<Option<DateTime<Local>>, ()> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    let time = NaiveDateTime::parse_from_str(text.trim(), TIME_FORMAT).map_err(|_| ())?;
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(Some)
+        .ok_or(())
+}
+
+/// Shows the journal window. `retained_files` is the saved number of set aside
+/// files kept.
+pub fn journal_ui(
+    ui: &mut egui::Ui,
+    journal: &mut Journal,
+    filter: &mut JournalFilter,
+    retained_files: &mut usize,
+) {
+    if let Some(error) = &journal.error {
+        ui.colored_label(Color32::DARK_RED, error);
+    }
+
+    let from = parse_time(&filter.from);
+    let to = parse_time(&filter.to);
+    Grid::new("journal_filter").num_columns(4).show(ui, |ui| {
+        ui.label("From");
+        ui.add(
+            TextEdit::singleline(&mut filter.from)
+                .hint_text("dd/mm/yyyy hh:mm")
+                .text_color_opt(from.is_err().then_some(Color32::RED))
+                .desired_width(120.),
+        );
+        ui.label("To");
+        ui.add(
+            TextEdit::singleline(&mut filter.to)
+                .hint_text("dd/mm/yyyy hh:mm")
+                .text_color_opt(to.is_err().then_some(Color32::RED))
+                .desired_width(120.),
+        );
+        ui.end_row();
+
+        ui.label("Tag");
+        ui.add(
+            TextEdit::singleline(&mut filter.source)
+                .hint_text("Any")
+                .desired_width(120.),
+        );
+        ui.label("Severity");
+        ComboBox::from_id_source("journal_severity")
+            .selected_text(match filter.min_severity {
+                Some(severity) => format!("{} and above", severity),
+                None => "Any".to_string(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut filter.min_severity, None, "Any");
+                for severity in Severity::all() {
+                    let text = format!("{} and above", severity);
+                    ui.selectable_value(&mut filter.min_severity, Some(severity), text);
+                }
+            });
+        ui.end_row();
+
+        ui.label("Type");
+        ComboBox::from_id_source("journal_kind")
+            .selected_text(match filter.kind {
+                Some(kind) => kind.to_string(),
+                None => "Any".to_string(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut filter.kind, None, "Any");
+                for kind in EventKind::all() {
+                    ui.selectable_value(&mut filter.kind, Some(kind), kind.to_string());
+                }
+            });
+        ui.end_row();
+
+        ui.label("Keep files")
+            .on_hover_text("The number of full journal files kept, 0 keeps them all.");
+        if ui
+            .add(DragValue::new(retained_files).clamp_range(0..=1000))
+            .changed()
+        {
+            journal.set_retained_files(*retained_files);
+        }
+        ui.end_row();
+    });
+
+    let (from, to) = (from.unwrap_or(None), to.unwrap_or(None));
+    let matches: Vec<&Event> = journal
+        .events()
+        .iter()
+        .filter(|event| filter.matches(event, from, to))
+        .collect();
+
+    ui.horizontal(|ui| {
+        if ui
+            .button(format!("{} Export CSV", egui_phosphor::regular::FILE_CSV))
+            .clicked()
+        {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_file_name("events.csv")
+                .add_filter("CSV", &["csv"])
+                .save_file()
+            {
+                // The export reads the files so it has the events no longer
+                // in memory.
+                let exported = journal.read_all().and_then(|events| {
+                    let matches: Vec<&Event> = events
+                        .iter()
+                        .filter(|event| filter.matches(event, from, to))
+                        .collect();
+                    export_csv(&matches, &path).map(|_| matches.len())
+                });
+                filter.status = Some(
+                    exported
+                        .map(|count| format!("Exported {} events.", count))
+                        .map_err(|e| format!("Could not export the events. {}", e)),
+                );
+            }
+        }
+        match &filter.status {
+            Some(Ok(status)) => {
+                ui.colored_label(Color32::GRAY, status);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(Color32::DARK_RED, error);
+            }
+            None => {}
+        }
+    });
+    if journal.is_partial() {
+        ui.colored_label(
+            Color32::GRAY,
+            "Older events are not shown, the export has the whole journal.",
+        );
+    }
+    if matches.len() > MAX_ROWS {
+        ui.colored_label(
+            Color32::GRAY,
+            format!(
+                "Showing the latest {} of {} events.",
+                MAX_ROWS,
+                matches.len()
+            ),
+        );
+    }
+    ui.separator();
+
+    ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+        Grid::new("journal_events")
+            .num_columns(6)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Time");
+                ui.strong("Type");
+                ui.strong("Source");
+                ui.strong("Severity");
+                ui.strong("User");
+                ui.strong("Message");
+                ui.end_row();
+
+                for event in matches.iter().rev().take(MAX_ROWS) {
+                    ui.label(event.time.format("%d/%m/%Y %H:%M:%S").to_string());
+                    ui.label(event.kind.to_string());
+                    ui.label(&event.source);
+                    match event.severity {
+                        Some(severity) => ui.label(
+                            RichText::new(severity.to_string())
+                                .color(Color32::WHITE)
+                                .background_color(severity.color()),
+                        ),
+                        None => ui.label(""),
+                    };
+                    ui.label(&event.user);
+                    ui.label(&event.message);
+                    ui.end_row();
+                }
+            });
+    });
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(message: &str) -> Event {
+        Event::new(
+            EventKind::Alarm,
+            "LIMIT PT1-1 HH",
+            Some(Severity::Critical),
+            message,
+        )
+    }
+
+    #[test]
+    fn events_round_trip_through_the_journal_lines() {
+        let alarm = event("Alarm raised");
+        assert_eq!(Event::from_line(&alarm.to_line()), Some(alarm));
+
+        let write = Event::new(EventKind::Write, "PT1-1", None, "52.5 Barg").by_operator();
+        assert_eq!(Event::from_line(&write.to_line()), Some(write));
+
+        // The separators of a message can't split its line.
+        let error = event("Could not\tread.\r\nTimed out");
+        let line = error.to_line();
+        assert_eq!(line.matches('\t').count(), 5);
+        assert_eq!(
+            Event::from_line(&line).unwrap().message,
+            "Could not read.  Timed out"
+        );
+
+        assert_eq!(Event::from_line("not an event"), None);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("Alarm raised"), "Alarm raised");
+        assert_eq!(csv_field("52,5 Barg"), "\"52,5 Barg\"");
+        assert_eq!(
+            csv_field("the \"ESD\" button"),
+            "\"the \"\"ESD\"\" button\""
+        );
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    fn messages(events: &[Event]) -> Vec<&str> {
+        events.iter().map(|event| event.message.as_str()).collect()
+    }
+
+    #[test]
+    fn full_journal_files_are_set_aside_without_losing_events() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("JOURNAL.txt");
+        let mut journal = Journal::open(&path);
+        journal.max_file_bytes = 200;
+        // About 80 bytes a line, so three lines a file.
+        for i in 0..9 {
+            journal.record(event(&format!("Event {}", i)));
+        }
+        assert_eq!(journal.error, None);
+        let rotated: Vec<PathBuf> = rotated_files(&path)
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect();
+        assert_eq!(
+            rotated,
+            [
+                directory.path().join("JOURNAL.1.txt"),
+                directory.path().join("JOURNAL.2.txt")
+            ]
+        );
+
+        let all: Vec<String> = (0..9).map(|i| format!("Event {}", i)).collect();
+        let journal = Journal::open(&path);
+        assert_eq!(messages(journal.events()), all);
+        assert!(!journal.is_partial());
+        assert_eq!(messages(&journal.read_all().unwrap()), all);
+    }
+
+    #[test]
+    fn only_the_retained_files_are_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("JOURNAL.txt");
+        let mut journal = Journal::open(&path);
+        journal.max_file_bytes = 200;
+        journal.set_retained_files(1);
+        for i in 0..9 {
+            journal.record(event(&format!("Event {}", i)));
+        }
+        assert_eq!(journal.error, None);
+        assert!(!directory.path().join("JOURNAL.1.txt").exists());
+        assert!(directory.path().join("JOURNAL.2.txt").exists());
+
+        let journal = Journal::open(&path);
+        assert_eq!(
+            messages(journal.events()),
+            ["Event 3", "Event 4", "Event 5", "Event 6", "Event 7", "Event 8"]
+        );
+    }
+
+    #[test]
+    fn the_oldest_events_are_dropped_from_memory() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut journal = Journal::open(&directory.path().join("JOURNAL.txt"));
+        journal.max_events = 10;
+        for i in 0..11 {
+            journal.record(event(&format!("Event {}", i)));
+        }
+        assert_eq!(journal.events().len(), 9);
+        assert_eq!(journal.events()[0].message, "Event 2");
+        assert!(journal.is_partial());
+        // They are still in the file.
+        assert_eq!(journal.read_all().unwrap().len(), 11);
+    }
+}
@@ -1,6 +1,24 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+mod alarms;
 mod app;
+mod blocks;
+mod bus;
+mod codec;
+mod datascan;
+mod health;
+mod historian;
+mod journal;
+mod limits;
+mod logger;
+mod logix;
 mod modbus;
+mod replay;
+mod scaling;
+mod scan;
+mod siemens;
+mod signals;
+mod tags;
+mod trend;
+mod writes;
 pub use app::CarbonApp;
-pub use modbus::*;
@@ -0,0 +1,385 @@
+use egui::{ComboBox, DragValue, Grid};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use crate::signals::Severity;
+
+//#################################################### Analog alarm limits.
+
+/// The HH/H/L/LL alarm limits of an analog tag.
+///
+/// An alarm is raised once the value has been beyond its setpoint for `on_delay`
+/// seconds, and cleared once it has been back inside the setpoint minus the
+/// deadband for `off_delay` seconds.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Limits {
+    pub high_high: Limit,
+    pub high: Limit,
+    pub low: Limit,
+    pub low_low: Limit,
+    pub deadband: f64,
+    pub on_delay: f64,
+    pub off_delay: f64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            high_high: Limit::new(100.0, Severity::Critical),
+            high: Limit::new(90.0, Severity::High),
+            low: Limit::new(10.0, Severity::High),
+            low_low: Limit::new(0.0, Severity::Critical),
+            deadband: 1.0,
+            on_delay: 0.0,
+            off_delay: 0.0,
+        }
+    }
+}
+
+impl Limits {
+    pub fn limit(&self, kind: LimitKind) -> &Limit {
+        match kind {
+            LimitKind::HighHigh => &self.high_high,
+            LimitKind::High => &self.high,
+            LimitKind::Low => &self.low,
+            LimitKind::LowLow => &self.low_low,
+        }
+    }
+
+    fn limit_mut(&mut self, kind: LimitKind) -> &mut Limit {
+        match kind {
+            LimitKind::HighHigh => &mut self.high_high,
+            LimitKind::High => &mut self.high,
+            LimitKind::Low => &mut self.low,
+            LimitKind::LowLow => &mut self.low_low,
+        }
+    }
+
+    /// The limits that are switched on.
+    pub fn enabled(&self) -> impl Iterator<Item = (LimitKind, &Limit)> {
+        LimitKind::all()
+            .into_iter()
+            .map(|kind| (kind, self.limit(kind)))
+            .filter(|(_, limit)| limit.enabled)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub struct Limit {
+    pub enabled: bool,
+    pub setpoint: f64,
+    pub severity: Severity,
+}
+
+impl Limit {
+    fn new(setpoint: f64, severity: Severity) -> Self {
+        Self {
+            enabled: false,
+            setpoint,
+            severity,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum LimitKind {
+    HighHigh,
+    High,
+    Low,
+    LowLow,
+}
+
+impl LimitKind {
+    pub fn all() -> [LimitKind; 4] {
+        [
+            LimitKind::HighHigh,
+            LimitKind::High,
+            LimitKind::Low,
+            LimitKind::LowLow,
+        ]
+    }
+
+    fn is_high(&self) -> bool {
+        matches!(self, LimitKind::HighHigh | LimitKind::High)
+    }
+
+    /// Whether `value` is beyond `threshold` in the direction of the limit.
+    fn exceeded(&self, value: f64, threshold: f64) -> bool {
+        if self.is_high() {
+            value >= threshold
+        } else {
+            value <= threshold
+        }
+    }
+}
+
+impl Display for LimitKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitKind::HighHigh => write!(f, "HH"),
+            LimitKind::High => write!(f, "H"),
+            LimitKind::Low => write!(f, "L"),
+            LimitKind::LowLow => write!(f, "LL"),
+        }
+    }
+}
+
+/// Starts the name of every limit alarm, so none can be taken for a digital
+/// signal of the same label.
+pub const LIMIT_ALARM_PREFIX: &str = "LIMIT ";
+
+/// The name a limit alarm of a tag is raised under, for example "LIMIT PT1-1 HH".
+pub fn alarm_name(tag: &str, kind: LimitKind) -> String {
+    format!("{}{} {}", LIMIT_ALARM_PREFIX, tag, kind)
+}
+
+/// Keeps the on/off delay timers of the analog limits, keyed by tag and limit.
+#[derive(Default)]
+pub struct LimitMonitor {
+    timers: HashMap<(String, LimitKind), LimitTimer>,
+}
+
+#[derive(Default)]
+struct LimitTimer {
+    active: bool,
+    /// When the condition started to differ from `active`.
+    since: Option<Instant>,
+    severity: Severity,
+}
+
+impl LimitMonitor {
+    /// Evaluates the limits of the tag `name` against its current value.
+    /// Returns the alarm name, condition and severity of every limit, the
+    /// disabled ones being reported as inactive so their alarms clear.
+    pub fn evaluate(
+        &mut self,
+        name: &str,
+        limits: &Limits,
+        value: f64,
+        now: Instant,
+    ) -> Vec<(String, bool, Severity)> {
+        LimitKind::all()
+            .into_iter()
+            .map(|kind| {
+                let limit = limits.limit(kind);
+                let alarm = alarm_name(name, kind);
+                let timer = self.timers.entry((name.to_string(), kind)).or_default();
+                timer.severity = limit.severity;
+                if !limit.enabled {
+                    timer.active = false;
+                    timer.since = None;
+                    return (alarm, false, limit.severity);
+                }
+
+                // An active alarm holds until the value is back past the deadband.
+                let threshold = match (timer.active, kind.is_high()) {
+                    (false, _) => limit.setpoint,
+                    (true, true) => limit.setpoint - limits.deadband,
+                    (true, false) => limit.setpoint + limits.deadband,
+                };
+                let exceeded = kind.exceeded(value, threshold);
+                if exceeded == timer.active {
+                    timer.since = None;
+                } else {
+                    let delay = if exceeded {
+                        limits.on_delay
+                    } else {
+                        limits.off_delay
+                    };
+                    let since = *timer.since.get_or_insert(now);
+                    if now.duration_since(since) >= Duration::from_secs_f64(delay.max(0.0)) {
+                        timer.active = exceeded;
+                        timer.since = None;
+                    }
+                }
+                (alarm, timer.active, limit.severity)
+            })
+            .collect()
+    }
+
+    /// Forgets the timers of the tags `keep` turns down, the renamed and
+    /// deleted ones. Returns the alarms they held active, with their
+    /// severity, so they can be cleared.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) -> Vec<(String, Severity)> {
+        let mut active = Vec::new();
+        self.timers.retain(|(tag, kind), timer| {
+            let kept = keep(tag);
+            if !kept && timer.active {
+                active.push((alarm_name(tag, *kind), timer.severity));
+            }
+            kept
+        });
+        active
+    }
+}
+//####################################################
+
+//#################################################### Limits editor.
+
+/// Returns true if the limits were changed.
+pub fn limits_ui(ui: &mut egui::Ui, limits: &mut Limits) -> bool {
+    let mut changed = false;
+    Grid::new("alarm_limits")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Limit");
+            ui.strong("Setpoint");
+            ui.strong("Severity");
+            ui.end_row();
+            for kind in LimitKind::all() {
+                let limit = limits.limit_mut(kind);
+                changed |= ui
+                    .checkbox(&mut limit.enabled, format!("{}", kind))
+                    .changed();
+                changed |= ui
+                    .add_enabled(
+                        limit.enabled,
+                        DragValue::new(&mut limit.setpoint).speed(0.1),
+                    )
+                    .changed();
+                ui.add_enabled_ui(limit.enabled, |ui| {
+                    ComboBox::from_id_source(("limit_severity", kind as u8))
+                        .selected_text(format!("{}", limit.severity))
+                        .width(70.)
+                        .show_ui(ui, |ui| {
+                            for severity in Severity::all() {
+                                let text = format!("{}", severity);
+                                changed |= ui
+                                    .selectable_value(&mut limit.severity, severity, text)
+                                    .changed();
+                            }
+                        });
+                });
+                ui.end_row();
+            }
+        });
+
+    ui.separator();
+    Grid::new("alarm_limits_timing")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Deadband");
+            changed |= ui
+                .add(
+                    DragValue::new(&mut limits.deadband)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f64::MAX),
+                )
+                .changed();
+            ui.end_row();
+            ui.label("On delay");
+            changed |= ui
+                .add(
+                    DragValue::new(&mut limits.on_delay)
+                        .speed(0.1)
+                        .clamp_range(0.0..=3600.0)
+                        .suffix(" s"),
+                )
+                .changed();
+            ui.end_row();
+            ui.label("Off delay");
+            changed |= ui
+                .add(
+                    DragValue::new(&mut limits.off_delay)
+                        .speed(0.1)
+                        .clamp_range(0.0..=3600.0)
+                        .suffix(" s"),
+                )
+                .changed();
+            ui.end_row();
+        });
+    changed
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn high_limit(deadband: f64, on_delay: f64, off_delay: f64) -> Limits {
+        let mut limits = Limits {
+            deadband,
+            on_delay,
+            off_delay,
+            ..Default::default()
+        };
+        limits.high.enabled = true;
+        limits
+    }
+
+    /// The condition of the H alarm of "PT1-1" for `value` at `now`.
+    fn high(monitor: &mut LimitMonitor, limits: &Limits, value: f64, now: Instant) -> bool {
+        monitor
+            .evaluate("PT1-1", limits, value, now)
+            .into_iter()
+            .find(|(name, _, _)| *name == alarm_name("PT1-1", LimitKind::High))
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn an_active_limit_holds_until_the_value_is_past_the_deadband() {
+        let limits = high_limit(2.0, 0.0, 0.0);
+        let mut monitor = LimitMonitor::default();
+        let now = Instant::now();
+        assert!(!high(&mut monitor, &limits, 89.9, now));
+        assert!(high(&mut monitor, &limits, 90.0, now));
+        assert!(high(&mut monitor, &limits, 89.0, now));
+        assert!(high(&mut monitor, &limits, 88.0, now));
+        assert!(!high(&mut monitor, &limits, 87.9, now));
+        assert!(!high(&mut monitor, &limits, 89.0, now));
+    }
+
+    #[test]
+    fn the_on_and_off_delays_hold_back_the_transitions() {
+        let limits = high_limit(0.0, 2.0, 1.0);
+        let mut monitor = LimitMonitor::default();
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        assert!(!high(&mut monitor, &limits, 95.0, at(0.0)));
+        assert!(!high(&mut monitor, &limits, 95.0, at(1.9)));
+        assert!(high(&mut monitor, &limits, 95.0, at(2.0)));
+
+        // A short dip restarts the off delay.
+        assert!(high(&mut monitor, &limits, 50.0, at(3.0)));
+        assert!(high(&mut monitor, &limits, 95.0, at(3.5)));
+        assert!(high(&mut monitor, &limits, 50.0, at(4.0)));
+        assert!(high(&mut monitor, &limits, 50.0, at(4.9)));
+        assert!(!high(&mut monitor, &limits, 50.0, at(5.0)));
+    }
+
+    #[test]
+    fn a_disabled_limit_clears_at_once() {
+        let mut limits = high_limit(0.0, 0.0, 10.0);
+        let mut monitor = LimitMonitor::default();
+        let now = Instant::now();
+        assert!(high(&mut monitor, &limits, 95.0, now));
+        limits.high.enabled = false;
+        assert!(!high(&mut monitor, &limits, 95.0, now));
+    }
+
+    #[test]
+    fn the_timers_of_a_renamed_tag_are_dropped_with_their_active_alarms() {
+        let limits = high_limit(0.0, 0.0, 0.0);
+        let mut monitor = LimitMonitor::default();
+        let now = Instant::now();
+        assert!(high(&mut monitor, &limits, 95.0, now));
+        monitor.evaluate("TT1-1", &limits, 20.0, now);
+
+        let cleared = monitor.retain(|name| name == "TT1-1");
+        assert_eq!(
+            cleared,
+            [(alarm_name("PT1-1", LimitKind::High), Severity::High)]
+        );
+        assert_eq!(monitor.timers.len(), 4);
+        assert!(monitor.retain(|name| name == "TT1-1").is_empty());
+    }
+
+    #[test]
+    fn limit_alarms_have_their_own_names() {
+        assert_eq!(alarm_name("PT1-1", LimitKind::HighHigh), "LIMIT PT1-1 HH");
+    }
+}
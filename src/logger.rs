@@ -0,0 +1,516 @@
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use egui::{Button, Color32, ComboBox, DragValue, Grid, ScrollArea, TextEdit};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use crate::bus::Sample;
+use crate::codec::Value;
+use crate::tags::Tag;
+
+//#################################################### Logger configuration.
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct LoggerConfig {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// The start of the file names, followed by the creation time.
+    pub prefix: String,
+    /// The tags logged, one column each.
+    pub columns: Vec<String>,
+    pub timestamps: Timestamps,
+    /// Milliseconds between two rows, independent of the scan rate.
+    pub interval: u64,
+    pub rotation: Rotation,
+    /// The number of files kept in the directory, 0 to keep them all.
+    pub retention: usize,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: PathBuf::from("."),
+            prefix: "LOGGER".to_string(),
+            columns: [
+                "LT1-1", "PT1-1", "PT2-1", "PT1-2", "PT2-2", "PT2-3", "PT3-1",
+            ]
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect(),
+            timestamps: Timestamps::default(),
+            interval: 1000,
+            rotation: Rotation::default(),
+            retention: 30,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum Timestamps {
+    Local,
+    Utc,
+}
+
+impl Display for Timestamps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timestamps::Local => write!(f, "Local"),
+            Timestamps::Utc => write!(f, "UTC"),
+        }
+    }
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self::Local
+    }
+}
+
+/// When the logger starts a new file.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum Rotation {
+    /// A file per day, starting at midnight local time.
+    Daily,
+    /// A new file once the current one reaches the size in megabytes.
+    Size(u64),
+}
+
+impl Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rotation::Daily => write!(f, "Daily"),
+            Rotation::Size(_) => write!(f, "Size"),
+        }
+    }
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self::Daily
+    }
+}
+
+/// What the logger thread reports back to the UI.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct LoggerStatus {
+    pub file: Option<PathBuf>,
+    pub rows: u64,
+    /// The last write error, cleared by the next successful write.
+    pub error: Option<String>,
+}
+//####################################################
+
+//#################################################### The logger.
+
+/// The creation time in the file names, which sorts them from the oldest.
+const FILE_TIME: &str = "%Y%m%d_%H%M%S";
+
+/// Writes rows of tag values to rotating CSV files.
+pub struct Logger {
+    config: LoggerConfig,
+    file: Option<LogFile>,
+    pub status: LoggerStatus,
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    opened_at: DateTime<Local>,
+    size: u64,
+}
+
+impl Logger {
+    pub fn new(config: LoggerConfig) -> Self {
+        Self {
+            config,
+            file: None,
+            status: LoggerStatus::default(),
+        }
+    }
+
+    pub fn config(&self) -> &LoggerConfig {
+        &self.config
+    }
+
+    /// Applies a new configuration. Any change closes the current file so
+    /// the next one gets a matching header.
+    pub fn set_config(&mut self, config: LoggerConfig) {
+        if config != self.config {
+            self.config = config;
+            self.file = None;
+        }
+    }
+
+    /// Writes a row of `values` sampled at `time`. Errors are kept in the
+    /// status and the file is reopened on the next row.
+    pub fn write(&mut self, time: DateTime<Local>, values: &HashMap<String, Sample>) {
+        match self.write_row(time, values) {
+            Ok(()) => {
+                self.status.rows += 1;
+                self.status.error = None;
+            }
+            Err(e) => {
+                self.file = None;
+                self.status.error = Some(format!("Could not write the log. {}", e));
+            }
+        }
+    }
+
+    fn write_row(
+        &mut self,
+        time: DateTime<Local>,
+        values: &HashMap<String, Sample>,
+    ) -> std::io::Result<()> {
+        let rotate = self
+            .file
+            .as_ref()
+            .is_some_and(|file| match self.config.rotation {
+                Rotation::Daily => file.opened_at.date_naive() != time.date_naive(),
+                Rotation::Size(megabytes) => file.size >= megabytes * 1_000_000,
+            });
+        if rotate || self.file.is_none() {
+            self.open(time)?;
+        }
+
+        let timestamp = match self.config.timestamps {
+            Timestamps::Local => time.format("%d/%m/%Y,%H:%M:%S").to_string(),
+            Timestamps::Utc => time
+                .with_timezone(&Utc)
+                .format("%d/%m/%Y,%H:%M:%S")
+                .to_string(),
+        };
+        let mut line = timestamp;
+        for column in self.config.columns.iter() {
+            line.push(',');
+            if let Some(sample) = values.get(column) {
+                line.push_str(&csv_field(&log_field(sample)));
+                line.push(',');
+                line.push_str(&csv_field(&sample.quality.to_string()));
+            } else {
+                line.push(',');
+            }
+        }
+        line.push_str("\r\n");
+
+        if let Some(file) = self.file.as_mut() {
+            file.file.write_all(line.as_bytes())?;
+            file.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Starts a new file with a header row and applies the retention.
+    fn open(&mut self, time: DateTime<Local>) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.config.directory)?;
+        let path = self.config.directory.join(format!(
+            "{}_{}.csv",
+            self.config.prefix,
+            time.format(FILE_TIME)
+        ));
+        let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+
+        let mut header = format!("Date,Time ({})", self.config.timestamps);
+        for column in self.config.columns.iter() {
+            header.push(',');
+            header.push_str(&csv_field(column));
+            header.push(',');
+            header.push_str(&csv_field(&quality_column(column)));
+        }
+        header.push_str("\r\n");
+        file.write_all(header.as_bytes())?;
+
+        self.status.file = Some(path.clone());
+        self.file = Some(LogFile {
+            file,
+            path,
+            opened_at: time,
+            size: header.len() as u64,
+        });
+        self.apply_retention()
+    }
+
+    /// Deletes the oldest log files beyond the retention count.
+    fn apply_retention(&self) -> std::io::Result<()> {
+        if self.config.retention == 0 {
+            return Ok(());
+        }
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.config.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| is_log_file(name, &self.config.prefix))
+            })
+            .collect();
+        // The creation time in the names sorts the files from the oldest.
+        files.sort();
+        let current = self.file.as_ref().map(|file| file.path.as_path());
+        let excess = files.len().saturating_sub(self.config.retention);
+        for path in files
+            .iter()
+            .take(excess)
+            .filter(|path| Some(path.as_path()) != current)
+        {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` is one the logger gives its files, `{prefix}_%Y%m%d_%H%M%S.csv`.
+/// Only those are ever deleted by the retention.
+fn is_log_file(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix)
+        .and_then(|name| name.strip_prefix('_'))
+        .and_then(|name| name.strip_suffix(".csv"))
+        .is_some_and(|time| {
+            time.bytes().all(|c| c.is_ascii_digit() || c == b'_')
+                && NaiveDateTime::parse_from_str(time, FILE_TIME).is_ok()
+        })
+}
+
+/// A value as logged, its quality going in the next column.
+fn log_field(sample: &Sample) -> String {
+    match &sample.value {
+        Some(Value::Number(value)) => format!("{:.2}", value),
+        Some(Value::Text(text)) => text.clone(),
+        None => "".to_string(),
+    }
+}
+
+/// The header of the column holding the quality of the values of `column`.
+pub fn quality_column(column: &str) -> String {
+    format!("{} quality", column)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+//####################################################
+
+//#################################################### Logger settings window.
+
+/// Edits a copy of the logger settings. Returns true when the operator
+/// applies them, as each change of the running logger starts a new file.
+pub fn logger_ui(
+    ui: &mut egui::Ui,
+    config: &mut LoggerConfig,
+    applied: &LoggerConfig,
+    status: &LoggerStatus,
+    tags: &[Tag],
+) -> bool {
+    Grid::new("logger_config").num_columns(2).show(ui, |ui| {
+        ui.label("Logging");
+        ui.checkbox(&mut config.enabled, "Enabled");
+        ui.end_row();
+
+        ui.label("Directory");
+        ui.horizontal(|ui| {
+            ui.label(config.directory.display().to_string());
+            if ui
+                .button(egui_phosphor::regular::FOLDER_OPEN)
+                .on_hover_text("Choose the directory")
+                .clicked()
+            {
+                if let Some(directory) = rfd::FileDialog::new()
+                    .set_directory(&config.directory)
+                    .pick_folder()
+                {
+                    config.directory = directory;
+                }
+            }
+        });
+        ui.end_row();
+
+        ui.label("File prefix");
+        ui.add(TextEdit::singleline(&mut config.prefix).desired_width(120.));
+        ui.end_row();
+
+        ui.label("Timestamps");
+        ComboBox::from_id_source("logger_timestamps")
+            .selected_text(format!("{}", config.timestamps))
+            .show_ui(ui, |ui| {
+                for timestamps in [Timestamps::Local, Timestamps::Utc] {
+                    let text = format!("{}", timestamps);
+                    ui.selectable_value(&mut config.timestamps, timestamps, text);
+                }
+            });
+        ui.end_row();
+
+        ui.label("Sample interval");
+        ui.add(
+            DragValue::new(&mut config.interval)
+                .clamp_range(100..=3_600_000)
+                .suffix(" ms"),
+        );
+        ui.end_row();
+
+        ui.label("Rotation");
+        ui.horizontal(|ui| {
+            ComboBox::from_id_source("logger_rotation")
+                .selected_text(format!("{}", config.rotation))
+                .show_ui(ui, |ui| {
+                    for rotation in [Rotation::Daily, Rotation::Size(10)] {
+                        let selected = std::mem::discriminant(&config.rotation)
+                            == std::mem::discriminant(&rotation);
+                        let text = format!("{}", rotation);
+                        if ui.selectable_label(selected, text).clicked() && !selected {
+                            config.rotation = rotation;
+                        }
+                    }
+                });
+            if let Rotation::Size(megabytes) = &mut config.rotation {
+                ui.add(
+                    DragValue::new(megabytes)
+                        .clamp_range(1..=10_000)
+                        .suffix(" MB"),
+                );
+            }
+        });
+        ui.end_row();
+
+        ui.label("Keep");
+        ui.add(
+            DragValue::new(&mut config.retention)
+                .clamp_range(0..=10_000)
+                .suffix(" files"),
+        )
+        .on_hover_text("0 keeps every file");
+        ui.end_row();
+    });
+
+    ui.separator();
+    ui.label("Columns");
+    ScrollArea::vertical().max_height(200.).show(ui, |ui| {
+        for tag in tags.iter() {
+            let mut logged = config.columns.contains(&tag.name);
+            if ui
+                .checkbox(&mut logged, format!("{}  {}", tag.name, tag.description))
+                .changed()
+            {
+                if logged {
+                    config.columns.push(tag.name.clone());
+                } else {
+                    config.columns.retain(|column| *column != tag.name);
+                }
+            }
+        }
+    });
+
+    ui.separator();
+    let edited = config != applied;
+    let apply = ui
+        .horizontal(|ui| {
+            let apply = ui
+                .add_enabled(
+                    edited,
+                    Button::new(format!("{} Apply", egui_phosphor::regular::PEN)),
+                )
+                .clicked();
+            if ui.add_enabled(edited, Button::new("Revert")).clicked() {
+                *config = applied.clone();
+            }
+            apply
+        })
+        .inner;
+    if let Some(path) = &status.file {
+        ui.colored_label(
+            Color32::GRAY,
+            format!("{}: {} rows", path.display(), status.rows),
+        );
+    }
+    if let Some(error) = &status.error {
+        ui.colored_label(Color32::DARK_RED, error);
+    }
+
+    apply
+}
+
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Quality;
+    use chrono::TimeZone;
+
+    #[test]
+    fn only_the_names_the_logger_writes_are_log_files() {
+        assert!(is_log_file("LOGGER_20240101_120000.csv", "LOGGER"));
+        assert!(!is_log_file("LOGGER_notes.csv", "LOGGER"));
+        assert!(!is_log_file("LOGGER_20240101_120000_copy.csv", "LOGGER"));
+        assert!(!is_log_file("LOGGER_20240101_120000.txt", "LOGGER"));
+        assert!(!is_log_file("LOG_20240101_120000.csv", "LOGGER"));
+        assert!(!is_log_file("LOGGER_A_20240101_120000.csv", "LOGGER"));
+    }
+
+    #[test]
+    fn retention_keeps_the_files_it_did_not_write() {
+        let directory = tempfile::tempdir().unwrap();
+        for name in [
+            "LOGGER_20240101_000000.csv",
+            "LOGGER_20240102_000000.csv",
+            "LOGGER_notes.csv",
+            "LOGGER_20240101_000000_copy.csv",
+        ] {
+            File::create(directory.path().join(name)).unwrap();
+        }
+
+        let mut logger = Logger::new(LoggerConfig {
+            directory: directory.path().to_path_buf(),
+            retention: 2,
+            ..Default::default()
+        });
+        let time = Local.with_ymd_and_hms(2024, 1, 3, 0, 0, 0).unwrap();
+        logger.write(time, &HashMap::new());
+        assert_eq!(logger.status.error, None);
+
+        let mut names: Vec<String> = std::fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "LOGGER_20240101_000000_copy.csv",
+                "LOGGER_20240102_000000.csv",
+                "LOGGER_20240103_000000.csv",
+                "LOGGER_notes.csv",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_quality_of_a_value_has_its_own_column() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut logger = Logger::new(LoggerConfig {
+            directory: directory.path().to_path_buf(),
+            columns: vec!["PT1-1".to_string(), "LT1-1".to_string()],
+            ..Default::default()
+        });
+        let time = Local.with_ymd_and_hms(2024, 1, 3, 8, 30, 0).unwrap();
+        let sample = Sample {
+            value: Some(Value::Number(52.5)),
+            quality: Quality::Stale,
+            timestamp: time,
+        };
+        logger.write(time, &HashMap::from([("PT1-1".to_string(), sample)]));
+
+        let text = std::fs::read_to_string(logger.status.file.unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "Date,Time (Local),PT1-1,PT1-1 quality,LT1-1,LT1-1 quality\r\n\
+             03/01/2024,08:30:00,52.50,Stale,,\r\n"
+        );
+    }
+}
@@ -0,0 +1,508 @@
+use bytes::Bytes;
+use egui::{Button, Color32, ComboBox, Grid, ScrollArea, TextEdit};
+use rseip::client::ab_eip::{PathParser, TagType, TagValue};
+use rseip::client::{AbEipClient, AbService};
+use rseip::precludes::{EPath, MessageService, PortSegment};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::codec::Value;
+use crate::health::{Failure, ScanError};
+
+//#################################################### Logix data types.
+
+/// The CIP types of the Logix controllers.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum CipType {
+    Bool,
+    Sint,
+    Int,
+    Dint,
+    Lint,
+    Real,
+    /// The predefined STRING structure, a DINT length and 82 characters.
+    String,
+}
+
+/// The number of characters of a Logix STRING.
+const STRING_LENGTH: usize = 82;
+
+impl CipType {
+    pub fn all() -> [CipType; 7] {
+        [
+            CipType::Bool,
+            CipType::Sint,
+            CipType::Int,
+            CipType::Dint,
+            CipType::Lint,
+            CipType::Real,
+            CipType::String,
+        ]
+    }
+
+    /// The type reported by the controller, `None` for a structure.
+    fn tag_type(&self) -> Option<TagType> {
+        match self {
+            CipType::Bool => Some(TagType::Bool),
+            CipType::Sint => Some(TagType::Sint),
+            CipType::Int => Some(TagType::Int),
+            CipType::Dint => Some(TagType::Dint),
+            CipType::Lint => Some(TagType::Lint),
+            CipType::Real => Some(TagType::Real),
+            CipType::String => None,
+        }
+    }
+
+    /// Decodes the little endian data of a read, checking the controller
+    /// agrees on the type.
+    fn decode(&self, tag_type: TagType, bytes: &[u8]) -> Result<Value, String> {
+        let matches = match self.tag_type() {
+            Some(expected) => expected == tag_type,
+            None => tag_type.is_structure(),
+        };
+        if !matches {
+            return Err(format!(
+                "The controller has a {:?}, not a {}.",
+                tag_type, self
+            ));
+        }
+        let short = || "The controller sent a short value.".to_string();
+        let value = match self {
+            CipType::Bool => (*bytes.first().ok_or_else(short)? != 0) as u8 as f64,
+            CipType::Sint => *bytes.first().ok_or_else(short)? as i8 as f64,
+            CipType::Int => i16::from_le_bytes(array(bytes).ok_or_else(short)?) as f64,
+            CipType::Dint => i32::from_le_bytes(array(bytes).ok_or_else(short)?) as f64,
+            CipType::Lint => i64::from_le_bytes(array(bytes).ok_or_else(short)?) as f64,
+            CipType::Real => f32::from_le_bytes(array(bytes).ok_or_else(short)?) as f64,
+            CipType::String => {
+                let length = i32::from_le_bytes(array(bytes).ok_or_else(short)?);
+                let text = bytes
+                    .get(4..4 + length.clamp(0, STRING_LENGTH as i32) as usize)
+                    .ok_or_else(short)?;
+                return Ok(Value::Text(text.iter().map(|byte| *byte as char).collect()));
+            }
+        };
+        Ok(Value::Number(value))
+    }
+
+    /// Encodes a value to write in little endian.
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let out_of_range = |value: f64| format!("{} is out of range for a {}.", value, self);
+        let integer = |value: f64, min: f64, max: f64| {
+            let value = value.round();
+            if value < min || value > max {
+                Err(out_of_range(value))
+            } else {
+                Ok(value)
+            }
+        };
+        match (self, value) {
+            (CipType::String, Value::Text(text)) => {
+                if !text.is_ascii() {
+                    return Err("Only ASCII text can be written.".to_string());
+                }
+                if text.len() > STRING_LENGTH {
+                    return Err(format!(
+                        "The text is longer than {} characters.",
+                        STRING_LENGTH
+                    ));
+                }
+                let mut bytes = (text.len() as i32).to_le_bytes().to_vec();
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.resize(4 + STRING_LENGTH, 0);
+                Ok(bytes)
+            }
+            (CipType::String, Value::Number(_)) => {
+                Err("A string tag needs a text value.".to_string())
+            }
+            (_, Value::Text(_)) => Err(format!("A {} needs a number.", self)),
+            (_, Value::Number(value)) if !value.is_finite() => {
+                Err("The value is not a number.".to_string())
+            }
+            (CipType::Bool, Value::Number(value)) => match *value {
+                v if v == 0.0 => Ok(vec![0]),
+                v if v == 1.0 => Ok(vec![1]),
+                v => Err(format!("A BOOL is 0 or 1, not {}.", v)),
+            },
+            (CipType::Sint, Value::Number(value)) => {
+                let v = integer(*value, i8::MIN as f64, i8::MAX as f64)?;
+                Ok((v as i8).to_le_bytes().to_vec())
+            }
+            (CipType::Int, Value::Number(value)) => {
+                let v = integer(*value, i16::MIN as f64, i16::MAX as f64)?;
+                Ok((v as i16).to_le_bytes().to_vec())
+            }
+            (CipType::Dint, Value::Number(value)) => {
+                let v = integer(*value, i32::MIN as f64, i32::MAX as f64)?;
+                Ok((v as i32).to_le_bytes().to_vec())
+            }
+            (CipType::Lint, Value::Number(value)) => {
+                let v = integer(*value, i64::MIN as f64, i64::MAX as f64)?;
+                Ok((v as i64).to_le_bytes().to_vec())
+            }
+            (CipType::Real, Value::Number(value)) => {
+                if value.abs() > f32::MAX as f64 {
+                    return Err(out_of_range(*value));
+                }
+                Ok((*value as f32).to_le_bytes().to_vec())
+            }
+        }
+    }
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> Option<[u8; N]> {
+    bytes.get(..N)?.try_into().ok()
+}
+
+impl Display for CipType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CipType::Bool => write!(f, "BOOL"),
+            CipType::Sint => write!(f, "SINT"),
+            CipType::Int => write!(f, "INT"),
+            CipType::Dint => write!(f, "DINT"),
+            CipType::Lint => write!(f, "LINT"),
+            CipType::Real => write!(f, "REAL"),
+            CipType::String => write!(f, "STRING"),
+        }
+    }
+}
+
+/// A controller tag read by its symbolic name, like `Program:MainProgram.Level`,
+/// and published under the name of a tag.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub struct LogixTag {
+    pub name: String,
+    pub symbol: String,
+    pub data_type: CipType,
+}
+
+impl LogixTag {
+    fn new(name: &str, symbol: &str, data_type: CipType) -> Self {
+        Self {
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            data_type,
+        }
+    }
+
+    /// The parsed symbolic path.
+    pub fn path(&self) -> Result<EPath, String> {
+        EPath::parse_tag(self.symbol.trim())
+            .map_err(|e| format!("\"{}\" is not a tag path. {}", self.symbol, e))
+    }
+}
+
+pub fn default_logix_tags() -> Vec<LogixTag> {
+    vec![
+        LogixTag::new("EIP-REAL-1", "Real1", CipType::Real),
+        LogixTag::new("EIP-DINT-1", "Dint1", CipType::Dint),
+    ]
+}
+//####################################################
+
+//#################################################### Logix tag services.
+
+/// An unconnected client routed through the backplane to the CPU in `slot`.
+pub async fn logix_client(ip: &str, slot: u8) -> Result<AbEipClient, String> {
+    let client = AbEipClient::new_host_lookup(ip.trim())
+        .await
+        .map_err(|e| format!("\"{}\" is not a reachable host. {}", ip, e))?;
+    Ok(client.with_connection_path(PortSegment {
+        port: 1,
+        link: vec![slot].into(),
+    }))
+}
+
+/// Ends the session with the controller and closes the socket.
+pub async fn close_logix_client(client: &mut AbEipClient) {
+    let _ = client.close().await;
+}
+
+/// Gives up on `future` after `timeout`.
+async fn within<T>(
+    timeout: Duration,
+    future: impl Future<Output = Result<T, rseip::ClientError>>,
+) -> Result<T, ScanError> {
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result.map_err(|e| {
+            let failure = match &e {
+                rseip::ClientError::Io { err, .. } => Failure::from(err.kind()),
+                // The other errors come from the reply, a refusal status
+                // mostly: the controller is there.
+                rseip::ClientError::Custom { .. } => Failure::Exception,
+            };
+            ScanError::new(failure, e.to_string())
+        }),
+        Err(_) => Err(ScanError::new(
+            Failure::Timeout,
+            "The controller did not answer in time.",
+        )),
+    }
+}
+
+/// Reads all the tags, one Read Tag service each. Tags with an invalid path
+/// are left out.
+///
+/// A tag the controller refuses or answers with another type doesn't stop the
+/// scan: it is left out of the values and its error is returned by tag name.
+/// Only a failure of the link fails the whole scan.
+pub async fn read_logix_tags(
+    client: &mut AbEipClient,
+    tags: &[LogixTag],
+    timeout: Duration,
+) -> Result<(HashMap<String, Value>, HashMap<String, String>), ScanError> {
+    let mut values = HashMap::new();
+    let mut errors = HashMap::new();
+    for tag in tags.iter() {
+        let Ok(path) = tag.path() else {
+            continue;
+        };
+        let read = within(timeout, client.read_tag(path)).await;
+        match decode_read(tag, read)? {
+            Ok(value) => {
+                values.insert(tag.name.clone(), value);
+            }
+            Err(e) => {
+                errors.insert(tag.name.clone(), e);
+            }
+        }
+    }
+    // A controller that refuses every tag fails the scan as before.
+    if let (true, Some(error)) = (values.is_empty(), errors.values().next()) {
+        return Err(ScanError::new(Failure::Exception, error.clone()));
+    }
+    Ok((values, errors))
+}
+
+/// The value of a tag out of its read, or the error of the tag alone. A
+/// failure of the link is the error of the scan.
+fn decode_read(
+    tag: &LogixTag,
+    read: Result<TagValue<Bytes>, ScanError>,
+) -> Result<Result<Value, String>, ScanError> {
+    match read {
+        Ok(read) => Ok(tag
+            .data_type
+            .decode(read.tag_type, &read.value)
+            .map_err(|e| format!("{}: {}", tag.symbol, e))),
+        // A refusal is an answer about this tag only.
+        Err(e) if e.failure == Failure::Exception => Ok(Err(format!("{}: {}", tag.symbol, e))),
+        Err(e) => Err(ScanError::new(e.failure, format!("{}: {}", tag.symbol, e))),
+    }
+}
+
+/// Writes the value of the tag `name`. The type of a STRING is read first,
+/// as the controller identifies structures by a handle of its own.
+pub async fn write_logix_tag(
+    client: &mut AbEipClient,
+    tags: &[LogixTag],
+    name: &str,
+    value: &Value,
+    timeout: Duration,
+) -> Result<(), String> {
+    let tag = tags
+        .iter()
+        .find(|tag| tag.name == name)
+        .ok_or(format!("{} is not in the EtherNet/IP tag list.", name))?;
+    let path = tag.path()?;
+    let bytes = tag.data_type.encode(value)?;
+    let tag_type = match tag.data_type.tag_type() {
+        Some(tag_type) => tag_type,
+        None => {
+            let read: TagValue<Bytes> = within(timeout, client.read_tag(path.clone())).await?;
+            read.tag_type
+        }
+    };
+    within(
+        timeout,
+        client.write_tag(path, (tag_type, 1_u16, bytes.as_slice())),
+    )
+    .await?;
+    Ok(())
+}
+//####################################################
+
+//#################################################### Logix tag list editor.
+
+/// Shows the EtherNet/IP tag list. Returns true if it was changed.
+pub fn logix_tags_ui(ui: &mut egui::Ui, tags: &mut Vec<LogixTag>) -> bool {
+    let before = tags.clone();
+    let mut remove = None;
+
+    ScrollArea::vertical().max_height(250.).show(ui, |ui| {
+        Grid::new("logix_tags")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Tag");
+                ui.strong("Controller Tag");
+                ui.strong("Type");
+                ui.end_row();
+
+                for (i, tag) in tags.iter_mut().enumerate() {
+                    ui.add(TextEdit::singleline(&mut tag.name).desired_width(80.));
+                    let error = tag.path().err();
+                    ui.add(
+                        TextEdit::singleline(&mut tag.symbol)
+                            .desired_width(140.)
+                            .text_color_opt(error.is_some().then_some(Color32::RED)),
+                    )
+                    .on_hover_text(error.unwrap_or_default());
+                    ComboBox::from_id_source(("logix_tag_type", i))
+                        .selected_text(format!("{}", tag.data_type))
+                        .show_ui(ui, |ui| {
+                            for data_type in CipType::all() {
+                                ui.selectable_value(
+                                    &mut tag.data_type,
+                                    data_type,
+                                    format!("{}", data_type),
+                                );
+                            }
+                        });
+                    if ui
+                        .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                        .on_hover_text("Remove tag")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    if let Some(i) = remove {
+        tags.remove(i);
+    }
+    if ui
+        .button(format!("{} Add tag", egui_phosphor::regular::PLUS))
+        .clicked()
+    {
+        tags.push(LogixTag::new("NEW-TAG", "Tag", CipType::Dint));
+    }
+
+    *tags != before
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_encodes_the_types() {
+        let cases = [
+            (CipType::Bool, 1.),
+            (CipType::Sint, -100.),
+            (CipType::Int, -1234.),
+            (CipType::Dint, 123_456_789.),
+            (CipType::Lint, -9_000_000_000.),
+            (CipType::Real, 12.5),
+        ];
+        for (data_type, value) in cases {
+            let tag_type = data_type.tag_type().unwrap();
+            let bytes = data_type.encode(&Value::Number(value)).unwrap();
+            assert_eq!(
+                data_type.decode(tag_type, &bytes),
+                Ok(Value::Number(value)),
+                "{data_type}"
+            );
+        }
+        let text = Value::Text("RUNNING".to_string());
+        let bytes = CipType::String.encode(&text).unwrap();
+        assert_eq!(bytes.len(), 86);
+        assert_eq!(
+            CipType::String.decode(TagType::Structure(0x0FCE), &bytes),
+            Ok(text)
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_types_and_values() {
+        assert!(CipType::Real.decode(TagType::Dint, &[0; 4]).is_err());
+        assert!(CipType::Dint
+            .decode(TagType::Structure(1), &[0; 4])
+            .is_err());
+        assert!(CipType::Dint.decode(TagType::Dint, &[0; 2]).is_err());
+        assert!(CipType::Int.encode(&Value::Number(40000.)).is_err());
+        assert!(CipType::Bool.encode(&Value::Number(0.5)).is_err());
+        assert!(CipType::String
+            .encode(&Value::Text("X".repeat(83)))
+            .is_err());
+    }
+
+    #[test]
+    fn a_bad_tag_is_left_out_and_a_dead_link_fails_the_scan() {
+        let tag = LogixTag::new("PT", "Program:Main.PT", CipType::Real);
+        let read = |tag_type, value: &[u8]| {
+            Ok(TagValue {
+                tag_type,
+                value: Bytes::copy_from_slice(value),
+            })
+        };
+        assert_eq!(
+            decode_read(&tag, read(TagType::Real, &12.5_f32.to_le_bytes())),
+            Ok(Ok(Value::Number(12.5)))
+        );
+        // The other tags are still read.
+        assert!(decode_read(&tag, read(TagType::Dint, &[0; 4]))
+            .unwrap()
+            .is_err());
+        let refused = ScanError::new(Failure::Exception, "Path destination unknown");
+        assert_eq!(
+            decode_read(&tag, Err(refused)),
+            Ok(Err("Program:Main.PT: Path destination unknown".to_string()))
+        );
+        let silent = ScanError::new(Failure::Timeout, "No answer");
+        assert_eq!(
+            decode_read(&tag, Err(silent)).unwrap_err().failure,
+            Failure::Timeout
+        );
+    }
+
+    #[test]
+    fn failures_of_the_controller_are_told_apart() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let silent = std::future::pending::<Result<(), rseip::ClientError>>();
+            assert_eq!(
+                within(Duration::from_secs(1), silent)
+                    .await
+                    .unwrap_err()
+                    .failure,
+                Failure::Timeout
+            );
+            let reset = async {
+                Err::<(), _>(rseip::ClientError::from(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionReset,
+                )))
+            };
+            assert_eq!(
+                within(Duration::from_secs(1), reset)
+                    .await
+                    .unwrap_err()
+                    .failure,
+                Failure::Other
+            );
+            let refused = async {
+                Err::<(), _>(rseip::ClientError::Custom {
+                    kind: "custom",
+                    msg: "cip error: message reply status 0x05".into(),
+                })
+            };
+            assert_eq!(
+                within(Duration::from_secs(1), refused)
+                    .await
+                    .unwrap_err()
+                    .failure,
+                Failure::Exception
+            );
+        });
+    }
+}
@@ -0,0 +1,571 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use egui::{Color32, ComboBox, Grid, Slider};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{self, Quality};
+use crate::codec::Value;
+use crate::logger::quality_column;
+use crate::tags::Tag;
+use crate::trend::{chart, local_time, Sample, Series};
+
+//#################################################### Logger files.
+
+/// The columns of the logger files written before they had a header row.
+const LEGACY_COLUMNS: [&str; 7] = [
+    "LT1-1", "PT1-1", "PT2-1", "PT1-2", "PT2-2", "PT2-3", "PT3-1",
+];
+
+const COLORS: [Color32; 7] = [
+    Color32::LIGHT_BLUE,
+    Color32::from_rgb(255, 140, 0),
+    Color32::LIGHT_GREEN,
+    Color32::from_rgb(255, 80, 80),
+    Color32::from_rgb(200, 120, 255),
+    Color32::YELLOW,
+    Color32::from_rgb(0, 200, 200),
+];
+
+/// The content of a logger file.
+pub struct LogFile {
+    pub columns: Vec<String>,
+    /// The timestamps of the rows.
+    pub times: Vec<f64>,
+    /// One series per column. Empty or unreadable fields are left out.
+    pub series: Vec<VecDeque<Sample>>,
+    /// The number of lines that could not be read.
+    pub skipped: usize,
+}
+
+impl LogFile {
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    fn time(&self, index: usize) -> f64 {
+        self.times.get(index).copied().unwrap_or_default()
+    }
+}
+
+/// Parses a logger file: a `%d/%m/%Y` date, a `%H:%M:%S` time and the values,
+/// separated by tabs or commas.
+///
+/// The files of the structured logger start with a header row naming the
+/// columns and the time zone, each value column followed by its quality.
+/// The older files have no header, UTC timestamps and the tags the logger
+/// used to write.
+pub fn parse_log(text: &str) -> Result<LogFile, String> {
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    let delimiter = match lines.peek() {
+        Some(line) if !line.contains('\t') => ',',
+        _ => '\t',
+    };
+
+    let mut columns: Vec<String> = LEGACY_COLUMNS.iter().map(|tag| tag.to_string()).collect();
+    let mut utc = true;
+    if let Some(first) = lines.peek() {
+        let header = split_fields(first, delimiter);
+        // The header the logger writes, `Date,Time (Local)` or `Date,Time (UTC)`.
+        // Any other first line is data, if only a malformed row.
+        if header.first().is_some_and(|date| date == "Date")
+            && header.get(1).is_some_and(|time| time.starts_with("Time ("))
+        {
+            utc = header[1] != "Time (Local)";
+            columns = header.into_iter().skip(2).collect();
+            lines.next();
+        }
+    }
+
+    // The values of the structured logger are followed by their quality, the
+    // ones that aren't good are left out like unreadable fields.
+    let qualities: Vec<Option<usize>> = columns
+        .iter()
+        .map(|column| {
+            let quality = quality_column(column);
+            columns.iter().position(|other| *other == quality)
+        })
+        .collect();
+    let values: Vec<(usize, Option<usize>)> = qualities
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !qualities.contains(&Some(*index)))
+        .map(|(index, quality)| (index, *quality))
+        .collect();
+    let columns: Vec<String> = values
+        .iter()
+        .map(|(index, _)| columns[*index].clone())
+        .collect();
+
+    let mut times = Vec::new();
+    let mut series = vec![VecDeque::new(); columns.len()];
+    let mut skipped = 0;
+    for line in lines {
+        let fields = split_fields(line, delimiter);
+        let Some(time) = parse_time(&fields, utc) else {
+            skipped += 1;
+            continue;
+        };
+        times.push(time);
+        for (series, (index, quality)) in series.iter_mut().zip(values.iter()) {
+            let good = quality.map_or(true, |quality| {
+                fields.get(quality + 2).map(String::as_str) == Some("Good")
+            });
+            let value = fields.get(index + 2).map(|field| field.parse::<f64>());
+            if let (true, Some(Ok(value))) = (good, value) {
+                series.push_back(Sample { time, value });
+            }
+        }
+    }
+
+    if times.is_empty() {
+        return Err("No samples found in the file.".to_string());
+    }
+    Ok(LogFile {
+        columns,
+        times,
+        series,
+        skipped,
+    })
+}
+
+/// Splits a line on `delimiter`, honouring double quoted fields.
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+        .iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+fn parse_time(fields: &[String], utc: bool) -> Option<f64> {
+    let time = NaiveDateTime::parse_from_str(
+        &format!("{} {}", fields.first()?, fields.get(1)?),
+        "%d/%m/%Y %H:%M:%S",
+    )
+    .ok()?;
+    let time = if utc {
+        time.and_utc().timestamp()
+    } else {
+        Local.from_local_datetime(&time).earliest()?.timestamp()
+    };
+    Some(time as f64)
+}
+//####################################################
+
+//#################################################### Replay.
+
+const SPEEDS: [(&str, f64); 5] = [
+    ("1x", 1.),
+    ("10x", 10.),
+    ("60x", 60.),
+    ("600x", 600.),
+    ("3600x", 3600.),
+];
+
+/// An opened logger file, with the selected time range and the replay state.
+pub struct Replay {
+    pub path: PathBuf,
+    pub log: LogFile,
+    shown: Vec<bool>,
+    /// The first and last sample of the selected range.
+    from: usize,
+    to: usize,
+    /// The sample shown on the mimic.
+    position: usize,
+    playing: bool,
+    speed: f64,
+    /// The log time reached by the playback, between two samples.
+    play_time: f64,
+    /// Whether the mimic shows the replayed values instead of the live ones.
+    pub on_mimic: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not open {}. {}", path.display(), e))?;
+        let log = parse_log(&text)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            shown: vec![true; log.columns.len()],
+            from: 0,
+            to: log.len() - 1,
+            position: 0,
+            playing: false,
+            speed: 10.,
+            play_time: log.time(0),
+            on_mimic: true,
+            log,
+        })
+    }
+
+    /// Puts the replayed value of `tag` on it, for a copy of the live tag
+    /// drawn on the mimic. Only the good values of a row were logged as
+    /// numbers, so a row without one shows the tag as Bad.
+    pub fn apply(&self, tag: &mut Tag) {
+        let Some(column) = self.log.columns.iter().position(|name| *name == tag.name) else {
+            return;
+        };
+        let time = self.log.time(self.position);
+        match (sample_at(&self.log.series[column], time), local_time(time)) {
+            (Some(value), Some(timestamp)) => tag.set_sample(&bus::Sample {
+                value: Some(Value::Number(value)),
+                quality: Quality::Good,
+                timestamp,
+            }),
+            _ => {
+                tag.timestamp = None;
+                tag.quality = Quality::Bad;
+            }
+        }
+    }
+
+    /// Advances the playback by `dt` seconds of wall clock time.
+    fn advance(&mut self, dt: f64) {
+        if !self.playing {
+            return;
+        }
+        self.play_time += dt * self.speed;
+        while self.position < self.to && self.log.time(self.position + 1) <= self.play_time {
+            self.position += 1;
+        }
+        if self.position >= self.to {
+            self.playing = false;
+        }
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.position = position.clamp(self.from, self.to);
+        self.play_time = self.log.time(self.position);
+    }
+}
+
+/// The value logged in the row at `time`, unlike `value_at` which carries the
+/// last one through the rows where it wasn't good.
+fn sample_at(series: &VecDeque<Sample>, time: f64) -> Option<f64> {
+    let index = series.partition_point(|sample| sample.time < time);
+    series
+        .get(index)
+        .filter(|sample| sample.time == time)
+        .map(|sample| sample.value)
+}
+
+fn time_text(time: f64) -> String {
+    local_time(time)
+        .map(|time| time.format("%d/%m/%Y %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+pub fn replay_ui(ui: &mut egui::Ui, replay: &mut Replay, tags: &[Tag]) {
+    replay.advance(ui.input(|i| i.stable_dt) as f64);
+
+    ui.label(format!(
+        "{} {}, {} samples from {} to {}",
+        egui_phosphor::regular::FILE_TEXT,
+        replay.path.display(),
+        replay.log.len(),
+        time_text(replay.log.time(0)),
+        time_text(replay.log.time(replay.log.len() - 1)),
+    ));
+    if replay.log.skipped > 0 {
+        ui.colored_label(
+            Color32::DARK_RED,
+            format!("{} lines could not be read.", replay.log.skipped),
+        );
+    }
+    ui.separator();
+
+    // Time range selection.
+    let last = replay.log.len() - 1;
+    let from_text = time_text(replay.log.time(replay.from));
+    let to_text = time_text(replay.log.time(replay.to));
+    Grid::new("replay_range").num_columns(2).show(ui, |ui| {
+        ui.label("From");
+        ui.add(
+            Slider::new(&mut replay.from, 0..=last)
+                .show_value(false)
+                .text(from_text),
+        );
+        ui.end_row();
+        ui.label("To");
+        ui.add(
+            Slider::new(&mut replay.to, 0..=last)
+                .show_value(false)
+                .text(to_text),
+        );
+        ui.end_row();
+    });
+    if replay.from > replay.to {
+        std::mem::swap(&mut replay.from, &mut replay.to);
+    }
+    if !(replay.from..=replay.to).contains(&replay.position) {
+        replay.seek(replay.position);
+    }
+
+    // Statistics of the selected range.
+    let (from, to) = (replay.from, replay.to);
+    let log = &replay.log;
+    Grid::new("replay_statistics")
+        .num_columns(6)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Show");
+            ui.strong("Tag");
+            ui.strong("Min");
+            ui.strong("Max");
+            ui.strong("Avg");
+            ui.strong("Value");
+            ui.end_row();
+            for (i, (name, series)) in log.columns.iter().zip(log.series.iter()).enumerate() {
+                let unit = tags
+                    .iter()
+                    .find(|tag| &tag.name == name)
+                    .map_or("", |tag| tag.unit.as_str());
+                let (start, end) = (log.time(from), log.time(to));
+                let (count, min, max, sum) = series
+                    .iter()
+                    .skip_while(|sample| sample.time < start)
+                    .take_while(|sample| sample.time <= end)
+                    .fold(
+                        (0, f64::MAX, f64::MIN, 0.0),
+                        |(count, min, max, sum), sample| {
+                            (
+                                count + 1,
+                                min.min(sample.value),
+                                max.max(sample.value),
+                                sum + sample.value,
+                            )
+                        },
+                    );
+                let number = |value: f64| {
+                    if count > 0 {
+                        format!("{:.2} {}", value, unit)
+                    } else {
+                        "-".to_string()
+                    }
+                };
+                ui.checkbox(&mut replay.shown[i], "");
+                ui.colored_label(COLORS[i % COLORS.len()], name);
+                ui.label(number(min));
+                ui.label(number(max));
+                ui.label(number(sum / count.max(1) as f64));
+                let value = sample_at(series, log.time(replay.position));
+                ui.label(value.map_or("-".to_string(), |value| format!("{:.2} {}", value, unit)));
+                ui.end_row();
+            }
+        });
+    ui.separator();
+
+    // Playback controls.
+    ui.horizontal(|ui| {
+        if ui
+            .button(egui_phosphor::regular::SKIP_BACK)
+            .on_hover_text("Start of the range")
+            .clicked()
+        {
+            replay.seek(replay.from);
+        }
+        if ui
+            .button(egui_phosphor::regular::CARET_LEFT)
+            .on_hover_text("Previous sample")
+            .clicked()
+        {
+            replay.seek(replay.position.saturating_sub(1));
+        }
+        let play = if replay.playing {
+            egui_phosphor::regular::PAUSE
+        } else {
+            egui_phosphor::regular::PLAY
+        };
+        if ui.button(play).clicked() {
+            if !replay.playing && replay.position >= replay.to {
+                replay.seek(replay.from);
+            }
+            replay.playing = !replay.playing;
+        }
+        if ui
+            .button(egui_phosphor::regular::CARET_RIGHT)
+            .on_hover_text("Next sample")
+            .clicked()
+        {
+            replay.seek(replay.position + 1);
+        }
+        ComboBox::from_id_source("replay_speed")
+            .selected_text(
+                SPEEDS
+                    .iter()
+                    .find(|(_, speed)| *speed == replay.speed)
+                    .map_or("", |(text, _)| text),
+            )
+            .width(60.)
+            .show_ui(ui, |ui| {
+                for (text, speed) in SPEEDS {
+                    ui.selectable_value(&mut replay.speed, speed, text);
+                }
+            });
+        let mut position = replay.position;
+        if ui
+            .add(
+                Slider::new(&mut position, replay.from..=replay.to)
+                    .show_value(false)
+                    .text(time_text(replay.log.time(replay.position))),
+            )
+            .changed()
+        {
+            replay.seek(position);
+        }
+        ui.checkbox(&mut replay.on_mimic, "Show on mimic");
+    });
+
+    let series: Vec<Series<'_>> = replay
+        .log
+        .columns
+        .iter()
+        .zip(replay.log.series.iter())
+        .enumerate()
+        .filter(|(i, _)| replay.shown[*i])
+        .map(|(i, (name, samples))| Series {
+            name,
+            unit: tags
+                .iter()
+                .find(|tag| &tag.name == name)
+                .map_or("", |tag| tag.unit.as_str()),
+            color: COLORS[i % COLORS.len()],
+            outages: None,
+            samples,
+            scale: None,
+        })
+        .collect();
+    let start = replay.log.time(replay.from);
+    let end = replay.log.time(replay.to);
+    let marker = replay.log.time(replay.position);
+    let response = chart(ui, &series, start, end, Some(marker));
+
+    // A click on the chart moves the replay there.
+    if let Some(pos) = response.interact_pointer_pos() {
+        if response.clicked() || response.dragged() {
+            let fraction = ((pos.x - response.rect.left()) / response.rect.width()) as f64;
+            let time = start + fraction * (end - start);
+            let position = replay.log.times.partition_point(|t| *t <= time);
+            replay.seek(position.saturating_sub(1));
+        }
+    }
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_files_have_the_old_columns_and_utc_times() {
+        let text = "17/10/2024\t 08:30:00\t1.50\t2.25\t3.00\t4.00\t5.00\t6.00\t7.00\t\r\n\
+                    17/10/2024\t 08:30:01\t1.60\t2.35\t3.10\t4.10\t5.10\t6.10\t7.10\t\r\n";
+        let log = parse_log(text).unwrap();
+        assert_eq!(log.columns, LEGACY_COLUMNS);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.skipped, 0);
+        let start = NaiveDateTime::parse_from_str("17/10/2024 08:30:00", "%d/%m/%Y %H:%M:%S")
+            .unwrap()
+            .and_utc()
+            .timestamp() as f64;
+        assert_eq!(log.times, [start, start + 1.]);
+        assert_eq!(log.series[1][0].value, 2.25);
+        assert_eq!(log.series[6][1].value, 7.10);
+    }
+
+    #[test]
+    fn a_malformed_first_row_is_not_a_header() {
+        let text = "17/10/2024\t 8h30\t1.50\t2.25\t\r\n\
+                    17/10/2024\t 08:30:01\t1.60\t2.35\t\r\n";
+        let log = parse_log(text).unwrap();
+        assert_eq!(log.columns, LEGACY_COLUMNS);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.skipped, 1);
+    }
+
+    #[test]
+    fn values_that_are_not_good_are_left_out() {
+        let text = "Date,Time (UTC),PT1-1,PT1-1 quality,LT1-1,LT1-1 quality\r\n\
+                    17/10/2024,08:30:00,1.50,Good,2.00,Stale\r\n\
+                    17/10/2024,08:30:01,1.60,Comm fail,,\r\n";
+        let log = parse_log(text).unwrap();
+        assert_eq!(log.columns, ["PT1-1", "LT1-1"]);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.series[0].len(), 1);
+        assert_eq!(log.series[0][0].value, 1.50);
+        assert!(log.series[1].is_empty());
+    }
+
+    #[test]
+    fn headered_files_name_their_columns() {
+        let text = "Date,Time (Local),PT1-1,\"Flow, total\"\r\n\
+                    17/10/2024,08:30:00,1.50,\r\n\
+                    17/10/2024,08:30:01,1.60,10.5\r\n";
+        let log = parse_log(text).unwrap();
+        assert_eq!(log.columns, ["PT1-1", "Flow, total"]);
+        assert_eq!(log.len(), 2);
+        let start = Local
+            .with_ymd_and_hms(2024, 10, 17, 8, 30, 0)
+            .unwrap()
+            .timestamp() as f64;
+        assert_eq!(log.times[0], start);
+        // The empty field is left out of its series.
+        assert_eq!(log.series[1].len(), 1);
+        assert_eq!(log.series[1][0].time, start + 1.);
+    }
+
+    #[test]
+    fn the_mimic_shows_a_replayed_row_only_when_it_was_good() {
+        let text = "Date,Time (UTC),PT1-1,PT1-1 quality\r\n\
+                    17/10/2024,08:30:00,1.50,Good\r\n\
+                    17/10/2024,08:30:01,1.60,Comm fail\r\n";
+        let log = parse_log(text).unwrap();
+        let mut replay = Replay {
+            path: PathBuf::new(),
+            shown: vec![true],
+            from: 0,
+            to: 1,
+            position: 0,
+            playing: false,
+            speed: 1.,
+            play_time: log.time(0),
+            on_mimic: true,
+            log,
+        };
+        let live = Tag {
+            name: "PT1-1".to_string(),
+            ..Default::default()
+        };
+
+        let mut shown = live.clone();
+        replay.apply(&mut shown);
+        assert_eq!(shown.quality, Quality::Good);
+        assert_eq!(shown.value, 1.5);
+
+        // The last good value isn't carried through the outage.
+        replay.seek(1);
+        let mut shown = live.clone();
+        replay.apply(&mut shown);
+        assert_eq!(shown.quality, Quality::Bad);
+        assert_eq!(shown.display_value(), "----");
+    }
+}
@@ -0,0 +1,438 @@
+use egui::{ComboBox, DragValue, Grid};
+use std::fmt::Display;
+
+//#################################################### Engineering unit scaling.
+
+/// Converts a raw device value into engineering units.
+///
+/// The curve is applied first, then the gain and offset, and the result is
+/// finally clamped if a clamp range is enabled.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Scaling {
+    pub curve: Curve,
+    pub gain: f64,
+    pub offset: f64,
+    pub clamp: bool,
+    pub clamp_low: f64,
+    pub clamp_high: f64,
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            curve: Curve::None,
+            gain: 1.0,
+            offset: 0.0,
+            clamp: false,
+            clamp_low: 0.0,
+            clamp_high: 100.0,
+        }
+    }
+}
+
+impl Scaling {
+    pub fn apply(&self, raw: f64) -> f64 {
+        let value = self.curve.apply(raw) * self.gain + self.offset;
+        if self.clamp {
+            value.clamp(
+                self.clamp_low.min(self.clamp_high),
+                self.clamp_high.max(self.clamp_low),
+            )
+        } else {
+            value
+        }
+    }
+
+    /// The raw value giving `eu`, used to write setpoints. The clamp is
+    /// ignored. Returns `None` if the scaling can't be inverted.
+    pub fn raw(&self, eu: f64) -> Option<f64> {
+        if !self.is_invertible() {
+            return None;
+        }
+        self.curve.raw((eu - self.offset) / self.gain)
+    }
+
+    /// Whether each engineering value comes from a single raw value, so a
+    /// setpoint can be written.
+    pub fn is_invertible(&self) -> bool {
+        self.gain != 0.0 && self.curve.is_invertible()
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub enum Curve {
+    None,
+    /// Maps the raw range linearly onto the engineering range.
+    Linear(Range),
+    /// Square root extraction for flows measured by differential pressure.
+    /// Raw values below the low end of the range give the low end of the
+    /// engineering range.
+    SquareRoot(Range),
+    /// Piecewise linear interpolation between `(raw, eu)` points. Values
+    /// outside the table are extrapolated from the first or last segment.
+    Table(Vec<(f64, f64)>),
+}
+
+impl Curve {
+    pub fn apply(&self, raw: f64) -> f64 {
+        match self {
+            Curve::None => raw,
+            Curve::Linear(range) => range.eu_low + range.span() * range.fraction(raw),
+            Curve::SquareRoot(range) => {
+                range.eu_low + range.span() * range.fraction(raw).max(0.0).sqrt()
+            }
+            Curve::Table(points) => interpolate(points, raw),
+        }
+    }
+
+    fn raw(&self, eu: f64) -> Option<f64> {
+        match self {
+            Curve::None => Some(eu),
+            Curve::Linear(range) => Some(range.raw(range.eu_fraction(eu)?)),
+            Curve::SquareRoot(range) => Some(range.raw(range.eu_fraction(eu)?.max(0.0).powi(2))),
+            // The table read backwards.
+            Curve::Table(points) => {
+                if !self.is_invertible() {
+                    return None;
+                }
+                let swapped: Vec<(f64, f64)> = points.iter().map(|(raw, eu)| (*eu, *raw)).collect();
+                Some(interpolate(&swapped, eu))
+            }
+        }
+    }
+
+    /// An empty range, or a table whose engineering values don't strictly
+    /// rise or fall with the raw ones, maps several raw values onto one.
+    fn is_invertible(&self) -> bool {
+        match self {
+            Curve::None => true,
+            Curve::Linear(range) | Curve::SquareRoot(range) => {
+                range.span() != 0.0 && range.raw_high != range.raw_low
+            }
+            Curve::Table(points) => {
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let steps: Vec<f64> = points
+                    .windows(2)
+                    .map(|pair| pair[1].1 - pair[0].1)
+                    .collect();
+                !steps.is_empty()
+                    && (steps.iter().all(|step| *step > 0.0)
+                        || steps.iter().all(|step| *step < 0.0))
+            }
+        }
+    }
+}
+
+impl Display for Curve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Curve::None => write!(f, "None"),
+            Curve::Linear(_) => write!(f, "Linear"),
+            Curve::SquareRoot(_) => write!(f, "Square root"),
+            Curve::Table(_) => write!(f, "Lookup table"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub struct Range {
+    pub raw_low: f64,
+    pub raw_high: f64,
+    pub eu_low: f64,
+    pub eu_high: f64,
+}
+
+impl Default for Range {
+    /// A 4-20 mA analog input card giving 0..27648 counts, mapped onto 0..100 %.
+    fn default() -> Self {
+        Self {
+            raw_low: 0.0,
+            raw_high: 27648.0,
+            eu_low: 0.0,
+            eu_high: 100.0,
+        }
+    }
+}
+
+impl Range {
+    fn span(&self) -> f64 {
+        self.eu_high - self.eu_low
+    }
+
+    fn fraction(&self, raw: f64) -> f64 {
+        let raw_span = self.raw_high - self.raw_low;
+        if raw_span == 0.0 {
+            0.0
+        } else {
+            (raw - self.raw_low) / raw_span
+        }
+    }
+
+    fn eu_fraction(&self, eu: f64) -> Option<f64> {
+        if self.span() == 0.0 {
+            None
+        } else {
+            Some((eu - self.eu_low) / self.span())
+        }
+    }
+
+    fn raw(&self, fraction: f64) -> f64 {
+        self.raw_low + (self.raw_high - self.raw_low) * fraction
+    }
+}
+
+fn interpolate(points: &[(f64, f64)], raw: f64) -> f64 {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    match points.len() {
+        0 => raw,
+        1 => points[0].1,
+        len => {
+            // We pick the segment containing the raw value, or the closest one.
+            let upper = points
+                .iter()
+                .position(|(x, _)| *x >= raw)
+                .unwrap_or(len - 1)
+                .max(1);
+            let (x0, y0) = points[upper - 1];
+            let (x1, y1) = points[upper];
+            if x1 == x0 {
+                y0
+            } else {
+                y0 + (y1 - y0) * (raw - x0) / (x1 - x0)
+            }
+        }
+    }
+}
+//####################################################
+
+//#################################################### Scaling editor.
+
+/// Returns true if the scaling was changed.
+pub fn scaling_ui(ui: &mut egui::Ui, scaling: &mut Scaling) -> bool {
+    let mut changed = false;
+    ComboBox::from_label("Curve")
+        .selected_text(format!("{}", scaling.curve))
+        .show_ui(ui, |ui| {
+            let curves = [
+                Curve::None,
+                Curve::Linear(Range::default()),
+                Curve::SquareRoot(Range::default()),
+                Curve::Table(vec![(0.0, 0.0), (27648.0, 100.0)]),
+            ];
+            for curve in curves {
+                let selected =
+                    std::mem::discriminant(&scaling.curve) == std::mem::discriminant(&curve);
+                if ui
+                    .selectable_label(selected, format!("{}", curve))
+                    .clicked()
+                    && !selected
+                {
+                    scaling.curve = curve;
+                    changed = true;
+                }
+            }
+        });
+
+    match &mut scaling.curve {
+        Curve::None => {}
+        Curve::Linear(range) | Curve::SquareRoot(range) => {
+            Grid::new("scaling_range").num_columns(4).show(ui, |ui| {
+                ui.label("Raw low");
+                changed |= ui.add(DragValue::new(&mut range.raw_low)).changed();
+                ui.label("Raw high");
+                changed |= ui.add(DragValue::new(&mut range.raw_high)).changed();
+                ui.end_row();
+                ui.label("EU low");
+                changed |= ui
+                    .add(DragValue::new(&mut range.eu_low).speed(0.1))
+                    .changed();
+                ui.label("EU high");
+                changed |= ui
+                    .add(DragValue::new(&mut range.eu_high).speed(0.1))
+                    .changed();
+                ui.end_row();
+            });
+        }
+        Curve::Table(points) => {
+            let mut remove = None;
+            Grid::new("scaling_table")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Raw");
+                    ui.strong("EU");
+                    ui.end_row();
+                    for (i, (raw, eu)) in points.iter_mut().enumerate() {
+                        changed |= ui.add(DragValue::new(raw)).changed();
+                        changed |= ui.add(DragValue::new(eu).speed(0.1)).changed();
+                        if ui.button(egui_phosphor::regular::TRASH).clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(i) = remove {
+                points.remove(i);
+                changed = true;
+            }
+            if ui
+                .button(format!("{} Add point", egui_phosphor::regular::PLUS))
+                .clicked()
+            {
+                let last = points.last().copied().unwrap_or((0.0, 0.0));
+                points.push(last);
+                changed = true;
+            }
+        }
+    }
+
+    ui.separator();
+    Grid::new("scaling_gain").num_columns(4).show(ui, |ui| {
+        ui.label("Gain");
+        changed |= ui
+            .add(DragValue::new(&mut scaling.gain).speed(0.01))
+            .changed();
+        ui.label("Offset");
+        changed |= ui
+            .add(DragValue::new(&mut scaling.offset).speed(0.1))
+            .changed();
+        ui.end_row();
+        changed |= ui.checkbox(&mut scaling.clamp, "Clamp").changed();
+        ui.end_row();
+        ui.label("Low");
+        changed |= ui
+            .add_enabled(
+                scaling.clamp,
+                DragValue::new(&mut scaling.clamp_low).speed(0.1),
+            )
+            .changed();
+        ui.label("High");
+        changed |= ui
+            .add_enabled(
+                scaling.clamp,
+                DragValue::new(&mut scaling.clamp_high).speed(0.1),
+            )
+            .changed();
+        ui.end_row();
+    });
+    changed
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaling(curve: Curve) -> Scaling {
+        Scaling {
+            curve,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn linear_maps_the_raw_range_onto_the_engineering_range() {
+        let scaling = scaling(Curve::Linear(Range::default()));
+        assert_eq!(scaling.apply(0.0), 0.0);
+        assert_eq!(scaling.apply(13824.0), 50.0);
+        assert_eq!(scaling.apply(27648.0), 100.0);
+        // Not clamped unless asked to.
+        assert_eq!(scaling.apply(-2764.8), -10.0);
+    }
+
+    #[test]
+    fn square_root_extracts_and_holds_the_low_end_below_the_range() {
+        let scaling = scaling(Curve::SquareRoot(Range {
+            raw_low: 0.0,
+            raw_high: 100.0,
+            eu_low: 0.0,
+            eu_high: 50.0,
+        }));
+        assert_eq!(scaling.apply(25.0), 25.0);
+        assert_eq!(scaling.apply(100.0), 50.0);
+        assert_eq!(scaling.apply(-20.0), 0.0);
+    }
+
+    #[test]
+    fn table_interpolates_and_extrapolates_from_the_end_segments() {
+        // Points in any order.
+        let scaling = scaling(Curve::Table(vec![
+            (100.0, 50.0),
+            (0.0, 0.0),
+            (200.0, 200.0),
+        ]));
+        assert_eq!(scaling.apply(50.0), 25.0);
+        assert_eq!(scaling.apply(150.0), 125.0);
+        assert_eq!(scaling.apply(-100.0), -50.0);
+        assert_eq!(scaling.apply(300.0), 350.0);
+
+        assert_eq!(Curve::Table(vec![]).apply(7.0), 7.0);
+        assert_eq!(Curve::Table(vec![(0.0, 3.0)]).apply(7.0), 3.0);
+    }
+
+    #[test]
+    fn gain_and_offset_follow_the_curve_and_the_clamp_comes_last() {
+        let mut scaling = Scaling {
+            curve: Curve::Linear(Range::default()),
+            gain: 2.0,
+            offset: -10.0,
+            ..Default::default()
+        };
+        assert_eq!(scaling.apply(13824.0), 90.0);
+        assert_eq!(scaling.apply(27648.0), 190.0);
+
+        scaling.clamp = true;
+        assert_eq!(scaling.apply(27648.0), 100.0);
+        assert_eq!(scaling.apply(0.0), 0.0);
+        // Swapped clamp bounds still clamp.
+        scaling.clamp_low = 100.0;
+        scaling.clamp_high = 0.0;
+        assert_eq!(scaling.apply(27648.0), 100.0);
+    }
+
+    #[test]
+    fn each_curve_is_inverted_for_setpoints() {
+        let curves = [
+            Curve::None,
+            Curve::Linear(Range::default()),
+            Curve::SquareRoot(Range::default()),
+            Curve::Table(vec![(0.0, 0.0), (100.0, 50.0), (200.0, 200.0)]),
+            Curve::Table(vec![(0.0, 100.0), (1000.0, 0.0)]),
+        ];
+        let raws = [25.0, 13824.0, 6912.0, 150.0, 250.0];
+        for (curve, raw) in curves.into_iter().zip(raws) {
+            let mut scaling = scaling(curve);
+            assert_eq!(scaling.raw(scaling.apply(raw)), Some(raw), "{:?}", scaling);
+            scaling.gain = 2.0;
+            scaling.offset = -10.0;
+            assert_eq!(scaling.raw(scaling.apply(raw)), Some(raw), "{:?}", scaling);
+        }
+    }
+
+    #[test]
+    fn a_scaling_that_folds_raw_values_together_is_not_inverted() {
+        let folded = [
+            Curve::Table(vec![(0.0, 0.0), (100.0, 50.0), (200.0, 20.0)]),
+            Curve::Table(vec![(0.0, 10.0), (100.0, 10.0)]),
+            Curve::Table(vec![(0.0, 10.0)]),
+            Curve::Linear(Range {
+                eu_high: 0.0,
+                ..Default::default()
+            }),
+        ];
+        for curve in folded {
+            let scaling = scaling(curve);
+            assert!(!scaling.is_invertible(), "{:?}", scaling);
+            assert_eq!(scaling.raw(5.0), None);
+        }
+        let flat = Scaling {
+            gain: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(flat.raw(5.0), None);
+    }
+}
@@ -0,0 +1,272 @@
+use egui::ComboBox;
+use std::fmt::Display;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::time::{interval, Instant, Interval, MissedTickBehavior};
+
+//#################################################### Fixed-rate scan scheduling.
+
+/// The shortest scan period, so a zero period doesn't spin.
+const MIN_PERIOD: Duration = Duration::from_millis(10);
+
+/// How often a tag or read block is scanned. The periods of the classes are
+/// set per device.
+#[derive(
+    serde::Deserialize, serde::Serialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy,
+)]
+pub enum ScanClass {
+    Fast,
+    Normal,
+    Slow,
+    /// Only read when the operator asks for it.
+    OnDemand,
+}
+
+impl ScanClass {
+    pub fn all() -> [ScanClass; 4] {
+        [
+            ScanClass::Fast,
+            ScanClass::Normal,
+            ScanClass::Slow,
+            ScanClass::OnDemand,
+        ]
+    }
+}
+
+impl Default for ScanClass {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl Display for ScanClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanClass::Fast => write!(f, "Fast"),
+            ScanClass::Normal => write!(f, "Normal"),
+            ScanClass::Slow => write!(f, "Slow"),
+            ScanClass::OnDemand => write!(f, "On demand"),
+        }
+    }
+}
+
+/// The timing of a scan class: how late its scans start and how many ticks
+/// they missed.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScanStats {
+    pub class: ScanClass,
+    pub period: Duration,
+    pub scans: u64,
+    /// Ticks skipped because a scan ran past them.
+    pub overruns: u64,
+    /// How late the last scan started.
+    pub jitter: Duration,
+    pub max_jitter: Duration,
+    total_jitter: Duration,
+    /// When the last scan was due.
+    last_due: Option<Instant>,
+    /// When the first and the last scans started, for the achieved rate.
+    first_start: Option<Instant>,
+    last_start: Option<Instant>,
+}
+
+impl ScanStats {
+    pub fn new(class: ScanClass, period: Duration) -> Self {
+        Self {
+            class,
+            period,
+            scans: 0,
+            overruns: 0,
+            jitter: Duration::ZERO,
+            max_jitter: Duration::ZERO,
+            total_jitter: Duration::ZERO,
+            last_due: None,
+            first_start: None,
+            last_start: None,
+        }
+    }
+
+    /// Records a scan that was due at `due` and started at `started`.
+    pub fn record(&mut self, due: Instant, started: Instant) {
+        if let Some(last_due) = self.last_due {
+            // The ticks in between were skipped by a scan that ran late.
+            let ticks =
+                due.saturating_duration_since(last_due).as_nanos() / self.period.as_nanos().max(1);
+            self.overruns += (ticks as u64).saturating_sub(1);
+        }
+        self.last_due = Some(due);
+        self.jitter = started.saturating_duration_since(due);
+        self.max_jitter = self.max_jitter.max(self.jitter);
+        self.total_jitter += self.jitter;
+        self.scans += 1;
+        self.first_start.get_or_insert(started);
+        self.last_start = Some(started);
+    }
+
+    /// The scans per second since the first one.
+    pub fn rate(&self) -> f64 {
+        match (self.first_start, self.last_start) {
+            (Some(first), Some(last)) if last > first => {
+                (self.scans - 1) as f64 / (last - first).as_secs_f64()
+            }
+            _ => 0.,
+        }
+    }
+
+    /// The mean lateness of the scans.
+    pub fn mean_jitter(&self) -> Duration {
+        if self.scans == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos((self.total_jitter.as_nanos() / self.scans as u128) as u64)
+        }
+    }
+}
+
+impl Display for ScanStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ms: {:.2} scans/s  jitter {} μs (mean {} μs, max {} μs)  overruns {}",
+            self.class,
+            self.period.as_millis(),
+            self.rate(),
+            self.jitter.as_micros(),
+            self.mean_jitter().as_micros(),
+            self.max_jitter.as_micros(),
+            self.overruns
+        )
+    }
+}
+
+/// Fires the scan classes of a connection, each at its own fixed rate.
+///
+/// The ticks are set from the start of the first scan, so the time a scan
+/// takes doesn't add to the period. A scan that runs past the next tick
+/// skips it instead of bursting to catch up. The classes due together take
+/// turns, so a class that overruns its period can't starve the others.
+pub struct Scheduler {
+    intervals: Vec<Interval>,
+    pub stats: Vec<ScanStats>,
+    /// The class polled first, rotated so classes due together take turns.
+    next: usize,
+}
+
+impl Scheduler {
+    /// A scheduler of classes scanning at their periods, all due right away.
+    /// Must be called from within the runtime.
+    pub fn new(classes: &[(ScanClass, Duration)]) -> Self {
+        let intervals = classes
+            .iter()
+            .map(|(_, period)| {
+                let mut interval = interval((*period).max(MIN_PERIOD));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                interval
+            })
+            .collect();
+        Self {
+            intervals,
+            stats: classes
+                .iter()
+                .map(|(class, period)| ScanStats::new(*class, (*period).max(MIN_PERIOD)))
+                .collect(),
+            next: 0,
+        }
+    }
+
+    /// Waits for the next class due and returns its index. Never returns
+    /// without classes.
+    pub async fn tick(&mut self) -> usize {
+        let count = self.intervals.len();
+        let next = self.next;
+        let intervals = &mut self.intervals;
+        let (index, due) = poll_fn(|cx| {
+            for offset in 0..count {
+                let index = (next + offset) % count;
+                if let Poll::Ready(due) = intervals[index].poll_tick(cx) {
+                    return Poll::Ready((index, due));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        self.next = (index + 1) % count;
+        self.stats[index].record(due, Instant::now());
+        index
+    }
+}
+
+/// The picker of the scan class of a tag or read block.
+/// Returns true if another class was picked.
+pub fn scan_class_ui(ui: &mut egui::Ui, id_source: impl Hash, class: &mut ScanClass) -> bool {
+    let mut changed = false;
+    ComboBox::from_id_source(id_source)
+        .selected_text(format!("{}", class))
+        .show_ui(ui, |ui| {
+            for scan_class in ScanClass::all() {
+                let text = format!("{}", scan_class);
+                changed |= ui.selectable_value(class, scan_class, text).changed();
+            }
+        });
+    changed
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_scans_count_as_jitter_and_skipped_ticks_as_overruns() {
+        let period = Duration::from_millis(100);
+        let mut stats = ScanStats::new(ScanClass::Normal, period);
+        let start = Instant::now();
+
+        stats.record(start, start + Duration::from_millis(2));
+        stats.record(start + period, start + period + Duration::from_millis(6));
+        assert_eq!(stats.overruns, 0);
+        assert_eq!(stats.jitter, Duration::from_millis(6));
+        assert_eq!(stats.max_jitter, Duration::from_millis(6));
+        assert_eq!(stats.mean_jitter(), Duration::from_millis(4));
+
+        // A long scan ran past the next two ticks.
+        let due = start + period * 4;
+        stats.record(due, due);
+        assert_eq!(stats.overruns, 2);
+        assert_eq!(stats.jitter, Duration::ZERO);
+        assert_eq!(stats.scans, 3);
+        // Two scans in the 398 ms between the first and the last start.
+        assert!((stats.rate() - 2. / 0.398).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_class_that_overruns_doesnt_starve_the_others() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let ms = Duration::from_millis;
+            let mut scheduler =
+                Scheduler::new(&[(ScanClass::Fast, ms(100)), (ScanClass::Slow, ms(1000))]);
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(10) {
+                // The fast scans take longer than their period.
+                match scheduler.tick().await {
+                    0 => tokio::time::sleep(ms(150)).await,
+                    _ => tokio::time::sleep(ms(10)).await,
+                }
+            }
+            let [fast, slow] = &scheduler.stats[..] else {
+                panic!("two classes were scheduled");
+            };
+            assert!(slow.scans >= 10);
+            assert!(slow.max_jitter <= ms(150));
+            assert!(fast.overruns > 0);
+            assert!((6.0..7.0).contains(&fast.rate()));
+        });
+    }
+}
@@ -0,0 +1,944 @@
+use egui::{Button, Color32, ComboBox, DragValue, Grid, ScrollArea, TextEdit};
+use s7::transport::Transport;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::codec::Value;
+use crate::health::{Failure, ScanError};
+
+//#################################################### S7 addressing.
+
+/// A memory area of an S7 PLC.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Area {
+    DataBlock(u16),
+    Merker,
+    Input,
+    Output,
+}
+
+impl Area {
+    /// The area code and DB number of the S7 protocol.
+    fn code(&self) -> (u8, u16) {
+        match self {
+            Area::Input => (0x81, 0),
+            Area::Output => (0x82, 0),
+            Area::Merker => (0x83, 0),
+            Area::DataBlock(db) => (0x84, *db),
+        }
+    }
+}
+
+/// The access width given by the address, like the `W` of `DB1.DBW4`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Width {
+    Bit,
+    Byte,
+    Word,
+    DoubleWord,
+}
+
+/// A parsed S7 address like `DB1.DBX0.3`, `DB10.DBD8`, `M20.1`, `MW4`, `IB0` or `QD8`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Address {
+    pub area: Area,
+    pub byte: u32,
+    pub bit: u8,
+    width: Width,
+}
+
+impl Address {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("\"{}\" is not an S7 address.", text);
+        let upper = text.trim().to_uppercase();
+
+        let (area, rest) = if let Some(rest) = upper.strip_prefix("DB") {
+            let (db, rest) = rest.split_once(".DB").ok_or_else(invalid)?;
+            let db = db.parse().map_err(|_| invalid())?;
+            (Area::DataBlock(db), rest)
+        } else if let Some(rest) = upper.strip_prefix('M') {
+            (Area::Merker, rest)
+        } else if let Some(rest) = upper.strip_prefix('I').or(upper.strip_prefix('E')) {
+            (Area::Input, rest)
+        } else if let Some(rest) = upper.strip_prefix('Q').or(upper.strip_prefix('A')) {
+            (Area::Output, rest)
+        } else {
+            return Err(invalid());
+        };
+
+        // Bits are written without a letter outside the data blocks, `M20.1`.
+        let (width, rest) = match rest.chars().next() {
+            Some('X') => (Width::Bit, &rest[1..]),
+            Some('B') => (Width::Byte, &rest[1..]),
+            Some('W') => (Width::Word, &rest[1..]),
+            Some('D') => (Width::DoubleWord, &rest[1..]),
+            Some(c) if c.is_ascii_digit() && !matches!(area, Area::DataBlock(_)) => {
+                (Width::Bit, rest)
+            }
+            _ => return Err(invalid()),
+        };
+        let (byte, bit) = match (width, rest.split_once('.')) {
+            (Width::Bit, Some((byte, bit))) => (byte, bit.parse().map_err(|_| invalid())?),
+            (Width::Bit, None) => return Err(invalid()),
+            (_, Some(_)) => return Err(invalid()),
+            (_, None) => (rest, 0),
+        };
+        if bit > 7 {
+            return Err(format!("{}: bits go from 0 to 7.", text.trim()));
+        }
+        Ok(Self {
+            area,
+            byte: byte.parse().map_err(|_| invalid())?,
+            bit,
+            width,
+        })
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let letter = match self.width {
+            Width::Bit => "X",
+            Width::Byte => "B",
+            Width::Word => "W",
+            Width::DoubleWord => "D",
+        };
+        let area = match self.area {
+            Area::DataBlock(db) => format!("DB{}.DB{}", db, letter),
+            Area::Merker => format!("M{}", letter),
+            Area::Input => format!("I{}", letter),
+            Area::Output => format!("Q{}", letter),
+        };
+        match self.width {
+            Width::Bit => write!(f, "{}{}.{}", area, self.byte, self.bit),
+            _ => write!(f, "{}{}", area, self.byte),
+        }
+    }
+}
+//####################################################
+
+//#################################################### S7 data types.
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum DataType {
+    Bool,
+    Int,
+    DInt,
+    Real,
+    Word,
+    /// An S7 string of the given maximum length. It starts with the maximum
+    /// and the actual length bytes.
+    String(u8),
+}
+
+impl DataType {
+    pub fn all() -> [DataType; 6] {
+        [
+            DataType::Bool,
+            DataType::Int,
+            DataType::DInt,
+            DataType::Real,
+            DataType::Word,
+            DataType::String(32),
+        ]
+    }
+
+    /// The number of bytes read, a whole byte for a bool.
+    fn size(&self) -> u32 {
+        match self {
+            DataType::Bool => 1,
+            DataType::Int | DataType::Word => 2,
+            DataType::DInt | DataType::Real => 4,
+            DataType::String(length) => *length as u32 + 2,
+        }
+    }
+
+    /// Checks the address has the width of the type.
+    fn check(&self, address: &Address) -> Result<(), String> {
+        let width = match self {
+            DataType::Bool => Width::Bit,
+            DataType::Int | DataType::Word => Width::Word,
+            DataType::DInt | DataType::Real => Width::DoubleWord,
+            DataType::String(_) => Width::Byte,
+        };
+        if address.width == width {
+            Ok(())
+        } else {
+            let example = Address {
+                width,
+                bit: 0,
+                ..*address
+            };
+            Err(format!(
+                "A {} needs an address like {}, not {}.",
+                self, example, address
+            ))
+        }
+    }
+
+    /// Decodes the value from the bytes read at `address`.
+    fn decode(&self, bytes: &[u8], address: &Address) -> Option<Value> {
+        let bytes = bytes.get(..self.size() as usize)?;
+        let value = match self {
+            DataType::Bool => (bytes[0] >> address.bit & 1) as f64,
+            DataType::Int => i16::from_be_bytes(bytes.try_into().ok()?) as f64,
+            DataType::Word => u16::from_be_bytes(bytes.try_into().ok()?) as f64,
+            DataType::DInt => i32::from_be_bytes(bytes.try_into().ok()?) as f64,
+            DataType::Real => f32::from_be_bytes(bytes.try_into().ok()?) as f64,
+            DataType::String(_) => {
+                let length = (bytes[1] as usize).min(bytes.len() - 2);
+                let text = bytes[2..2 + length]
+                    .iter()
+                    .map(|byte| *byte as char)
+                    .collect();
+                return Some(Value::Text(text));
+            }
+        };
+        Some(Value::Number(value))
+    }
+
+    /// Encodes a value to write, a bool being a single bit.
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let out_of_range = |value: f64| format!("{} is out of range for an S7 {}.", value, self);
+        match (self, value) {
+            (DataType::String(max), Value::Text(text)) => {
+                if !text.is_ascii() {
+                    return Err("Only ASCII text can be written.".to_string());
+                }
+                if text.len() > *max as usize {
+                    return Err(format!("The text is longer than {} characters.", max));
+                }
+                let mut bytes = vec![*max, text.len() as u8];
+                bytes.extend_from_slice(text.as_bytes());
+                Ok(bytes)
+            }
+            (DataType::String(_), Value::Number(_)) => {
+                Err("A string tag needs a text value.".to_string())
+            }
+            (_, Value::Text(_)) => Err(format!("An S7 {} needs a number.", self)),
+            (_, Value::Number(value)) if !value.is_finite() => {
+                Err("The value is not a number.".to_string())
+            }
+            (DataType::Bool, Value::Number(value)) => match *value {
+                v if v == 0.0 => Ok(vec![0]),
+                v if v == 1.0 => Ok(vec![1]),
+                v => Err(format!("A Bool is 0 or 1, not {}.", v)),
+            },
+            (DataType::Int, Value::Number(value)) => {
+                let v = value.round();
+                if v < i16::MIN as f64 || v > i16::MAX as f64 {
+                    return Err(out_of_range(v));
+                }
+                Ok((v as i16).to_be_bytes().to_vec())
+            }
+            (DataType::Word, Value::Number(value)) => {
+                let v = value.round();
+                if v < 0.0 || v > u16::MAX as f64 {
+                    return Err(out_of_range(v));
+                }
+                Ok((v as u16).to_be_bytes().to_vec())
+            }
+            (DataType::DInt, Value::Number(value)) => {
+                let v = value.round();
+                if v < i32::MIN as f64 || v > i32::MAX as f64 {
+                    return Err(out_of_range(v));
+                }
+                Ok((v as i32).to_be_bytes().to_vec())
+            }
+            (DataType::Real, Value::Number(value)) => {
+                if value.abs() > f32::MAX as f64 {
+                    return Err(out_of_range(*value));
+                }
+                Ok((*value as f32).to_be_bytes().to_vec())
+            }
+        }
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Bool => write!(f, "Bool"),
+            DataType::Int => write!(f, "Int"),
+            DataType::DInt => write!(f, "DInt"),
+            DataType::Real => write!(f, "Real"),
+            DataType::Word => write!(f, "Word"),
+            DataType::String(_) => write!(f, "String"),
+        }
+    }
+}
+
+/// A value read from an S7 PLC, published under the name of a tag.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub struct S7Tag {
+    pub name: String,
+    pub address: String,
+    pub data_type: DataType,
+}
+
+impl S7Tag {
+    fn new(name: &str, address: &str, data_type: DataType) -> Self {
+        Self {
+            name: name.to_string(),
+            address: address.to_string(),
+            data_type,
+        }
+    }
+
+    /// The parsed address, checked against the data type.
+    pub fn address(&self) -> Result<Address, String> {
+        let address = Address::parse(&self.address)?;
+        self.data_type.check(&address)?;
+        Ok(address)
+    }
+}
+
+/// The two process values of the test PLC Carbon was first connected to.
+pub fn default_s7_tags() -> Vec<S7Tag> {
+    vec![
+        S7Tag::new("S7-REAL-1", "DB1.DBD4", DataType::Real),
+        S7Tag::new("S7-REAL-2", "DB1.DBD8", DataType::Real),
+    ]
+}
+//####################################################
+
+//#################################################### S7 protocol.
+
+/// The Siemens limit on the number of variables of a single request.
+const MAX_ITEMS: usize = 20;
+/// The S7 header of a job, without the TPKT and COTP headers.
+const JOB_HEADER: usize = 10;
+/// The S7 header of an acknowledgement, with the error class and code.
+const ACK_HEADER: usize = 12;
+/// The TPKT and COTP headers in front of every S7 PDU.
+const ISO_HEADER: usize = 7;
+/// Two areas closer than this many bytes are read together.
+const MAX_GAP: u32 = 16;
+
+const READ_VAR: u8 = 0x04;
+const WRITE_VAR: u8 = 0x05;
+const TRANSPORT_BIT: u8 = 0x01;
+const TRANSPORT_BYTE: u8 = 0x02;
+
+/// A range of bytes read with one variable of a request.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Span {
+    area: Area,
+    start: u32,
+    length: u32,
+}
+
+/// Wraps an S7 PDU made of `parameters` and `data` in a job telegram.
+fn telegram(parameters: &[u8], data: &[u8]) -> Vec<u8> {
+    let length = ISO_HEADER + JOB_HEADER + parameters.len() + data.len();
+    let mut telegram = vec![3, 0];
+    telegram.extend_from_slice(&(length as u16).to_be_bytes());
+    telegram.extend_from_slice(&[2, 0xF0, 0x80]);
+    telegram.extend_from_slice(&[0x32, 0x01, 0, 0, 0, 0]);
+    telegram.extend_from_slice(&(parameters.len() as u16).to_be_bytes());
+    telegram.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    telegram.extend_from_slice(parameters);
+    telegram.extend_from_slice(data);
+    telegram
+}
+
+/// The 12 byte specification of a variable.
+fn item(area: Area, transport: u8, count: u16, address: u32) -> [u8; 12] {
+    let (area, db) = area.code();
+    let count = count.to_be_bytes();
+    let db = db.to_be_bytes();
+    let address = address.to_be_bytes();
+    [
+        0x12, 0x0A, 0x10, transport, count[0], count[1], db[0], db[1], area, address[1],
+        address[2], address[3],
+    ]
+}
+
+/// Tells the failures of the S7 transport apart.
+fn transport_error(error: s7::error::Error) -> ScanError {
+    use s7::error::Error;
+    let failure = match &error {
+        Error::IOError(kind) => Failure::from(*kind),
+        Error::Response { .. } | Error::CPU { .. } => Failure::Exception,
+        Error::Iso | Error::PduLength(_) | Error::TryFrom(..) | Error::InvalidResponse { .. } => {
+            Failure::Crc
+        }
+        _ => Failure::Other,
+    };
+    ScanError::new(failure, error.to_string())
+}
+
+fn short_response() -> ScanError {
+    ScanError::new(Failure::Crc, "The PLC sent a short response.")
+}
+
+/// Checks the header of an acknowledgement and returns its data part.
+fn acknowledgement(response: &[u8], function: u8) -> Result<&[u8], ScanError> {
+    let header = response
+        .get(ISO_HEADER..ISO_HEADER + ACK_HEADER + 2)
+        .ok_or_else(short_response)?;
+    let (class, code) = (header[10], header[11]);
+    if class != 0 || code != 0 {
+        return Err(ScanError::new(
+            Failure::Exception,
+            format!(
+                "The PLC refused the request, error {:#04x} {:#04x}.",
+                class, code
+            ),
+        ));
+    }
+    if header[12] != function {
+        return Err(ScanError::new(
+            Failure::Crc,
+            "The PLC answered another request.",
+        ));
+    }
+    let parameters = u16::from_be_bytes([header[6], header[7]]) as usize;
+    Ok(response
+        .get(ISO_HEADER + ACK_HEADER + parameters..)
+        .unwrap_or_default())
+}
+
+fn return_code(code: u8) -> String {
+    match code {
+        0x01 => "Hardware fault".to_string(),
+        0x03 => "Access denied".to_string(),
+        0x05 => "Address out of range".to_string(),
+        0x06 => "Data type not supported".to_string(),
+        0x07 => "Data type inconsistent".to_string(),
+        0x0A => "Object does not exist".to_string(),
+        code => format!("Error {:#04x}", code),
+    }
+}
+
+/// Merges the byte ranges of the tags into the fewest spans, each small
+/// enough for a response of `pdu_length` bytes.
+fn spans(ranges: &[(Area, u32, u32)], pdu_length: usize) -> Vec<Span> {
+    let max_length = (pdu_length - ACK_HEADER - 2 - 4) as u32;
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|(area, start, _)| (area.code(), *start));
+
+    let mut spans: Vec<Span> = Vec::new();
+    for (area, start, length) in ranges {
+        match spans.last_mut() {
+            Some(span)
+                if span.area == area
+                    && start <= span.start + span.length + MAX_GAP
+                    && (start + length).max(span.start + span.length) - span.start
+                        <= max_length =>
+            {
+                span.length = (start + length).max(span.start + span.length) - span.start;
+            }
+            _ => spans.push(Span {
+                area,
+                start,
+                length,
+            }),
+        }
+    }
+    // A string longer than a PDU is read in pieces.
+    spans
+        .into_iter()
+        .flat_map(|span| {
+            (0..span.length)
+                .step_by(max_length as usize)
+                .map(move |offset| Span {
+                    area: span.area,
+                    start: span.start + offset,
+                    length: (span.length - offset).min(max_length),
+                })
+        })
+        .collect()
+}
+
+/// Groups the spans into requests, each within the item limit and with a
+/// request and a response that fit in `pdu_length` bytes.
+fn requests(spans: &[Span], pdu_length: usize) -> Vec<&[Span]> {
+    let mut requests = Vec::new();
+    let mut first = 0;
+    let mut response = ACK_HEADER + 2;
+    for (i, span) in spans.iter().enumerate() {
+        // Every item but the last is padded to an even length.
+        let size = 4 + span.length as usize + span.length as usize % 2;
+        let items = i - first + 1;
+        let request = JOB_HEADER + 2 + 12 * items;
+        if i > first && (items > MAX_ITEMS || response + size > pdu_length || request > pdu_length)
+        {
+            requests.push(&spans[first..i]);
+            first = i;
+            response = ACK_HEADER + 2;
+        }
+        response += size;
+    }
+    if first < spans.len() {
+        requests.push(&spans[first..]);
+    }
+    requests
+}
+
+/// Reads the spans with a single multi-variable request.
+fn read_spans(
+    transport: &mut impl Transport,
+    spans: &[Span],
+) -> Result<Vec<(Span, Vec<u8>)>, ScanError> {
+    let mut parameters = vec![READ_VAR, spans.len() as u8];
+    for span in spans {
+        parameters.extend_from_slice(&item(
+            span.area,
+            TRANSPORT_BYTE,
+            span.length as u16,
+            span.start * 8,
+        ));
+    }
+    let response = transport
+        .send(&telegram(&parameters, &[]))
+        .map_err(transport_error)?;
+    let mut data = acknowledgement(&response, READ_VAR)?;
+
+    let mut results = Vec::new();
+    for span in spans {
+        let header = data.get(..4).ok_or_else(short_response)?;
+        if header[0] != 0xFF {
+            return Err(ScanError::new(
+                Failure::Exception,
+                format!(
+                    "{} {}: {}.",
+                    Address {
+                        area: span.area,
+                        byte: span.start,
+                        bit: 0,
+                        width: Width::Byte,
+                    },
+                    span.length,
+                    return_code(header[0])
+                ),
+            ));
+        }
+        let bits = u16::from_be_bytes([header[2], header[3]]) as usize;
+        // Byte transports give the length in bits, the others in bytes.
+        let length = if matches!(header[1], 0x03..=0x05) {
+            (bits + 7) / 8
+        } else {
+            bits
+        };
+        let bytes = data.get(4..4 + length).ok_or_else(short_response)?;
+        results.push((*span, bytes.to_vec()));
+        data = data.get(4 + length + length % 2..).unwrap_or_default();
+    }
+    Ok(results)
+}
+
+/// Reads all the tags, batching them into as few requests as the PDU allows.
+/// Tags with an invalid address are left out.
+pub fn read_s7_tags(
+    transport: &mut impl Transport,
+    tags: &[S7Tag],
+) -> Result<HashMap<String, Value>, ScanError> {
+    let addresses: Vec<(&S7Tag, Address)> = tags
+        .iter()
+        .filter_map(|tag| Some((tag, tag.address().ok()?)))
+        .collect();
+    let ranges: Vec<(Area, u32, u32)> = addresses
+        .iter()
+        .map(|(tag, address)| (address.area, address.byte, tag.data_type.size()))
+        .collect();
+    let pdu_length = transport.pdu_length().max(240) as usize;
+    let spans = spans(&ranges, pdu_length);
+
+    let mut blocks: Vec<(Span, Vec<u8>)> = Vec::new();
+    for request in requests(&spans, pdu_length) {
+        for (span, bytes) in read_spans(transport, request)? {
+            // The pieces of a string longer than a PDU are joined back.
+            match blocks.last_mut() {
+                Some((last, data))
+                    if last.area == span.area && last.start + last.length == span.start =>
+                {
+                    last.length += span.length;
+                    data.extend(bytes);
+                }
+                _ => blocks.push((span, bytes)),
+            }
+        }
+    }
+
+    let mut values = HashMap::new();
+    for (tag, address) in addresses.iter() {
+        let block = blocks.iter().find(|(span, _)| {
+            span.area == address.area
+                && span.start <= address.byte
+                && address.byte + tag.data_type.size() <= span.start + span.length
+        });
+        if let Some((span, bytes)) = block {
+            let offset = (address.byte - span.start) as usize;
+            if let Some(value) = bytes
+                .get(offset..)
+                .and_then(|bytes| tag.data_type.decode(bytes, address))
+            {
+                values.insert(tag.name.clone(), value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Writes the value of the tag `name`, a single bit for a bool.
+pub fn write_s7_tag(
+    transport: &mut impl Transport,
+    tags: &[S7Tag],
+    name: &str,
+    value: &Value,
+) -> Result<(), String> {
+    let tag = tags
+        .iter()
+        .find(|tag| tag.name == name)
+        .ok_or(format!("{} is not in the S7 tag list.", name))?;
+    let address = tag.address()?;
+    let bytes = tag.data_type.encode(value)?;
+
+    let (parameters, data) = if tag.data_type == DataType::Bool {
+        let mut parameters = vec![WRITE_VAR, 1];
+        parameters.extend_from_slice(&item(
+            address.area,
+            TRANSPORT_BIT,
+            1,
+            address.byte * 8 + address.bit as u32,
+        ));
+        (parameters, vec![0, 0x03, 0, 1, bytes[0]])
+    } else {
+        let mut parameters = vec![WRITE_VAR, 1];
+        parameters.extend_from_slice(&item(
+            address.area,
+            TRANSPORT_BYTE,
+            bytes.len() as u16,
+            address.byte * 8,
+        ));
+        let mut data = vec![0, 0x04];
+        data.extend_from_slice(&(bytes.len() as u16 * 8).to_be_bytes());
+        data.extend_from_slice(&bytes);
+        (parameters, data)
+    };
+    let response = transport
+        .send(&telegram(&parameters, &data))
+        .map_err(transport_error)?;
+    match acknowledgement(&response, WRITE_VAR)?.first() {
+        Some(0xFF) => Ok(()),
+        Some(code) => Err(format!("{}: {}.", address, return_code(*code))),
+        None => Err("The PLC sent a short response.".to_string()),
+    }
+}
+//####################################################
+
+//#################################################### S7 tag list editor.
+
+/// Shows the S7 tag list. Returns true if it was changed.
+pub fn s7_tags_ui(ui: &mut egui::Ui, tags: &mut Vec<S7Tag>) -> bool {
+    let before = tags.clone();
+    let mut remove = None;
+
+    ScrollArea::vertical().max_height(250.).show(ui, |ui| {
+        Grid::new("s7_tags")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Tag");
+                ui.strong("Address");
+                ui.strong("Type");
+                ui.end_row();
+
+                for (i, tag) in tags.iter_mut().enumerate() {
+                    ui.add(TextEdit::singleline(&mut tag.name).desired_width(80.));
+                    let error = tag.address().err();
+                    ui.add(
+                        TextEdit::singleline(&mut tag.address)
+                            .desired_width(90.)
+                            .text_color_opt(error.is_some().then_some(Color32::RED)),
+                    )
+                    .on_hover_text(error.unwrap_or_default());
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_source(("s7_tag_type", i))
+                            .selected_text(format!("{}", tag.data_type))
+                            .show_ui(ui, |ui| {
+                                for data_type in DataType::all() {
+                                    let selected = std::mem::discriminant(&tag.data_type)
+                                        == std::mem::discriminant(&data_type);
+                                    let text = format!("{}", data_type);
+                                    if ui.selectable_label(selected, text).clicked() && !selected {
+                                        tag.data_type = data_type;
+                                    }
+                                }
+                            });
+                        if let DataType::String(length) = &mut tag.data_type {
+                            ui.add(DragValue::new(length).clamp_range(1..=254).suffix(" chars"));
+                        }
+                    });
+                    if ui
+                        .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                        .on_hover_text("Remove tag")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    if let Some(i) = remove {
+        tags.remove(i);
+    }
+    if ui
+        .button(format!("{} Add tag", egui_phosphor::regular::PLUS))
+        .clicked()
+    {
+        tags.push(S7Tag::new("NEW-TAG", "DB1.DBD0", DataType::Real));
+    }
+
+    *tags != before
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        let cases = [
+            ("DB1.DBX0.3", Area::DataBlock(1), 0, 3, Width::Bit),
+            ("db10.dbb2", Area::DataBlock(10), 2, 0, Width::Byte),
+            ("DB1.DBW4", Area::DataBlock(1), 4, 0, Width::Word),
+            ("DB200.DBD8", Area::DataBlock(200), 8, 0, Width::DoubleWord),
+            ("M20.1", Area::Merker, 20, 1, Width::Bit),
+            ("MW4", Area::Merker, 4, 0, Width::Word),
+            ("I0.7", Area::Input, 0, 7, Width::Bit),
+            ("IB3", Area::Input, 3, 0, Width::Byte),
+            ("QD8", Area::Output, 8, 0, Width::DoubleWord),
+            ("E1.0", Area::Input, 1, 0, Width::Bit),
+            ("A2.1", Area::Output, 2, 1, Width::Bit),
+        ];
+        for (text, area, byte, bit, width) in cases {
+            assert_eq!(
+                Address::parse(text),
+                Ok(Address {
+                    area,
+                    byte,
+                    bit,
+                    width
+                }),
+                "{text}"
+            );
+        }
+        for text in [
+            "DB1.DBX0",
+            "DB1.DBW4.1",
+            "M20.8",
+            "X1",
+            "DB.DBW0",
+            "DB1.DBQ2",
+        ] {
+            assert!(Address::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn checks_the_width_of_the_type() {
+        let tag = S7Tag::new("T", "DB1.DBW4", DataType::Real);
+        assert_eq!(
+            tag.address(),
+            Err("A Real needs an address like DB1.DBD4, not DB1.DBW4.".to_string())
+        );
+        assert!(S7Tag::new("T", "MW4", DataType::Int).address().is_ok());
+    }
+
+    #[test]
+    fn decodes_and_encodes_the_types() {
+        let address = Address::parse("DB1.DBX0.2").unwrap();
+        assert_eq!(
+            DataType::Bool.decode(&[0b100], &address),
+            Some(Value::Number(1.))
+        );
+        let cases = [
+            (DataType::Int, -1234.),
+            (DataType::Word, 48879.),
+            (DataType::DInt, -123_456_789.),
+            (DataType::Real, 12.5),
+        ];
+        for (data_type, value) in cases {
+            let bytes = data_type.encode(&Value::Number(value)).unwrap();
+            assert_eq!(
+                data_type.decode(&bytes, &address),
+                Some(Value::Number(value)),
+                "{data_type}"
+            );
+        }
+        let text = Value::Text("PUMP".to_string());
+        let mut bytes = DataType::String(8).encode(&text).unwrap();
+        assert_eq!(&bytes[..2], &[8, 4]);
+        bytes.resize(10, 0);
+        assert_eq!(DataType::String(8).decode(&bytes, &address), Some(text));
+        assert!(DataType::Int.encode(&Value::Number(40000.)).is_err());
+        assert!(DataType::Bool.encode(&Value::Number(2.)).is_err());
+    }
+
+    #[test]
+    fn merges_close_ranges_into_spans() {
+        let db1 = Area::DataBlock(1);
+        let ranges = [
+            (db1, 8, 4),
+            (db1, 4, 4),
+            (Area::Merker, 0, 2),
+            (db1, 100, 2),
+            (Area::DataBlock(2), 0, 4),
+        ];
+        assert_eq!(
+            spans(&ranges, 240),
+            vec![
+                Span {
+                    area: Area::Merker,
+                    start: 0,
+                    length: 2
+                },
+                Span {
+                    area: db1,
+                    start: 4,
+                    length: 8
+                },
+                Span {
+                    area: db1,
+                    start: 100,
+                    length: 2
+                },
+                Span {
+                    area: Area::DataBlock(2),
+                    start: 0,
+                    length: 4
+                },
+            ]
+        );
+        // A long string is split to fit the PDU.
+        let long = spans(&[(db1, 0, 500)], 240);
+        assert_eq!(long.len(), 3);
+        assert!(long.iter().all(|span| span.length <= 222));
+
+        // And its pieces are joined back before it is decoded.
+        let text = "A".repeat(254);
+        let mut db1 = DataType::String(254)
+            .encode(&Value::Text(text.clone()))
+            .unwrap();
+        db1.resize(256, 0);
+        let mut plc = FakePlc { db1, sent: vec![] };
+        let tags = vec![S7Tag::new("NOTE", "DB1.DBB0", DataType::String(254))];
+        let values = read_s7_tags(&mut plc, &tags).unwrap();
+        assert_eq!(plc.sent.len(), 2);
+        assert_eq!(values["NOTE"], Value::Text(text));
+    }
+
+    #[test]
+    fn splits_requests_by_pdu_and_item_count() {
+        let many: Vec<Span> = (0..25)
+            .map(|i| Span {
+                area: Area::DataBlock(i),
+                start: 0,
+                length: 2,
+            })
+            .collect();
+        let requests = requests(&many, 960);
+        assert_eq!(
+            requests.iter().map(|r| r.len()).collect::<Vec<_>>(),
+            vec![20, 5]
+        );
+        let big: Vec<Span> = (0..3)
+            .map(|i| Span {
+                area: Area::DataBlock(i),
+                start: 0,
+                length: 200,
+            })
+            .collect();
+        assert_eq!(super::requests(&big, 240).len(), 3);
+    }
+
+    /// Answers read requests from a fake data block 1 and records the telegrams.
+    struct FakePlc {
+        db1: Vec<u8>,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Transport for FakePlc {
+        fn send(&mut self, request: &[u8]) -> Result<Vec<u8>, s7::error::Error> {
+            self.sent.push(request.to_vec());
+            let parameters = &request[ISO_HEADER + JOB_HEADER..];
+            let mut data = Vec::new();
+            for item in parameters[2..].chunks(12).take(parameters[1] as usize) {
+                let length = u16::from_be_bytes([item[4], item[5]]) as usize;
+                let start = u32::from_be_bytes([0, item[9], item[10], item[11]]) as usize / 8;
+                data.extend_from_slice(&[0xFF, 0x04]);
+                data.extend_from_slice(&(length as u16 * 8).to_be_bytes());
+                data.extend_from_slice(&self.db1[start..start + length]);
+                if length % 2 == 1 {
+                    data.push(0);
+                }
+            }
+            let mut response = vec![3, 0, 0, 0, 2, 0xF0, 0x80];
+            response.extend_from_slice(&[0x32, 0x03, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+            response.extend_from_slice(&[READ_VAR, parameters[1]]);
+            response.extend_from_slice(&data);
+            Ok(response)
+        }
+
+        fn pdu_length(&self) -> i32 {
+            240
+        }
+
+        fn negotiate(&mut self) -> Result<(), s7::error::Error> {
+            Ok(())
+        }
+
+        fn connection_type(&self) -> s7::transport::Connection {
+            s7::transport::Connection::PG
+        }
+    }
+
+    #[test]
+    fn reads_tags_with_one_request() {
+        let mut db1 = vec![0; 64];
+        db1[0] = 0b10;
+        db1[4..8].copy_from_slice(&12.5_f32.to_be_bytes());
+        db1[8..10].copy_from_slice(&(-7_i16).to_be_bytes());
+        db1[20..24].copy_from_slice(&[4, 2, b'O', b'K']);
+        let mut plc = FakePlc { db1, sent: vec![] };
+        let tags = vec![
+            S7Tag::new("RUN", "DB1.DBX0.1", DataType::Bool),
+            S7Tag::new("PT", "DB1.DBD4", DataType::Real),
+            S7Tag::new("COUNT", "DB1.DBW8", DataType::Int),
+            S7Tag::new("STATE", "DB1.DBB20", DataType::String(4)),
+            S7Tag::new("BAD", "DB1.DBW4", DataType::Real),
+        ];
+        let values = read_s7_tags(&mut plc, &tags).unwrap();
+        assert_eq!(plc.sent.len(), 1);
+        assert_eq!(values.len(), 4);
+        assert_eq!(values["RUN"], Value::Number(1.));
+        assert_eq!(values["PT"], Value::Number(12.5));
+        assert_eq!(values["COUNT"], Value::Number(-7.));
+        assert_eq!(values["STATE"], Value::Text("OK".to_string()));
+    }
+
+    #[test]
+    fn refusals_are_answers_and_garbled_responses_are_not() {
+        let mut response = vec![3, 0, 0, 0, 2, 0xF0, 0x80];
+        response.extend_from_slice(&[0x32, 0x03, 0, 0, 0, 0, 0, 2, 0, 0, 0x85, 0x00]);
+        response.extend_from_slice(&[READ_VAR, 1]);
+        assert_eq!(
+            acknowledgement(&response, READ_VAR).unwrap_err().failure,
+            Failure::Exception
+        );
+        assert_eq!(
+            acknowledgement(&response[..12], READ_VAR)
+                .unwrap_err()
+                .failure,
+            Failure::Crc
+        );
+        assert_eq!(
+            transport_error(s7::error::Error::IOError(std::io::ErrorKind::TimedOut)).failure,
+            Failure::Timeout
+        );
+    }
+}
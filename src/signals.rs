@@ -0,0 +1,472 @@
+use egui::{Button, Color32, ComboBox, DragValue, Grid, ScrollArea, TextEdit};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::blocks::RegisterType;
+use crate::health::COMM_FAIL_ALARM_PREFIX;
+use crate::limits::LIMIT_ALARM_PREFIX;
+use crate::tags::DEFAULT_DEVICE;
+
+//#################################################### Discrete signals.
+
+/// A discrete input or PLC alarm bit shown in the right panel.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct DigitalSignal {
+    /// The text shown in the panel. It also identifies the signal, so it
+    /// should be unique.
+    pub label: String,
+    /// Name of the device the bit is read from.
+    pub device: String,
+    pub source: BitSource,
+    pub normal_state: NormalState,
+    pub severity: Severity,
+    /// Lets the operator switch a coil on and off from the panel.
+    pub command: bool,
+}
+
+impl Default for DigitalSignal {
+    fn default() -> Self {
+        Self {
+            label: "NEW SIGNAL".to_string(),
+            device: DEFAULT_DEVICE.to_string(),
+            source: BitSource::default(),
+            normal_state: NormalState::default(),
+            severity: Severity::default(),
+            command: false,
+        }
+    }
+}
+
+impl DigitalSignal {
+    fn register_bit(label: &str, address: u16, bit: u8) -> Self {
+        // Shutdown signals outrank everything else on the panel.
+        let severity = if label.starts_with("ESD") {
+            Severity::Critical
+        } else {
+            Severity::High
+        };
+        Self {
+            label: label.to_string(),
+            source: BitSource::RegisterBit { address, bit },
+            severity,
+            ..Default::default()
+        }
+    }
+
+    /// The coil the operator can switch, if the signal is a command.
+    pub fn command_coil(&self) -> Option<u16> {
+        match self.source {
+            BitSource::Coil(address) if self.command => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Whether the raw bit state is the abnormal one.
+    pub fn is_alarm(&self, state: bool) -> bool {
+        state != self.normal_state.healthy_state()
+    }
+}
+
+/// Where the bit of a signal comes from.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub enum BitSource {
+    /// A bit (0 = least significant) of a holding register.
+    RegisterBit {
+        address: u16,
+        bit: u8,
+    },
+    Coil(u16),
+    DiscreteInput(u16),
+}
+
+impl Default for BitSource {
+    fn default() -> Self {
+        Self::RegisterBit { address: 0, bit: 0 }
+    }
+}
+
+impl Display for BitSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BitSource::RegisterBit { .. } => write!(f, "Register bit"),
+            BitSource::Coil(_) => write!(f, "Coil"),
+            BitSource::DiscreteInput(_) => write!(f, "Discrete input"),
+        }
+    }
+}
+
+/// The contact state of a signal when the plant is healthy.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum NormalState {
+    /// Normally open: the bit is clear when healthy and set in alarm.
+    Open,
+    /// Normally closed: the bit is set when healthy, so a broken wire also alarms.
+    Closed,
+}
+
+impl NormalState {
+    fn healthy_state(&self) -> bool {
+        match self {
+            NormalState::Open => false,
+            NormalState::Closed => true,
+        }
+    }
+}
+
+impl Display for NormalState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NormalState::Open => write!(f, "NO"),
+            NormalState::Closed => write!(f, "NC"),
+        }
+    }
+}
+
+impl Default for NormalState {
+    fn default() -> Self {
+        Self::Closed
+    }
+}
+
+#[derive(
+    serde::Deserialize, serde::Serialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy,
+)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn all() -> [Severity; 4] {
+        [
+            Severity::Critical,
+            Severity::High,
+            Severity::Medium,
+            Severity::Low,
+        ]
+    }
+
+    /// The background colour of an active alarm.
+    pub fn color(&self) -> Color32 {
+        match self {
+            Severity::Critical => Color32::from_rgb(200, 0, 0),
+            Severity::High => Color32::DARK_RED,
+            Severity::Medium => Color32::from_rgb(200, 110, 0),
+            Severity::Low => Color32::from_rgb(150, 130, 0),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Low => write!(f, "Low"),
+            Severity::Medium => write!(f, "Medium"),
+            Severity::High => write!(f, "High"),
+            Severity::Critical => write!(f, "Critical"),
+        }
+    }
+}
+
+impl Default for Severity {
+    fn default() -> Self {
+        Self::High
+    }
+}
+
+/// Reads the raw bit states of the register bit signals of `device`, keyed by label.
+/// Register bits are in the holding registers, other tables give no state.
+pub fn read_signals(
+    signals: &[DigitalSignal],
+    device: &str,
+    table: RegisterType,
+    registers: &[u16],
+    start_address: u16,
+) -> HashMap<String, bool> {
+    if table != RegisterType::Holding {
+        return HashMap::new();
+    }
+    signals
+        .iter()
+        .filter(|signal| signal.device == device)
+        .filter_map(|signal| match signal.source {
+            BitSource::RegisterBit { address, bit } => {
+                let register = registers.get(address.checked_sub(start_address)? as usize)?;
+                Some((signal.label.clone(), check_bit(*register, bit as usize)))
+            }
+            BitSource::Coil(_) | BitSource::DiscreteInput(_) => None,
+        })
+        .collect()
+}
+
+/// Reads the states of the coil or discrete input signals of `device` out of
+/// a block of bits that starts at `start_address`, keyed by label.
+///
+/// Bits only map to signals: tags are registers, so a coil or discrete input
+/// shows on the panel and raises its alarm, but isn't logged or trended.
+pub fn read_bit_signals(
+    signals: &[DigitalSignal],
+    device: &str,
+    table: RegisterType,
+    bits: &[bool],
+    start_address: u16,
+) -> HashMap<String, bool> {
+    signals
+        .iter()
+        .filter(|signal| signal.device == device)
+        .filter_map(|signal| {
+            let address = match (table, &signal.source) {
+                (RegisterType::Coils, BitSource::Coil(address)) => *address,
+                (RegisterType::DiscreteInputs, BitSource::DiscreteInput(address)) => *address,
+                _ => return None,
+            };
+            let bit = bits.get(address.checked_sub(start_address)? as usize)?;
+            Some((signal.label.clone(), *bit))
+        })
+        .collect()
+}
+
+fn check_bit(value: u16, n: usize) -> bool {
+    if n < 16 {
+        value & (1 << n) != 0
+    } else {
+        false
+    }
+}
+
+/// The alarm panel of the single well head panel Carbon was first built for.
+pub fn default_signals() -> Vec<DigitalSignal> {
+    let first_word = [
+        "ESD PUSH BUTTON",
+        "TANK LVL 10%",
+        "TANK LVL 5%",
+        "PT3-1 LOW",
+        "HP1-1 MTNCE REQ",
+        "REGU FAULT HP",
+        "SCSSV PRES LOW",
+        "MV PRES LOW",
+        "ESDV PRES LOW",
+        "PT1-1 PRES HIGH",
+        "PLC-1 COM FAIL",
+        "PLC-2 COM FAIL",
+    ];
+    let second_word = [
+        (0, "ESD-1 FIRE EMG"),
+        (1, "ESD-3 SHUTDOWN"),
+        (2, "DIESEL LVL"),
+        (3, "WI PUMP OFF"),
+        (4, "WATER PUMP TEMP"),
+        (5, "WATER TNK LVL"),
+        (6, "CHEMICAL TNK LVL1"),
+        (7, "CHEMICAL TNK LVL2"),
+        (8, "CHEMICAL TNK LVL3"),
+        (9, "CHEMICAL TNK LVL4"),
+        (10, "DIFF PRES FILTRATION"),
+        (11, "HIGH PRES FLOWLINE"),
+        (12, "LOW PRES FLOWLINE"),
+        (14, "UNHEALTHY RESET"),
+    ];
+
+    let mut signals = Vec::new();
+    for (bit, label) in first_word.iter().enumerate() {
+        signals.push(DigitalSignal::register_bit(label, 31, bit as u8));
+    }
+    for (bit, label) in second_word.iter() {
+        signals.push(DigitalSignal::register_bit(label, 36, *bit));
+    }
+    signals
+}
+//####################################################
+
+//#################################################### Signal list editor.
+
+/// Shows the discrete signal editor. Returns true if the list was changed.
+pub fn signals_ui(ui: &mut egui::Ui, signals: &mut Vec<DigitalSignal>) -> bool {
+    let before = signals.clone();
+    let mut remove = None;
+
+    ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+        Grid::new("digital_signals")
+            .num_columns(9)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Label");
+                ui.strong("Device");
+                ui.strong("Source");
+                ui.strong("Address");
+                ui.strong("Bit");
+                ui.strong("Normal");
+                ui.strong("Severity");
+                ui.strong("Command");
+                ui.end_row();
+
+                for (i, signal) in signals.iter_mut().enumerate() {
+                    ui.add(TextEdit::singleline(&mut signal.label).desired_width(140.));
+                    ui.add(TextEdit::singleline(&mut signal.device).desired_width(60.));
+
+                    let address = match signal.source {
+                        BitSource::RegisterBit { address, .. }
+                        | BitSource::Coil(address)
+                        | BitSource::DiscreteInput(address) => address,
+                    };
+                    ComboBox::from_id_source(("signal_source", i))
+                        .selected_text(format!("{}", signal.source))
+                        .show_ui(ui, |ui| {
+                            let sources = [
+                                BitSource::RegisterBit { address, bit: 0 },
+                                BitSource::Coil(address),
+                                BitSource::DiscreteInput(address),
+                            ];
+                            for source in sources {
+                                let selected = std::mem::discriminant(&signal.source)
+                                    == std::mem::discriminant(&source);
+                                let text = format!("{}", source);
+                                if ui.selectable_label(selected, text).clicked() && !selected {
+                                    signal.source = source;
+                                }
+                            }
+                        });
+                    match &mut signal.source {
+                        BitSource::RegisterBit { address, bit } => {
+                            ui.add(DragValue::new(address).clamp_range(0..=65535));
+                            ui.add(DragValue::new(bit).clamp_range(0..=15));
+                        }
+                        BitSource::Coil(address) | BitSource::DiscreteInput(address) => {
+                            ui.add(DragValue::new(address).clamp_range(0..=65535));
+                            ui.label("");
+                        }
+                    }
+
+                    ComboBox::from_id_source(("signal_normal_state", i))
+                        .selected_text(format!("{}", signal.normal_state))
+                        .width(50.)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut signal.normal_state, NormalState::Open, "NO");
+                            ui.selectable_value(
+                                &mut signal.normal_state,
+                                NormalState::Closed,
+                                "NC",
+                            );
+                        });
+                    ComboBox::from_id_source(("signal_severity", i))
+                        .selected_text(format!("{}", signal.severity))
+                        .width(70.)
+                        .show_ui(ui, |ui| {
+                            for severity in Severity::all() {
+                                let text = format!("{}", severity);
+                                ui.selectable_value(&mut signal.severity, severity, text);
+                            }
+                        });
+                    let is_coil = matches!(signal.source, BitSource::Coil(_));
+                    ui.add_enabled(is_coil, egui::Checkbox::new(&mut signal.command, ""))
+                        .on_hover_text("Operators can switch the coil on and off");
+                    if ui
+                        .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                        .on_hover_text("Remove signal")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+
+    if let Some(i) = remove {
+        signals.remove(i);
+    }
+
+    let mut labels: Vec<&String> = signals.iter().map(|signal| &signal.label).collect();
+    labels.sort();
+    if labels.windows(2).any(|pair| pair[0] == pair[1]) {
+        ui.colored_label(Color32::DARK_RED, "Signal labels must be unique.");
+    }
+    let reserved = [
+        (LIMIT_ALARM_PREFIX, "the tag limit alarms"),
+        (COMM_FAIL_ALARM_PREFIX, "the comm fail alarms"),
+    ];
+    for (prefix, alarms) in reserved {
+        if labels.iter().any(|label| label.starts_with(prefix)) {
+            ui.colored_label(
+                Color32::DARK_RED,
+                format!(
+                    "Signal labels can't start with \"{}\", which names {}.",
+                    prefix.trim_end(),
+                    alarms
+                ),
+            );
+        }
+    }
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        if ui
+            .button(format!("{} Add signal", egui_phosphor::regular::PLUS))
+            .clicked()
+        {
+            signals.push(DigitalSignal::default());
+        }
+        if ui
+            .button(format!(
+                "{} Restore defaults",
+                egui_phosphor::regular::ARROW_COUNTER_CLOCKWISE
+            ))
+            .clicked()
+        {
+            *signals = default_signals();
+        }
+    });
+
+    *signals != before
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(label: &str, source: BitSource) -> DigitalSignal {
+        DigitalSignal {
+            label: label.to_string(),
+            source,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bits_are_read_at_their_offset_in_their_own_table() {
+        let signals = vec![
+            signal("PUMP RUN", BitSource::Coil(101)),
+            signal("DOOR OPEN", BitSource::DiscreteInput(100)),
+            signal("VALVE OPEN", BitSource::DiscreteInput(103)),
+            signal("BEFORE", BitSource::DiscreteInput(99)),
+            signal("AFTER", BitSource::DiscreteInput(104)),
+            DigitalSignal {
+                device: "OTHER".to_string(),
+                ..signal("ELSEWHERE", BitSource::DiscreteInput(101))
+            },
+        ];
+        let bits = [true, false, false, true];
+
+        let states = read_bit_signals(
+            &signals,
+            DEFAULT_DEVICE,
+            RegisterType::DiscreteInputs,
+            &bits,
+            100,
+        );
+        assert_eq!(
+            states,
+            HashMap::from([
+                ("DOOR OPEN".to_string(), true),
+                ("VALVE OPEN".to_string(), true),
+            ])
+        );
+
+        let states = read_bit_signals(&signals, DEFAULT_DEVICE, RegisterType::Coils, &bits, 100);
+        assert_eq!(states, HashMap::from([("PUMP RUN".to_string(), false)]));
+    }
+}
@@ -0,0 +1,655 @@
+use chrono::{DateTime, Local};
+use egui::{Button, Color32, ComboBox, DragValue, Grid, ScrollArea, SelectableLabel, TextEdit};
+use epaint::Pos2;
+use std::collections::HashMap;
+
+use crate::app::deserialize_some;
+use crate::blocks::RegisterType;
+use crate::bus::{Quality, Sample};
+use crate::codec::{decode, encode, ByteOrder, DataType, Value};
+use crate::limits::{limits_ui, Limits};
+use crate::scaling::{scaling_ui, Scaling};
+use crate::scan::{scan_class_ui, ScanClass};
+
+//#################################################### The tag database.
+
+/// A single analog point of the tag database.
+///
+/// Everything needed to locate the value in the polled data and to draw it on
+/// the mimic lives here, so a new site only needs a new tag list.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Tag {
+    pub name: String,
+    pub description: String,
+    pub unit: String,
+    /// Name of the device the value is read from.
+    pub device: String,
+    /// The table the value is read from, holding or input registers.
+    pub register_type: RegisterType,
+    /// Absolute register address of the first word of the value.
+    pub address: u16,
+    pub data_type: DataType,
+    pub byte_order: ByteOrder,
+    /// How often the value is read, when the blocks are generated from the
+    /// tag database.
+    pub scan_class: ScanClass,
+    pub scaling: Scaling,
+    pub limits: Limits,
+    pub write: WriteAccess,
+    pub pos: Pos2,
+    #[serde(skip)]
+    pub value: f32,
+    /// The last value of a string tag.
+    #[serde(skip)]
+    pub text: String,
+    #[serde(skip)]
+    pub quality: Quality,
+    /// When the device returned the value shown.
+    #[serde(skip)]
+    pub timestamp: Option<DateTime<Local>>,
+}
+
+impl Default for Tag {
+    fn default() -> Self {
+        Self {
+            name: "NEW-TAG".to_string(),
+            description: "".to_string(),
+            unit: "".to_string(),
+            device: DEFAULT_DEVICE.to_string(),
+            register_type: RegisterType::default(),
+            address: 0,
+            data_type: DataType::default(),
+            byte_order: ByteOrder::default(),
+            scan_class: ScanClass::default(),
+            scaling: Scaling::default(),
+            limits: Limits::default(),
+            write: WriteAccess::default(),
+            pos: Pos2::new(350., 350.),
+            value: 0.0,
+            text: "".to_string(),
+            quality: Quality::default(),
+            timestamp: None,
+        }
+    }
+}
+
+impl Tag {
+    fn new(name: &str, description: &str, unit: &str, address: u16, pos: Pos2) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            unit: unit.to_string(),
+            address,
+            pos,
+            ..Default::default()
+        }
+    }
+
+    /// Decodes the tag value out of a block of registers that starts at `start_address`
+    /// and scales it into engineering units.
+    /// Returns `None` if the tag is not covered by the block.
+    pub fn decode(&self, registers: &[u16], start_address: u16) -> Option<Value> {
+        let index = self.address.checked_sub(start_address)? as usize;
+        let value = decode(registers.get(index..)?, &self.data_type, self.byte_order)?;
+        match value {
+            Value::Number(raw) => Some(Value::Number(self.scaling.apply(raw))),
+            text => Some(text),
+        }
+    }
+
+    /// Stores a freshly decoded value.
+    pub fn set_value(&mut self, value: Value) {
+        match value {
+            Value::Number(value) => self.value = value as f32,
+            Value::Text(text) => self.text = text,
+        }
+    }
+
+    /// Stores the latest sample of the tag. A sample without a value keeps the
+    /// last one on display.
+    pub fn set_sample(&mut self, sample: &Sample) {
+        if let Some(value) = &sample.value {
+            self.set_value(value.clone());
+            self.timestamp = Some(sample.timestamp);
+        }
+        self.quality = sample.quality;
+    }
+
+    /// Validates an operator entry against the setpoint range and undoes the
+    /// scaling. The driver of the device encodes the raw value it returns.
+    pub fn setpoint(&self, entry: &str) -> Result<Value, String> {
+        if !self.write.enabled || self.register_type == RegisterType::Inputs {
+            return Err(format!("{} is read only.", self.name));
+        }
+        if self.data_type.is_text() {
+            return Ok(Value::Text(entry.to_string()));
+        }
+        let value: f64 = entry
+            .trim()
+            .parse()
+            .map_err(|_| format!("\"{}\" is not a number.", entry.trim()))?;
+        if value < self.write.min || value > self.write.max {
+            return Err(format!(
+                "The setpoint must be between {} and {} {}.",
+                self.write.min, self.write.max, self.unit
+            ));
+        }
+        let raw = self
+            .scaling
+            .raw(value)
+            .ok_or("The scaling of the tag can't be inverted.")?;
+        Ok(Value::Number(raw))
+    }
+
+    /// Encodes a raw value into the Modbus registers of the tag.
+    pub fn encode(&self, value: &Value) -> Result<Vec<u16>, String> {
+        encode(value, &self.data_type, self.byte_order)
+    }
+
+    /// The value as shown on the mimic, dashes until one was read.
+    pub fn display_value(&self) -> String {
+        if self.timestamp.is_none() {
+            "----".to_string()
+        } else if self.data_type.is_text() {
+            self.text.clone()
+        } else {
+            format!("{:.02}", self.value)
+        }
+    }
+}
+
+/// Whether the operator may write a tag, and the setpoint range in engineering units.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct WriteAccess {
+    pub enabled: bool,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for WriteAccess {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min: 0.0,
+            max: 100.0,
+        }
+    }
+}
+
+/// Decodes the engineering values of all the tags of `device`, keyed by tag name.
+pub fn read_tags(
+    tags: &[Tag],
+    device: &str,
+    register_type: RegisterType,
+    registers: &[u16],
+    start_address: u16,
+) -> HashMap<String, Value> {
+    tags.iter()
+        .filter(|tag| tag.device == device && tag.register_type == register_type)
+        .filter_map(|tag| Some((tag.name.clone(), tag.decode(registers, start_address)?)))
+        .collect()
+}
+
+/// Scales the values a device read by tag name, like S7 and Logix tags,
+/// with the tags of the same name in the database.
+pub fn scale_tags(tags: &[Tag], device: &str, values: &mut HashMap<String, Value>) {
+    for tag in tags.iter().filter(|tag| tag.device == device) {
+        if let Some(Value::Number(value)) = values.get_mut(&tag.name) {
+            *value = tag.scaling.apply(*value);
+        }
+    }
+}
+
+/// A tag as saved in the app state, the saved fields of `Tag`. The address is
+/// optional to tell the tags saved before the database, which don't have one.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct StoredTag {
+    name: String,
+    description: String,
+    unit: String,
+    device: String,
+    register_type: RegisterType,
+    #[serde(deserialize_with = "deserialize_some")]
+    address: Option<u16>,
+    data_type: DataType,
+    byte_order: ByteOrder,
+    scan_class: ScanClass,
+    scaling: Scaling,
+    limits: Limits,
+    write: WriteAccess,
+    pos: Pos2,
+}
+
+impl Default for StoredTag {
+    fn default() -> Self {
+        let tag = Tag::default();
+        Self {
+            name: tag.name,
+            description: tag.description,
+            unit: tag.unit,
+            device: tag.device,
+            register_type: tag.register_type,
+            address: None,
+            data_type: tag.data_type,
+            byte_order: tag.byte_order,
+            scan_class: tag.scan_class,
+            scaling: tag.scaling,
+            limits: tag.limits,
+            write: tag.write,
+            pos: tag.pos,
+        }
+    }
+}
+
+/// Loads the saved tag database. The tags saved before there was one only
+/// have a name and a position: tag `i` was the Float32 at register `2 * i`,
+/// labelled by the panel it was drawn on.
+pub fn deserialize_tags<'de, D>(deserializer: D) -> Result<Vec<Tag>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let stored: Vec<StoredTag> = serde::Deserialize::deserialize(deserializer)?;
+    let defaults = default_tags();
+    let tags = stored
+        .into_iter()
+        .enumerate()
+        .map(|(i, stored)| {
+            let mut tag = Tag {
+                name: stored.name,
+                description: stored.description,
+                unit: stored.unit,
+                device: stored.device,
+                register_type: stored.register_type,
+                address: stored.address.unwrap_or_default(),
+                data_type: stored.data_type,
+                byte_order: stored.byte_order,
+                scan_class: stored.scan_class,
+                scaling: stored.scaling,
+                limits: stored.limits,
+                write: stored.write,
+                pos: stored.pos,
+                ..Default::default()
+            };
+            if stored.address.is_none() {
+                tag.address = (2 * i).min(u16::MAX as usize) as u16;
+                tag.data_type = DataType::Float32;
+                if let Some(default) = defaults.get(i) {
+                    tag.description = default.description.clone();
+                    tag.unit = default.unit.clone();
+                }
+            }
+            tag
+        })
+        .collect();
+    Ok(tags)
+}
+
+/// The device name given to tags and to the connection by default.
+pub const DEFAULT_DEVICE: &str = "PLC-1";
+
+/// The tag list of the single well head panel Carbon was first built for.
+pub fn default_tags() -> Vec<Tag> {
+    vec![
+        Tag::new("LT1-1", "Hydr Oil Lvl", "%", 0, Pos2::new(350., 350.)),
+        Tag::new(
+            "PT1-1",
+            "WHCP Oil Pressure",
+            "Barg",
+            2,
+            Pos2::new(350., 400.),
+        ),
+        Tag::new("PT2-1", "MP Pressure", "Barg", 4, Pos2::new(450., 350.)),
+        Tag::new("PT1-2", "SCSSV Pressure", "Barg", 6, Pos2::new(450., 400.)),
+        Tag::new(
+            "PT2-2",
+            "MV Hydr Oil Pressure",
+            "Barg",
+            8,
+            Pos2::new(550., 350.),
+        ),
+        Tag::new(
+            "PT2-3",
+            "ESDV Hydr Oil Pressure",
+            "Barg",
+            10,
+            Pos2::new(550., 450.),
+        ),
+        Tag::new(
+            "PT3-1",
+            "Fusible Plug Hydr Oil",
+            "Barg",
+            12,
+            Pos2::new(550., 500.),
+        ),
+        Tag::new(
+            "TBA",
+            "ESDV Status Wtr Injection",
+            "Barg",
+            14,
+            Pos2::new(550., 550.),
+        ),
+    ]
+}
+
+//####################################################
+
+//#################################################### Tag database editor.
+
+/// Shows the tag database editor, `selected` being the tag whose scaling and
+/// alarm limits are edited.
+/// Returns true if the tag list was changed.
+pub fn tag_database_ui(
+    ui: &mut egui::Ui,
+    tags: &mut Vec<Tag>,
+    selected: &mut Option<usize>,
+) -> bool {
+    let mut changed = false;
+    let mut remove = None;
+
+    ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+        Grid::new("tag_database")
+            .num_columns(14)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Name");
+                ui.strong("Description");
+                ui.strong("Unit");
+                ui.strong("Device");
+                ui.strong("Address");
+                ui.strong("Data Type");
+                ui.strong("Byte Order");
+                ui.strong("Scan Class")
+                    .on_hover_text("Used when the read blocks are generated from the tags.");
+                ui.strong("Scaling");
+                ui.strong("Limits");
+                ui.strong("Write");
+                ui.strong("X");
+                ui.strong("Y");
+                ui.end_row();
+
+                for (i, tag) in tags.iter_mut().enumerate() {
+                    changed |= ui
+                        .add(TextEdit::singleline(&mut tag.name).desired_width(70.))
+                        .changed();
+                    changed |= ui
+                        .add(TextEdit::singleline(&mut tag.description).desired_width(160.))
+                        .changed();
+                    changed |= ui
+                        .add(TextEdit::singleline(&mut tag.unit).desired_width(40.))
+                        .changed();
+                    changed |= ui
+                        .add(TextEdit::singleline(&mut tag.device).desired_width(60.))
+                        .changed();
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_source(("tag_register_type", i))
+                            .selected_text(tag.register_type.short())
+                            .width(40.)
+                            .show_ui(ui, |ui| {
+                                for register_type in [RegisterType::Holding, RegisterType::Inputs] {
+                                    let text = format!("{}", register_type);
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut tag.register_type,
+                                            register_type,
+                                            text,
+                                        )
+                                        .changed();
+                                }
+                            })
+                            .response
+                            .on_hover_text(
+                                "Coils and discrete inputs are read as digital signals.",
+                            );
+                        changed |= ui
+                            .add(DragValue::new(&mut tag.address).clamp_range(0..=65535))
+                            .changed();
+                    });
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_source(("tag_data_type", i))
+                            .selected_text(format!("{}", tag.data_type))
+                            .show_ui(ui, |ui| {
+                                for data_type in DataType::all() {
+                                    let selected = tag.data_type.is_text() && data_type.is_text()
+                                        || tag.data_type == data_type;
+                                    let text = format!("{}", data_type);
+                                    if ui.selectable_label(selected, text).clicked() && !selected {
+                                        tag.data_type = data_type;
+                                        changed = true;
+                                    }
+                                }
+                            });
+                        if let DataType::Text(length) = &mut tag.data_type {
+                            changed |= ui
+                                .add(DragValue::new(length).clamp_range(1..=250).suffix(" chars"))
+                                .changed();
+                        }
+                    });
+                    ComboBox::from_id_source(("tag_byte_order", i))
+                        .selected_text(format!("{}", tag.byte_order))
+                        .show_ui(ui, |ui| {
+                            for byte_order in ByteOrder::all() {
+                                let text = format!("{}", byte_order);
+                                changed |= ui
+                                    .selectable_value(&mut tag.byte_order, byte_order, text)
+                                    .changed();
+                            }
+                        });
+                    changed |= scan_class_ui(ui, ("tag_scan_class", i), &mut tag.scan_class);
+                    let is_selected = *selected == Some(i);
+                    if ui
+                        .add(SelectableLabel::new(
+                            is_selected,
+                            format!("{}", tag.scaling.curve),
+                        ))
+                        .on_hover_text("Edit scaling")
+                        .clicked()
+                    {
+                        *selected = if is_selected { None } else { Some(i) };
+                    }
+                    let enabled_limits = tag
+                        .limits
+                        .enabled()
+                        .map(|(kind, _)| kind.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let enabled_limits = if enabled_limits.is_empty() {
+                        "None".to_string()
+                    } else {
+                        enabled_limits
+                    };
+                    if ui
+                        .add(SelectableLabel::new(is_selected, enabled_limits))
+                        .on_hover_text("Edit alarm limits")
+                        .clicked()
+                    {
+                        *selected = if is_selected { None } else { Some(i) };
+                    }
+                    changed |= ui
+                        .checkbox(&mut tag.write.enabled, "")
+                        .on_hover_text("Operators can write setpoints")
+                        .changed();
+                    changed |= ui.add(DragValue::new(&mut tag.pos.x).speed(1.)).changed();
+                    changed |= ui.add(DragValue::new(&mut tag.pos.y).speed(1.)).changed();
+                    if ui
+                        .add(Button::new(egui_phosphor::regular::TRASH.to_string()))
+                        .on_hover_text("Remove tag")
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+
+    if let Some(i) = remove {
+        tags.remove(i);
+        *selected = None;
+        changed = true;
+    }
+
+    if let Some(tag) = selected.and_then(|i| tags.get_mut(i)) {
+        ui.separator();
+        ui.horizontal_top(|ui| {
+            ui.vertical(|ui| {
+                ui.label(format!(
+                    "{} Scaling of {}",
+                    egui_phosphor::regular::WRENCH,
+                    tag.name
+                ));
+                changed |= scaling_ui(ui, &mut tag.scaling);
+            });
+            ui.separator();
+            ui.vertical(|ui| {
+                ui.label(format!(
+                    "{} Alarm limits of {}",
+                    egui_phosphor::regular::BELL,
+                    tag.name
+                ));
+                changed |= limits_ui(ui, &mut tag.limits);
+            });
+            ui.separator();
+            ui.vertical(|ui| {
+                ui.label(format!(
+                    "{} Setpoint of {}",
+                    egui_phosphor::regular::PENCIL_SIMPLE,
+                    tag.name
+                ));
+                changed |= write_access_ui(ui, &mut tag.write, &tag.scaling);
+            });
+        });
+    }
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        if ui
+            .button(format!("{} Add tag", egui_phosphor::regular::PLUS))
+            .clicked()
+        {
+            tags.push(Tag::default());
+            changed = true;
+        }
+        if ui
+            .button(format!(
+                "{} Restore defaults",
+                egui_phosphor::regular::ARROW_COUNTER_CLOCKWISE
+            ))
+            .clicked()
+        {
+            *tags = default_tags();
+            *selected = None;
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+/// Returns true if the write access was changed.
+fn write_access_ui(ui: &mut egui::Ui, write: &mut WriteAccess, scaling: &Scaling) -> bool {
+    let mut changed = false;
+    Grid::new("tag_write_access").num_columns(2).show(ui, |ui| {
+        ui.label("Writable");
+        changed |= ui.checkbox(&mut write.enabled, "").changed();
+        ui.end_row();
+        if write.enabled && !scaling.is_invertible() {
+            ui.label("");
+            ui.colored_label(
+                Color32::DARK_RED,
+                "The scaling can't be inverted,\nso setpoints are refused.",
+            );
+            ui.end_row();
+        }
+        ui.label("Minimum");
+        changed |= ui
+            .add_enabled(write.enabled, DragValue::new(&mut write.min).speed(0.1))
+            .changed();
+        ui.end_row();
+        ui.label("Maximum");
+        changed |= ui
+            .add_enabled(write.enabled, DragValue::new(&mut write.max).speed(0.1))
+            .changed();
+        ui.end_row();
+    });
+    changed
+}
+//####################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaling::{Curve, Range};
+
+    #[derive(serde::Deserialize)]
+    struct State {
+        #[serde(deserialize_with = "deserialize_tags")]
+        tags: Vec<Tag>,
+    }
+
+    #[test]
+    fn tags_saved_before_the_database_keep_their_registers() {
+        let state = "(tags: [(name: \"LT1-1\", pos: (x: 350.0, y: 350.0)), \
+                     (name: \"PT1-1\", pos: (x: 350.0, y: 400.0)), \
+                     (name: \"PT2-1\", pos: (x: 450.0, y: 350.0))])";
+        let state: State = ron::from_str(state).unwrap();
+
+        let addresses: Vec<u16> = state.tags.iter().map(|tag| tag.address).collect();
+        assert_eq!(addresses, [0, 2, 4]);
+        assert!(state
+            .tags
+            .iter()
+            .all(|tag| tag.data_type == DataType::Float32));
+        assert_eq!(state.tags[1].description, "WHCP Oil Pressure");
+        assert_eq!(state.tags[1].unit, "Barg");
+        assert_eq!(state.tags[2].pos, Pos2::new(450., 350.));
+    }
+
+    #[test]
+    fn saved_tags_round_trip() {
+        let mut tags = default_tags();
+        tags[0].address = 100;
+        tags[0].data_type = DataType::Int16;
+        tags[0].register_type = RegisterType::Inputs;
+        let saved = ron::to_string(&tags).unwrap();
+        let state: State = ron::from_str(&format!("(tags: {})", saved)).unwrap();
+        assert_eq!(state.tags, tags);
+    }
+
+    #[test]
+    fn setpoints_are_checked_and_unscaled() {
+        let mut tag = Tag {
+            scaling: Scaling {
+                curve: Curve::Linear(Range::default()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            tag.setpoint("50"),
+            Err(format!("{} is read only.", tag.name))
+        );
+
+        tag.write = WriteAccess {
+            enabled: true,
+            min: 10.0,
+            max: 90.0,
+        };
+        assert_eq!(tag.setpoint(" 50 "), Ok(Value::Number(13824.0)));
+        assert!(tag.setpoint("95").is_err());
+        assert!(tag.setpoint("5").is_err());
+        assert!(tag.setpoint("fifty").is_err());
+
+        tag.register_type = RegisterType::Inputs;
+        assert!(tag.setpoint("50").is_err());
+
+        tag.register_type = RegisterType::Holding;
+        tag.scaling.curve = Curve::Table(vec![(0.0, 0.0), (100.0, 50.0), (200.0, 20.0)]);
+        assert_eq!(
+            tag.setpoint("30"),
+            Err("The scaling of the tag can't be inverted.".to_string())
+        );
+    }
+}
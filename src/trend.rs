@@ -0,0 +1,621 @@
+use chrono::{DateTime, Local, TimeZone};
+use egui::{
+    Align2, Color32, ComboBox, DragValue, FontId, Grid, Pos2, Rect, Sense, Shape, Stroke, Vec2,
+};
+use std::collections::{HashMap, VecDeque};
+
+use crate::bus::Quality;
+use crate::historian::{Historian, HistoryCache};
+use crate::tags::Tag;
+use std::sync::Arc;
+
+//#################################################### Trend buffer.
+
+/// How long samples are kept in memory, older ones come from the historian.
+const RETENTION: f64 = 24. * 3600.;
+/// The minimum time between two samples of a tag. It bounds the buffer to
+/// 345 600 samples per tag whatever the scan rate.
+const RESOLUTION: f64 = 0.25;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Sample {
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+    pub value: f64,
+}
+
+/// A period without a good value, shaded on the trend.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Outage {
+    pub start: f64,
+    /// `None` while it lasts.
+    pub end: Option<f64>,
+    pub quality: Quality,
+}
+
+impl Outage {
+    fn contains(&self, time: f64) -> bool {
+        self.start <= time && self.end.map_or(true, |end| time < end)
+    }
+}
+
+/// An in-memory ring buffer of the recent values of every tag.
+#[derive(Default)]
+pub struct TrendBuffer {
+    series: HashMap<String, VecDeque<Sample>>,
+    outages: HashMap<String, VecDeque<Outage>>,
+}
+
+impl TrendBuffer {
+    /// Appends a sample, unless it comes less than [`RESOLUTION`] after the last one.
+    pub fn push(&mut self, tag: &str, time: DateTime<Local>, value: f64) {
+        let time = timestamp(time);
+        let series = self.series.entry(tag.to_string()).or_default();
+        if series
+            .back()
+            .is_some_and(|last| time - last.time < RESOLUTION)
+        {
+            return;
+        }
+        series.push_back(Sample { time, value });
+        while series
+            .front()
+            .is_some_and(|first| time - first.time > RETENTION)
+        {
+            series.pop_front();
+        }
+    }
+
+    pub fn series(&self, tag: &str) -> Option<&VecDeque<Sample>> {
+        self.series.get(tag)
+    }
+
+    /// Records the quality of a tag at `time`, opening or closing an outage.
+    pub fn set_quality(&mut self, tag: &str, time: DateTime<Local>, quality: Quality) {
+        let time = timestamp(time);
+        let outages = self.outages.entry(tag.to_string()).or_default();
+        if let Some(last) = outages.back_mut().filter(|last| last.end.is_none()) {
+            if last.quality == quality {
+                return;
+            }
+            last.end = Some(time);
+        }
+        if quality != Quality::Good {
+            outages.push_back(Outage {
+                start: time,
+                end: None,
+                quality,
+            });
+        }
+        while outages
+            .front()
+            .is_some_and(|first| first.end.is_some_and(|end| time - end > RETENTION))
+        {
+            outages.pop_front();
+        }
+    }
+
+    pub fn outages(&self, tag: &str) -> Option<&VecDeque<Outage>> {
+        self.outages.get(tag)
+    }
+}
+
+pub fn timestamp(time: DateTime<Local>) -> f64 {
+    time.timestamp_millis() as f64 / 1000.
+}
+
+pub fn local_time(timestamp: f64) -> Option<DateTime<Local>> {
+    Local
+        .timestamp_millis_opt((timestamp * 1000.) as i64)
+        .single()
+}
+
+/// The samples of `series` between `start` and `end`, plus the one on each
+/// side so lines run to the edges of the chart.
+pub fn visible(series: &VecDeque<Sample>, start: f64, end: f64) -> impl Iterator<Item = &Sample> {
+    let first = series
+        .partition_point(|sample| sample.time < start)
+        .saturating_sub(1);
+    let last = (series.partition_point(|sample| sample.time <= end) + 1).min(series.len());
+    series.range(first..last.max(first))
+}
+
+/// The value of `series` at `time`, being the last sample before it.
+pub fn value_at(series: &VecDeque<Sample>, time: f64) -> Option<f64> {
+    let index = series.partition_point(|sample| sample.time <= time);
+    series.get(index.checked_sub(1)?).map(|sample| sample.value)
+}
+//####################################################
+
+//#################################################### Trend settings.
+
+/// A tag plotted on the trend, with its own colour and vertical scale.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Pen {
+    pub tag: String,
+    pub color: Color32,
+    /// Fits the scale to the visible samples instead of `min`..`max`.
+    pub auto_scale: bool,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for Pen {
+    fn default() -> Self {
+        Self {
+            tag: "".to_string(),
+            color: Color32::LIGHT_BLUE,
+            auto_scale: true,
+            min: 0.0,
+            max: 100.0,
+        }
+    }
+}
+
+const PEN_COLORS: [Color32; 6] = [
+    Color32::LIGHT_BLUE,
+    Color32::from_rgb(255, 140, 0),
+    Color32::LIGHT_GREEN,
+    Color32::from_rgb(255, 80, 80),
+    Color32::from_rgb(200, 120, 255),
+    Color32::YELLOW,
+];
+
+const SPANS: [(&str, f64); 9] = [
+    ("1 min", 60.),
+    ("5 min", 300.),
+    ("15 min", 900.),
+    ("1 h", 3600.),
+    ("4 h", 4. * 3600.),
+    ("8 h", 8. * 3600.),
+    ("24 h", 24. * 3600.),
+    ("7 d", 7. * 86400.),
+    ("30 d", 30. * 86400.),
+];
+/// The widest zoom. Beyond the buffer the trend reads from the historian.
+const MAX_SPAN: f64 = 30. * 86400.;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default)]
+pub struct Trend {
+    pub pens: Vec<Pen>,
+    /// The width of the chart in seconds.
+    pub span: f64,
+    /// The time at the right edge of the chart, `None` to follow the live values.
+    #[serde(skip)]
+    pub end: Option<f64>,
+    /// The historian queries of the pens, keyed by tag.
+    #[serde(skip)]
+    history: HashMap<String, HistoryCache>,
+}
+
+impl Default for Trend {
+    fn default() -> Self {
+        Self {
+            pens: Vec::new(),
+            span: 600.,
+            end: None,
+            history: HashMap::new(),
+        }
+    }
+}
+//####################################################
+
+//#################################################### Trend window.
+
+/// Shows the trend. Returns the error of a historian query, if any failed.
+pub fn trend_ui(
+    ui: &mut egui::Ui,
+    trend: &mut Trend,
+    buffer: &TrendBuffer,
+    historian: Option<&Arc<Historian>>,
+    tags: &[Tag],
+) -> Option<String> {
+    ui.horizontal(|ui| {
+        ui.label("Span");
+        ComboBox::from_id_source("trend_span")
+            .selected_text(span_text(trend.span))
+            .show_ui(ui, |ui| {
+                for (text, span) in SPANS {
+                    if ui.selectable_label(trend.span == span, text).clicked() {
+                        trend.span = span;
+                    }
+                }
+            });
+        let live = trend.end.is_none();
+        if ui
+            .add_enabled(
+                !live,
+                egui::Button::new(format!("{} Live", egui_phosphor::regular::PLAY)),
+            )
+            .clicked()
+        {
+            trend.end = None;
+        }
+        ui.colored_label(
+            Color32::GRAY,
+            "Drag to pan, scroll to zoom, double click to go live.",
+        );
+    });
+
+    pens_ui(ui, trend, tags);
+    ui.separator();
+    chart_ui(ui, trend, buffer, historian, tags);
+
+    // The queries answer on their own thread.
+    if trend.history.values().any(HistoryCache::is_pending) {
+        ui.ctx().request_repaint();
+    }
+    trend
+        .history
+        .values()
+        .find_map(|cache| cache.error().map(|error| error.to_string()))
+}
+
+fn span_text(span: f64) -> String {
+    match SPANS.iter().find(|(_, s)| *s == span) {
+        Some((text, _)) => text.to_string(),
+        None if span >= 3600. => format!("{:.1} h", span / 3600.),
+        None if span >= 60. => format!("{:.1} min", span / 60.),
+        None => format!("{:.0} s", span),
+    }
+}
+
+fn pens_ui(ui: &mut egui::Ui, trend: &mut Trend, tags: &[Tag]) {
+    let mut remove = None;
+    Grid::new("trend_pens")
+        .num_columns(6)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Pen");
+            ui.strong("Colour");
+            ui.strong("Auto");
+            ui.strong("Min");
+            ui.strong("Max");
+            ui.end_row();
+            for (i, pen) in trend.pens.iter_mut().enumerate() {
+                ComboBox::from_id_source(("trend_pen", i))
+                    .selected_text(&pen.tag)
+                    .show_ui(ui, |ui| {
+                        for tag in tags.iter().filter(|tag| !tag.data_type.is_text()) {
+                            ui.selectable_value(&mut pen.tag, tag.name.clone(), &tag.name);
+                        }
+                    });
+                ui.color_edit_button_srgba(&mut pen.color);
+                ui.checkbox(&mut pen.auto_scale, "");
+                ui.add_enabled(!pen.auto_scale, DragValue::new(&mut pen.min).speed(0.1));
+                ui.add_enabled(!pen.auto_scale, DragValue::new(&mut pen.max).speed(0.1));
+                if ui
+                    .button(egui_phosphor::regular::TRASH)
+                    .on_hover_text("Remove pen")
+                    .clicked()
+                {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(i) = remove {
+        trend.pens.remove(i);
+    }
+
+    if ui
+        .button(format!("{} Add pen", egui_phosphor::regular::PLUS))
+        .clicked()
+    {
+        let tag = tags
+            .iter()
+            .filter(|tag| !tag.data_type.is_text())
+            .find(|tag| trend.pens.iter().all(|pen| pen.tag != tag.name))
+            .map(|tag| tag.name.clone())
+            .unwrap_or_default();
+        let color = PEN_COLORS[trend.pens.len() % PEN_COLORS.len()];
+        trend.pens.push(Pen {
+            tag,
+            color,
+            ..Default::default()
+        });
+    }
+}
+
+fn chart_ui(
+    ui: &mut egui::Ui,
+    trend: &mut Trend,
+    buffer: &TrendBuffer,
+    historian: Option<&Arc<Historian>>,
+    tags: &[Tag],
+) {
+    let now = timestamp(Local::now());
+    let mut end = trend.end.unwrap_or(now);
+    let start = end - trend.span;
+
+    // Pens whose buffer starts after the left edge get the older values
+    // from the historian.
+    let empty = VecDeque::new();
+    let history: Vec<Option<VecDeque<Sample>>> = trend
+        .pens
+        .iter()
+        .map(|pen| {
+            let historian = historian?;
+            let samples = buffer.series(&pen.tag).unwrap_or(&empty);
+            let first = samples.front().map_or(end, |sample| sample.time);
+            if first <= start {
+                return None;
+            }
+            let cache = trend.history.entry(pen.tag.clone()).or_default();
+            let mut merged: VecDeque<Sample> = VecDeque::new();
+            for point in cache
+                .get(historian, &pen.tag, start, first.min(end))
+                .iter()
+                .filter(|point| point.time < first)
+            {
+                merged.push_back(Sample {
+                    time: point.time,
+                    value: point.min,
+                });
+                if point.max != point.min {
+                    merged.push_back(Sample {
+                        time: point.time,
+                        value: point.max,
+                    });
+                }
+            }
+            merged.extend(visible(samples, start, end));
+            Some(merged)
+        })
+        .collect();
+    let series: Vec<Series<'_>> = trend
+        .pens
+        .iter()
+        .zip(history.iter())
+        .map(|(pen, history)| Series {
+            name: &pen.tag,
+            unit: tags
+                .iter()
+                .find(|tag| tag.name == pen.tag)
+                .map_or("", |tag| tag.unit.as_str()),
+            color: pen.color,
+            samples: history
+                .as_ref()
+                .or(buffer.series(&pen.tag))
+                .unwrap_or(&empty),
+            scale: (!pen.auto_scale).then_some((pen.min, pen.max)),
+            outages: buffer.outages(&pen.tag),
+        })
+        .collect();
+    let response = chart(ui, &series, start, end, None);
+    let seconds_per_point = trend.span / response.rect.width() as f64;
+
+    // Pan and zoom. Panning or zooming into the past freezes the chart.
+    if response.dragged() {
+        end -= response.drag_delta().x as f64 * seconds_per_point;
+        trend.end = Some(end.min(now));
+    }
+    if response.hovered() {
+        let scroll = ui.input(|i| i.scroll_delta.y + (i.zoom_delta() - 1.0) * 200.);
+        if scroll != 0.0 {
+            let factor = (-scroll as f64 / 200.).exp();
+            let new_span = (trend.span * factor).clamp(10., MAX_SPAN);
+            // The time under the pointer stays put.
+            let anchor = response.hover_pos().map_or(end, |pos| {
+                end - (response.rect.right() - pos.x) as f64 * seconds_per_point
+            });
+            end = anchor + (end - anchor) * new_span / trend.span;
+            trend.span = new_span;
+            if trend.end.is_some() || end < now {
+                trend.end = Some(end.min(now));
+            }
+        }
+    }
+    if response.double_clicked() {
+        trend.end = None;
+    }
+}
+//####################################################
+
+//#################################################### Time chart.
+
+/// A line of a time chart.
+pub struct Series<'a> {
+    pub name: &'a str,
+    pub unit: &'a str,
+    pub color: Color32,
+    pub samples: &'a VecDeque<Sample>,
+    /// The vertical range, `None` to fit the visible samples.
+    pub scale: Option<(f64, f64)>,
+    /// The periods without a good value, shaded in the colour of the series.
+    pub outages: Option<&'a VecDeque<Outage>>,
+}
+
+impl Series<'_> {
+    /// The quality of the series at `time`, when it wasn't good.
+    fn outage_at(&self, time: f64) -> Option<Quality> {
+        self.outages?
+            .iter()
+            .find(|outage| outage.contains(time))
+            .map(|outage| outage.quality)
+    }
+}
+
+impl Series<'_> {
+    /// The vertical range the series is drawn over.
+    fn range(&self, start: f64, end: f64) -> (f64, f64) {
+        let (low, high) = match self.scale {
+            Some((min, max)) => (min.min(max), max.max(min)),
+            None => Some(
+                visible(self.samples, start, end)
+                    .fold((f64::MAX, f64::MIN), |(low, high), sample| {
+                        (low.min(sample.value), high.max(sample.value))
+                    }),
+            )
+            .filter(|(low, high)| low <= high)
+            .unwrap_or((0.0, 1.0)),
+        };
+        // We keep flat lines off the chart borders.
+        let margin = ((high - low) * 0.05).max(f64::EPSILON.max(high.abs() * 1e-6));
+        (low - margin, high + margin)
+    }
+}
+
+/// Draws `series` between the `start` and `end` timestamps, with a time grid,
+/// the scale of every series and a readout under the pointer. `marker` draws a
+/// vertical line at a time, like the position of a replay.
+pub fn chart(
+    ui: &mut egui::Ui,
+    series: &[Series<'_>],
+    start: f64,
+    end: f64,
+    marker: Option<f64>,
+) -> egui::Response {
+    let size = Vec2::new(ui.available_width().max(400.), 300.);
+    let (response, painter) = ui.allocate_painter(size, Sense::click_and_drag());
+    let rect = response.rect;
+    let plot = Rect::from_min_max(rect.min + Vec2::new(0., 5.), rect.max - Vec2::new(0., 20.));
+    let span = (end - start).max(f64::EPSILON);
+    let seconds_per_point = span / plot.width() as f64;
+
+    painter.rect_filled(rect, 2., Color32::from_gray(20));
+    let to_x = |time: f64| plot.left() + ((time - start) / span) as f32 * plot.width();
+
+    // Time grid.
+    let grid_stroke = Stroke::new(1., Color32::from_gray(50));
+    let step = [
+        1., 2., 5., 10., 15., 30., 60., 120., 300., 600., 900., 1800., 3600., 7200., 10800.,
+        21600., 43200., 86400.,
+    ]
+    .into_iter()
+    .find(|step| span / step <= 8.)
+    .unwrap_or(86400.);
+    let offset = Local::now().offset().local_minus_utc() as f64;
+    let mut tick = ((start + offset) / step).ceil() * step - offset;
+    while tick <= end {
+        let x = to_x(tick);
+        painter.line_segment(
+            [Pos2::new(x, plot.top()), Pos2::new(x, plot.bottom())],
+            grid_stroke,
+        );
+        if let Some(time) = local_time(tick) {
+            let format = if step < 60. { "%H:%M:%S" } else { "%H:%M" };
+            painter.text(
+                Pos2::new(x, plot.bottom() + 2.),
+                Align2::CENTER_TOP,
+                time.format(format).to_string(),
+                FontId::proportional(11.),
+                Color32::GRAY,
+            );
+        }
+        tick += step;
+    }
+    for i in 1..4 {
+        let y = plot.top() + plot.height() * i as f32 / 4.;
+        painter.line_segment(
+            [Pos2::new(plot.left(), y), Pos2::new(plot.right(), y)],
+            grid_stroke,
+        );
+    }
+
+    // Outages, behind the lines.
+    for series in series.iter() {
+        for outage in series.outages.into_iter().flatten() {
+            let outage_end = outage.end.unwrap_or(end);
+            if outage_end < start || outage.start > end {
+                continue;
+            }
+            let x_range = to_x(outage.start.max(start))..=to_x(outage_end.min(end));
+            painter.rect_filled(
+                Rect::from_x_y_ranges(x_range, plot.y_range()),
+                0.,
+                series.color.gamma_multiply(0.2),
+            );
+        }
+    }
+
+    // Lines, reduced to the low and high value of every pixel column.
+    for (i, series) in series.iter().enumerate() {
+        let (low, high) = series.range(start, end);
+        let to_y =
+            |value: f64| plot.bottom() - ((value - low) / (high - low)) as f32 * plot.height();
+        let mut points: Vec<Pos2> = Vec::new();
+        let mut column: Option<(i32, f64, f64)> = None;
+        for sample in visible(series.samples, start, end) {
+            let x = to_x(sample.time);
+            match &mut column {
+                Some((col, min, max)) if *col == x as i32 => {
+                    *min = min.min(sample.value);
+                    *max = max.max(sample.value);
+                }
+                _ => {
+                    if let Some((col, min, max)) = column {
+                        points.push(Pos2::new(col as f32, to_y(min)));
+                        if max != min {
+                            points.push(Pos2::new(col as f32, to_y(max)));
+                        }
+                    }
+                    column = Some((x as i32, sample.value, sample.value));
+                }
+            }
+        }
+        if let Some((col, min, max)) = column {
+            points.push(Pos2::new(col as f32, to_y(min)));
+            points.push(Pos2::new(col as f32, to_y(max)));
+        }
+        painter
+            .with_clip_rect(plot)
+            .add(Shape::line(points, Stroke::new(1.5, series.color)));
+
+        // The scale in the top left corner.
+        painter.text(
+            plot.left_top() + Vec2::new(4., 2. + 14. * i as f32),
+            Align2::LEFT_TOP,
+            format!("{}  {:.2} .. {:.2} {}", series.name, low, high, series.unit),
+            FontId::proportional(11.),
+            series.color,
+        );
+    }
+
+    if let Some(time) = marker.filter(|time| (start..=end).contains(time)) {
+        let x = to_x(time);
+        painter.line_segment(
+            [Pos2::new(x, plot.top()), Pos2::new(x, plot.bottom())],
+            Stroke::new(2., Color32::YELLOW),
+        );
+    }
+
+    // Cursor readout.
+    if let Some(pos) = response.hover_pos().filter(|pos| plot.contains(*pos)) {
+        let time = start + (pos.x - plot.left()) as f64 * seconds_per_point;
+        painter.line_segment(
+            [
+                Pos2::new(pos.x, plot.top()),
+                Pos2::new(pos.x, plot.bottom()),
+            ],
+            Stroke::new(1., Color32::WHITE),
+        );
+        let mut lines = vec![local_time(time)
+            .map(|time| time.format("%d/%m/%Y %H:%M:%S").to_string())
+            .unwrap_or_default()];
+        for series in series.iter() {
+            let mut value = value_at(series.samples, time)
+                .map_or("-".to_string(), |value| format!("{:.2}", value));
+            if let Some(quality) = series.outage_at(time) {
+                value.push_str(&format!(" ({})", quality));
+            }
+            lines.push(format!("{}: {}", series.name, value));
+        }
+        let galley =
+            painter.layout_no_wrap(lines.join("\n"), FontId::monospace(11.), Color32::WHITE);
+        // The readout sits on the side of the cursor with the most room.
+        let left = if pos.x > plot.center().x {
+            pos.x - 8. - galley.size().x
+        } else {
+            pos.x + 8.
+        };
+        let text_rect = Rect::from_min_size(Pos2::new(left, plot.top() + 6.), galley.size());
+        painter.rect_filled(text_rect.expand(3.), 2., Color32::from_black_alpha(200));
+        painter.galley(text_rect.min, galley);
+    }
+
+    response
+}
+//####################################################
@@ -0,0 +1,287 @@
+use egui::{Button, Color32, Grid, RichText, TextEdit};
+use std::collections::HashMap;
+use tokio_modbus::prelude::Writer;
+
+use crate::blocks::RegisterType;
+use crate::codec::Value;
+use crate::signals::DigitalSignal;
+use crate::tags::Tag;
+
+//#################################################### Write commands.
+
+/// A write confirmed by the operator. The polling task of the device sends
+/// it between two reads.
+#[derive(PartialEq, Debug, Clone)]
+pub struct WriteRequest {
+    /// The tag or signal written.
+    pub source: String,
+    pub device: String,
+    /// The value in the operator's terms, like "52.5 Barg".
+    pub value: String,
+    pub command: WriteCommand,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum WriteCommand {
+    /// The raw value of the tag named by the request, encoded by the driver
+    /// of the device.
+    Setpoint(Value),
+    Coil {
+        address: u16,
+        value: bool,
+    },
+}
+
+impl WriteCommand {
+    /// Sends the command to a Modbus device. A setpoint is encoded with the
+    /// data type of the tag `source` and written with function 06 for a
+    /// single register and 16 for longer values, to a holding register only.
+    pub async fn send(
+        &self,
+        source: &str,
+        tags: &[Tag],
+        ctx: &mut impl Writer,
+    ) -> Result<(), String> {
+        let result = match self {
+            WriteCommand::Setpoint(value) => {
+                let tag = tags
+                    .iter()
+                    .find(|tag| tag.name == source)
+                    .ok_or(format!("{} is not in the tag database.", source))?;
+                if tag.register_type != RegisterType::Holding {
+                    return Err(format!("{} is not a holding register.", source));
+                }
+                match tag.encode(value)?.as_slice() {
+                    [register] => ctx.write_single_register(tag.address, *register).await,
+                    registers => ctx.write_multiple_registers(tag.address, registers).await,
+                }
+            }
+            WriteCommand::Coil { address, value } => ctx.write_single_coil(*address, *value).await,
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// The outcome of a write, reported back to the setpoint dialog.
+#[derive(PartialEq, Debug, Clone)]
+pub struct WriteResult {
+    pub source: String,
+    pub result: Result<String, String>,
+}
+//####################################################
+
+//#################################################### Setpoint dialog.
+
+enum Target {
+    Tag(String),
+    Signal(String),
+}
+
+/// The setpoint entry of a writable tag, or the switch of a command signal.
+pub struct SetpointDialog {
+    target: Target,
+    entry: String,
+    /// The write waiting for the operator's confirmation.
+    pending: Option<WriteRequest>,
+    /// Whether a write was sent from the dialog.
+    sent: bool,
+    /// The result of the last write, `None` while the device hasn't answered.
+    status: Option<Result<String, String>>,
+}
+
+impl SetpointDialog {
+    pub fn tag(tag: &Tag) -> Self {
+        Self::new(Target::Tag(tag.name.clone()), tag.display_value())
+    }
+
+    pub fn signal(signal: &DigitalSignal) -> Self {
+        Self::new(Target::Signal(signal.label.clone()), "".to_string())
+    }
+
+    fn new(target: Target, entry: String) -> Self {
+        Self {
+            target,
+            entry,
+            pending: None,
+            sent: false,
+            status: None,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match &self.target {
+            Target::Tag(name) | Target::Signal(name) => name,
+        }
+    }
+
+    /// Takes the result of a write if it is about the dialog's target.
+    pub fn report(&mut self, result: &WriteResult) {
+        if self.sent && result.source == self.title() {
+            self.status = Some(result.result.clone());
+        }
+    }
+}
+
+/// Shows the setpoint dialog. Returns the write once the operator confirmed it.
+pub fn setpoint_ui(
+    ui: &mut egui::Ui,
+    dialog: &mut SetpointDialog,
+    tags: &[Tag],
+    signals: &[DigitalSignal],
+    signal_states: &HashMap<String, bool>,
+    connected_devices: &[&str],
+) -> Option<WriteRequest> {
+    let mut request = None;
+    let connected = match &dialog.target {
+        Target::Tag(name) => {
+            let Some(tag) = tags.iter().find(|tag| tag.name == *name) else {
+                ui.colored_label(Color32::DARK_RED, format!("{} no longer exists.", name));
+                return None;
+            };
+            let connected = connected_devices.contains(&tag.device.as_str());
+            Grid::new("setpoint_tag").num_columns(2).show(ui, |ui| {
+                ui.label("Tag");
+                ui.label(format!("{}  {}", tag.name, tag.description));
+                ui.end_row();
+                ui.label("Device");
+                ui.label(format!("{}, register {}", tag.device, tag.address));
+                ui.end_row();
+                ui.label("Current value");
+                ui.label(format!("{} {}", tag.display_value(), tag.unit));
+                ui.end_row();
+                if !tag.data_type.is_text() {
+                    ui.label("Range");
+                    ui.label(format!(
+                        "{} .. {} {}",
+                        tag.write.min, tag.write.max, tag.unit
+                    ));
+                    ui.end_row();
+                }
+                ui.label("New value");
+                ui.add_enabled(
+                    dialog.pending.is_none(),
+                    TextEdit::singleline(&mut dialog.entry).desired_width(120.),
+                );
+                ui.end_row();
+            });
+
+            let setpoint = tag.setpoint(&dialog.entry);
+            if let Err(error) = &setpoint {
+                ui.colored_label(Color32::DARK_RED, error);
+            }
+            if ui
+                .add_enabled(
+                    setpoint.is_ok() && connected && dialog.pending.is_none(),
+                    Button::new(format!(
+                        "{} Send",
+                        egui_phosphor::regular::PAPER_PLANE_RIGHT
+                    )),
+                )
+                .clicked()
+            {
+                if let Ok(value) = setpoint {
+                    dialog.pending = Some(WriteRequest {
+                        source: tag.name.clone(),
+                        device: tag.device.clone(),
+                        value: format!("{} {}", dialog.entry.trim(), tag.unit)
+                            .trim_end()
+                            .to_string(),
+                        command: WriteCommand::Setpoint(value),
+                    });
+                }
+            }
+            connected
+        }
+        Target::Signal(label) => {
+            let Some(signal) = signals.iter().find(|signal| signal.label == *label) else {
+                ui.colored_label(Color32::DARK_RED, format!("{} no longer exists.", label));
+                return None;
+            };
+            let Some(address) = signal.command_coil() else {
+                ui.colored_label(
+                    Color32::DARK_RED,
+                    format!("{} is not a command.", signal.label),
+                );
+                return None;
+            };
+            let connected = connected_devices.contains(&signal.device.as_str());
+            Grid::new("setpoint_signal").num_columns(2).show(ui, |ui| {
+                ui.label("Signal");
+                ui.label(&signal.label);
+                ui.end_row();
+                ui.label("Device");
+                ui.label(format!("{}, coil {}", signal.device, address));
+                ui.end_row();
+                ui.label("Current state");
+                ui.label(match signal_states.get(&signal.label) {
+                    Some(true) => "ON",
+                    Some(false) => "OFF",
+                    None => "-",
+                });
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                for (text, value) in [("Switch on", true), ("Switch off", false)] {
+                    if ui
+                        .add_enabled(connected && dialog.pending.is_none(), Button::new(text))
+                        .clicked()
+                    {
+                        dialog.pending = Some(WriteRequest {
+                            source: signal.label.clone(),
+                            device: signal.device.clone(),
+                            value: if value { "ON" } else { "OFF" }.to_string(),
+                            command: WriteCommand::Coil { address, value },
+                        });
+                    }
+                }
+            });
+            connected
+        }
+    };
+
+    if !connected {
+        ui.colored_label(Color32::GRAY, "Connect to the device to write.");
+    }
+
+    // Nothing is sent before the operator confirms.
+    if let Some(pending) = &dialog.pending {
+        ui.separator();
+        ui.label(
+            RichText::new(format!(
+                "{} Write {} to {}?",
+                egui_phosphor::regular::WARNING,
+                pending.value,
+                pending.source
+            ))
+            .strong(),
+        );
+        ui.horizontal(|ui| {
+            if ui.button("Confirm").clicked() {
+                request = dialog.pending.take();
+                dialog.sent = true;
+                dialog.status = None;
+            }
+            if ui.button("Cancel").clicked() {
+                dialog.pending = None;
+            }
+        });
+    }
+
+    if dialog.sent {
+        ui.separator();
+        match &dialog.status {
+            None => {
+                ui.colored_label(Color32::GRAY, "Waiting for the device...");
+            }
+            Some(Ok(message)) => {
+                ui.colored_label(Color32::DARK_GREEN, message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(Color32::DARK_RED, error);
+            }
+        }
+    }
+
+    request
+}
+//####################################################